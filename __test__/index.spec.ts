@@ -19,7 +19,8 @@
 import test from 'ava'
 
 import process from 'node:process';
-import { SmbDirectoryHandle, SmbFileHandle } from '../indax';
+import { createHash, pbkdf2 } from 'node:crypto';
+import { SmbDirectoryHandle, SmbFileHandle, SmbWritableFileStream, SmbError, setMaxConcurrentOperations, setDebugLogger, toUncPath, fromUncPath } from '../indax';
 
 const smbURL = process.env.SMB_URL || 'smb://127.0.0.1/Users/Shared/smb/';
 //const smbPath = process.env.SMB_PATH;
@@ -1487,3 +1488,1277 @@ if (!process.env.TEST_USING_MOCKS) {
     await watcher.wait();
   })
 }
+
+test.serial('should return parent directory for nested file', async (t) => {
+  const rootHandle = await getRootHandle();
+  const dirHandle = await rootHandle.getDirectoryHandle('first');
+  const fileHandle = await dirHandle.getFileHandle('comment') as any as SmbFileHandle;
+  const parentHandle = await fileHandle.getParent();
+  t.truthy(parentHandle);
+  t.is(parentHandle?.kind, 'directory');
+  t.is(parentHandle?.name, 'first');
+  t.true(await parentHandle?.isSameEntry(dirHandle));
+  const grandparentHandle = await (parentHandle as any as SmbDirectoryHandle).getParent();
+  t.truthy(grandparentHandle);
+  t.true(await grandparentHandle?.isSameEntry(rootHandle));
+})
+
+test.serial('should return parent directory for top-level entry', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('annar') as any as SmbFileHandle;
+  const parentHandle = await fileHandle.getParent();
+  t.truthy(parentHandle);
+  t.true(await parentHandle?.isSameEntry(rootHandle));
+  const dirHandle = await rootHandle.getDirectoryHandle('quatre') as any as SmbDirectoryHandle;
+  const dirParentHandle = await dirHandle.getParent();
+  t.truthy(dirParentHandle);
+  t.true(await dirParentHandle?.isSameEntry(rootHandle));
+})
+
+test.serial('should return null as parent of root directory', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  t.is(await rootHandle.getParent(), null);
+})
+
+test.serial('should remove multiple entries in one batch', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const dirHandle = await rootHandle.getDirectoryHandle('batch-remove-dir', {create: true}) as any as SmbDirectoryHandle;
+  await dirHandle.getFileHandle('batch-file1', {create: true});
+  await dirHandle.getFileHandle('batch-file2', {create: true});
+  const subdirHandle = await dirHandle.getDirectoryHandle('batch-subdir', {create: true}) as any as SmbDirectoryHandle;
+  await subdirHandle.getFileHandle('batch-subfile', {create: true});
+  const res = await dirHandle.removeEntries(['batch-file1', 'batch-file2', 'batch-subdir', 'batch-missing'], {recursive: true});
+  t.deepEqual(res.removed, ['batch-file1', 'batch-file2', 'batch-subdir']);
+  t.is(res.failed.length, 1);
+  t.is(res.failed[0].name, 'batch-missing');
+  t.true(res.failed[0].error.indexOf('not found') != -1);
+  const keys: string[] = [];
+  for await (const key of dirHandle.keys()) {
+    keys.push(key);
+  }
+  t.deepEqual(keys, []);
+  await rootHandle.removeEntry('batch-remove-dir');
+})
+
+test.serial('should report non-empty directory when batch removing non-recursively', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const dirHandle = await rootHandle.getDirectoryHandle('batch-remove-dir', {create: true}) as any as SmbDirectoryHandle;
+  await dirHandle.getFileHandle('batch-file', {create: true});
+  const res = await rootHandle.removeEntries(['batch-remove-dir']);
+  t.deepEqual(res.removed, []);
+  t.is(res.failed.length, 1);
+  t.is(res.failed[0].name, 'batch-remove-dir');
+  await rootHandle.removeEntry('batch-remove-dir', {recursive: true});
+})
+
+test.serial('should create file when touching missing entry', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.touch('touched-file');
+  t.is(fileHandle.kind, 'file');
+  t.is(fileHandle.name, 'touched-file');
+  const file = await fileHandle.getFile();
+  t.is(file.size, 0);
+  await rootHandle.removeEntry('touched-file');
+})
+
+test.serial('should update modified time when touching existing file', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('touched-file', {create: true}) as any as SmbFileHandle;
+  await fileHandle.setTimes({modifiedTime: 1000000000 * 1000000000});
+  t.is(Number((await fileHandle.stat()).modifiedTime), 1000000000 * 1000000000);
+  const touchedHandle = await rootHandle.touch('touched-file') as any as SmbFileHandle;
+  t.true(await touchedHandle.isSameEntry(fileHandle));
+  const stats = await touchedHandle.stat();
+  t.true(Math.abs(Number(stats.modifiedTime) - (Date.now() * 1000000)) < 60 * 1000000000, `modified time not updated: ${JSON.stringify(stats)}`);
+  await rootHandle.removeEntry('touched-file');
+})
+
+test.serial('should report handles as connected', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  t.true(rootHandle.isConnected());
+  const fileHandle = await rootHandle.getFileHandle('annar') as any as SmbFileHandle;
+  t.true(fileHandle.isConnected());
+})
+
+test.serial('should iterate through keys with minimal prefetch', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const expectedKeys = new Set<string>(['3', 'annar', 'quatre', 'first']);
+  const keys = new Set<string>();
+  for await (const key of rootHandle.keys({prefetch: 1})) {
+    if (keys.size >= expectedKeys.size) {
+      t.fail('iterated past expected number of keys');
+      break;
+    }
+    t.true(expectedKeys.has(key));
+    keys.add(key);
+  }
+  t.is(keys.size, expectedKeys.size);
+})
+
+test.serial('should get file handle with specific access', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const attrHandle = await rootHandle.getFileHandle('annar', {create: false, access: 'readAttributes'});
+  t.is(attrHandle.name, 'annar');
+  const readHandle = await rootHandle.getFileHandle('annar', {create: false, access: 'read'});
+  t.is((await readHandle.getFile()).size, 123);
+  await t.throwsAsync(rootHandle.getFileHandle('annar', {create: false, access: 'bogus' as any}), {message: 'Unknown access "bogus"'});
+})
+
+test.serial('should write file with write-only access', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('write-only-file', {create: true, access: 'write'}) as any as SmbFileHandle;
+  const writable = await fileHandle.createWritable({keepExistingData: false, access: 'write'});
+  await writable.write('write only');
+  await writable.write(' appended');
+  await writable.close();
+  t.is(await (await fileHandle.getFile()).text(), 'write only appended');
+  await t.throwsAsync(fileHandle.createWritable({keepExistingData: false, access: 'read' as any}), {message: 'Access "read" not supported for writable streams'});
+  await rootHandle.removeEntry('write-only-file');
+})
+
+test.serial('should get file acl', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('annar') as any as SmbFileHandle;
+  const acl = await fileHandle.getAcl();
+  t.true(Array.isArray(acl.aces));
+  if (acl.owner !== undefined) {
+    t.true(acl.owner.startsWith('S-'));
+  }
+  for (const ace of acl.aces) {
+    t.true(ace.sid.startsWith('S-'));
+    t.true(['allowed', 'denied', 'audit', 'alarm'].includes(ace.type));
+  }
+})
+
+test.serial('should set file acl', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('acl-file', {create: true}) as any as SmbFileHandle;
+  const acl = await fileHandle.getAcl();
+  const aces = acl.aces.filter((ace) => ace.type === 'allowed' || ace.type === 'denied');
+  aces.push({sid: 'S-1-1-0', type: 'allowed', flags: 0, mask: 0x120089});
+  await fileHandle.setAcl({aces});
+  const updated = await fileHandle.getAcl();
+  t.true(updated.aces.some((ace) => ace.sid === 'S-1-1-0' && ace.type === 'allowed'));
+  await t.throwsAsync(fileHandle.setAcl({aces: [{sid: 'not-a-sid', type: 'allowed', flags: 0, mask: 1}]}), {message: 'invalid SID "not-a-sid"'});
+  await t.throwsAsync(fileHandle.setAcl({aces: [{sid: 'S-1-1-0', type: 'audit', flags: 0, mask: 1}]}), {message: 'ACE type "audit" not supported in a DACL'});
+  await rootHandle.removeEntry('acl-file');
+})
+
+test.serial('should read head of file', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('annar') as any as SmbFileHandle;
+  const head = await fileHandle.head(8);
+  t.is(Buffer.from(head).toString(), 'In order');
+  const all = await fileHandle.head(1000);
+  t.is(all.byteLength, 123);
+  const emptyHandle = await rootHandle.getFileHandle('3') as any as SmbFileHandle;
+  t.is((await emptyHandle.head(8)).byteLength, 0);
+})
+
+test.serial('should iterate through file lines', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('lines-file', {create: true}) as any as SmbFileHandle;
+  const writable = await fileHandle.createWritable();
+  await writable.write('first\nsecond\n\nfourth');
+  await writable.close();
+  const lines: string[] = [];
+  for await (const line of fileHandle.lines()) {
+    lines.push(line);
+  }
+  t.deepEqual(lines, ['first', 'second', '', 'fourth']);
+  const parts: string[] = [];
+  for await (const part of fileHandle.lines({delimiter: 'ec'})) {
+    parts.push(part);
+  }
+  t.deepEqual(parts, ['first\ns', 'ond\n\nfourth']);
+  await t.throwsAsync(async () => {
+    for await (const _line of fileHandle.lines({encoding: 'utf-16' as any})) {
+      t.fail('iterated lines with unsupported encoding');
+    }
+  }, {message: 'Unsupported encoding "utf-16"'});
+  await rootHandle.removeEntry('lines-file');
+  await t.throwsAsync(async () => {
+    for await (const _line of fileHandle.lines()) {
+      t.fail('iterated lines of removed file');
+    }
+  }, {instanceOf: SmbError, code: 'NOT_FOUND'});
+})
+
+test.serial('should create entries with connection default modes', async (t) => {
+  const rootHandle = new SmbDirectoryHandle(smbURL, {defaultFileMode: 0o640, defaultDirMode: 0o750});
+  const dirHandle = await rootHandle.getDirectoryHandle('default-mode-dir', {create: true});
+  const fileHandle = await dirHandle.getFileHandle('default-mode-file', {create: true});
+  t.is(fileHandle.kind, 'file');
+  await rootHandle.removeEntry('default-mode-dir', {recursive: true});
+  t.throws(() => new SmbDirectoryHandle(smbURL, {defaultFileMode: 0o17777}), {message: 'Invalid mode 0o17777'});
+})
+
+test.serial('should accept symlink policy on recursive operations', async (t) => {
+  const rootHandle = new SmbDirectoryHandle(smbURL);
+  const dirHandle = await rootHandle.getDirectoryHandle('symlinks-dir', {create: true});
+  const fileHandle = await dirHandle.getFileHandle('symlinks-file', {create: true});
+  t.deepEqual(await rootHandle.resolve(fileHandle, {symlinks: 'follow'}), ['symlinks-dir', 'symlinks-file']);
+  t.deepEqual(await rootHandle.resolve(fileHandle, {symlinks: 'error'}), ['symlinks-dir', 'symlinks-file']);
+  await t.throwsAsync(rootHandle.resolve(fileHandle, {symlinks: 'sometimes' as any}), {message: 'Unknown symlink policy "sometimes"'});
+  await t.throwsAsync(rootHandle.removeEntry('symlinks-dir', {recursive: true, symlinks: 'sometimes' as any}), {message: 'Unknown symlink policy "sometimes"'});
+  await rootHandle.removeEntry('symlinks-dir', {recursive: true, symlinks: 'follow'});
+  await t.throwsAsync(rootHandle.getDirectoryHandle('symlinks-dir'), {message: 'Directory "symlinks-dir" not found'});
+})
+
+test.serial('should report bytes written and final size on finish', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('finish-file', {create: true});
+  const writable = await fileHandle.createWritable({keepExistingData: false}) as any as SmbWritableFileStream;
+  await writable.write('hello ');
+  await writable.write({type: 'write', position: 10, data: 'world'});
+  const summary = await writable.finish();
+  t.is(summary.bytesWritten, 11);
+  t.is(summary.size, 15n);
+  await rootHandle.removeEntry('finish-file');
+})
+
+test.serial('should write file from readable stream', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('stream-file', {create: true}) as any as SmbFileHandle;
+  const writable = await fileHandle.createWritable();
+  await writable.write('previous contents that are longer');
+  await writable.close();
+  const encoder = new TextEncoder();
+  const chunks = ['streamed ', 'into ', 'file'];
+  const stream = new ReadableStream({
+    pull(controller) {
+      const chunk = chunks.shift();
+      if (chunk !== undefined) {
+        controller.enqueue(encoder.encode(chunk));
+      } else {
+        controller.close();
+      }
+    }
+  });
+  await fileHandle.writeFromStream(stream, {highWaterMark: 2});
+  t.is(await (await fileHandle.getFile()).text(), 'streamed into file');
+  await rootHandle.removeEntry('stream-file');
+})
+
+test.serial('should upload batch of files or roll back', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const dirHandle = await rootHandle.getDirectoryHandle('batch-dir', {create: true}) as any as SmbDirectoryHandle;
+  const result = await dirHandle.uploadBatch([{name: 'one', data: 'first'}, {name: 'two', data: new TextEncoder().encode('second')}]);
+  t.true(result.committed);
+  t.deepEqual(result.files.map((f) => f.status), ['committed', 'committed']);
+  const two = await dirHandle.getFileHandle('two');
+  t.is(await (await two.getFile()).text(), 'second');
+  const rolledBack = await dirHandle.uploadBatch([{name: 'three', data: 'third'}, {name: 'one', data: 'replaced'}], {overwrite: false});
+  t.false(rolledBack.committed);
+  t.deepEqual(rolledBack.files.map((f) => f.status), ['rolledBack', 'failed']);
+  t.is(rolledBack.files[1].error, 'Entry "one" already exists');
+  const names: string[] = [];
+  for await (const name of dirHandle.keys()) {
+    names.push(name);
+  }
+  t.deepEqual(names.sort(), ['one', 'two']);
+  const one = await dirHandle.getFileHandle('one');
+  t.is(await (await one.getFile()).text(), 'first');
+  await t.throwsAsync(dirHandle.uploadBatch([{name: 'a/b', data: ''}]), {message: 'Invalid file name "a/b"'});
+  await rootHandle.removeEntry('batch-dir', {recursive: true});
+})
+
+test.serial('should convert between handles and UNC paths', async (t) => {
+  const rootHandle = await getRootHandle();
+  const dirHandle = await rootHandle.getDirectoryHandle('first');
+  const fileHandle = await dirHandle.getFileHandle('comment');
+  const unc = toUncPath(fileHandle);
+  t.true(unc.startsWith('\\\\'));
+  t.true(unc.endsWith('\\first\\comment'));
+  t.true(fromUncPath(unc).endsWith('/first/comment'));
+  t.is(fromUncPath('\\\\server\\share\\dir\\file'), 'smb://server/share/dir/file');
+  t.throws(() => fromUncPath('C:\\dir'), {message: 'Invalid UNC path "C:\\\\dir"'});
+})
+
+test.serial('should report whether getOrCreateFile created the file', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const first = await rootHandle.getOrCreateFile('lock-file');
+  t.true(first.created);
+  t.is(first.handle.name, 'lock-file');
+  const second = await rootHandle.getOrCreateFile('lock-file');
+  t.false(second.created);
+  t.true(await second.handle.isSameEntry(first.handle));
+  await t.throwsAsync(rootHandle.getOrCreateFile('first'), {message: 'The path supplied exists, but was not an entry of requested type.'});
+  await rootHandle.removeEntry('lock-file');
+})
+
+test.serial('should count connection traffic in metrics', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  rootHandle.metrics({reset: true});
+  const fileHandle = await rootHandle.getFileHandle('annar');
+  t.is((await (await fileHandle.getFile()).text()).length, 123);
+  const writable = await fileHandle.createWritable({keepExistingData: true});
+  await writable.write({type: 'write', position: 123, data: 'tail'});
+  await writable.close();
+  const metrics = rootHandle.metrics({reset: true});
+  t.true(metrics.bytesRead >= 123);
+  t.is(metrics.bytesWritten, 4);
+  t.true(metrics.openOps > 0);
+  t.true(metrics.statOps > 0);
+  t.is(rootHandle.metrics().bytesRead, 0);
+  const restore = await fileHandle.createWritable({keepExistingData: true});
+  await restore.truncate(123);
+  await restore.close();
+})
+
+test.serial('should route debug events for connections made with debug set', async (t) => {
+  const events: any[] = [];
+  setDebugLogger((event) => events.push(event));
+  try {
+    const rootHandle = new SmbDirectoryHandle(smbURL, {debug: true});
+    await (await rootHandle.getFileHandle('annar')).getFile();
+    const removed = await rootHandle.getFileHandle('debug-file', {create: true}) as any as SmbFileHandle;
+    await rootHandle.removeEntry('debug-file');
+    await t.throwsAsync(removed.stat());
+    await new Promise((resolve) => setTimeout(resolve, 100));
+  } finally {
+    setDebugLogger(null);
+  }
+  const traced = events.filter((event) => event.op);
+  t.true(traced.some((event) => event.path === '/annar' && event.result === 'ok'));
+  t.true(traced.some((event) => event.op === 'stat' && event.path === '/debug-file' && event.result !== 'ok'));
+  t.true(traced.every((event) => event.level === 'debug' && event.durationUs >= 0));
+})
+
+test.serial('should complete concurrent operations with a concurrency cap', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('annar');
+  setMaxConcurrentOperations(1);
+  try {
+    const texts = await Promise.all([...Array(8)].map(async () => (await fileHandle.getFile()).text()));
+    t.true(texts.every((text) => text.length === 123));
+  } finally {
+    setMaxConcurrentOperations(0);
+  }
+})
+
+test.serial('should not hold libuv pool threads while queued behind the concurrency cap', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('annar');
+  setMaxConcurrentOperations(1);
+  try {
+    let settled = false;
+    const hashes = Promise.all([...Array(16)].map(() => fileHandle.hash('sha256'))).finally(() => { settled = true; });
+    const derived = new Promise((resolve, reject) => pbkdf2('secret', 'salt', 1, 16, 'sha256', (err) => err ? reject(err) : resolve(settled)));
+    t.false(await derived);
+    const listed = await Promise.all([rootHandle.list(), rootHandle.stat(), fileHandle.getFile()]);
+    t.true(listed[0].length > 0);
+    t.is(new Set(await hashes).size, 1);
+  } finally {
+    setMaxConcurrentOperations(0);
+  }
+})
+
+test.serial('should seek relative to the current position and the end', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('seek-file', {create: true});
+  const writable = await fileHandle.createWritable() as any;
+  await writable.write('0123456789');
+  await writable.write({type: 'seek', position: -4, whence: 'current'});
+  await writable.write('ab');
+  await writable.write({type: 'seek', position: -1, whence: 'end'});
+  await writable.write('z');
+  await writable.write({type: 'seek', position: 1});
+  await writable.write('y');
+  await t.throwsAsync(writable.write({type: 'seek', position: -11, whence: 'end'}), {message: 'Seeking to negative position -1'});
+  await t.throwsAsync(writable.write({type: 'seek', position: 0, whence: 'middle'}), {message: 'Unknown whence "middle"'});
+  await writable.close();
+  t.is(await (await fileHandle.getFile()).text(), '0y2345ab8z');
+  await rootHandle.removeEntry('seek-file');
+})
+
+test.serial('should preallocate without changing the file size', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('prealloc-file', {create: true}) as any as SmbFileHandle;
+  await fileHandle.preallocate(1024n * 1024n);
+  t.is((await fileHandle.getFile()).size, 0);
+  await t.throwsAsync(fileHandle.preallocate(-1n), {message: 'Invalid preallocation size -1'});
+  const full = await t.throwsAsync(fileHandle.preallocate(1n << 40n), {instanceOf: SmbError, message: 'Not enough space to preallocate 1099511627776 bytes'}) as SmbError;
+  t.is(full.code, 'DISK_FULL');
+  t.is(full.path, '/prealloc-file');
+  await rootHandle.removeEntry('prealloc-file');
+})
+
+test.serial('should refuse to watch an already watched handle unless multiple is set', async (t) => {
+  const rootHandle = await getRootHandle();
+  const dirHandle = await rootHandle.getDirectoryHandle('first') as any as SmbDirectoryHandle;
+  const first = dirHandle.watch(() => {});
+  t.throws(() => dirHandle.watch(() => {}), {message: 'Directory "first" is already being watched'});
+  const second = dirHandle.watch(() => {}, {multiple: true});
+  second.cancel();
+  await second.wait();
+  first.cancel();
+  await first.wait();
+  const third = dirHandle.watch(() => {});
+  third.cancel();
+  await third.wait();
+  t.pass();
+})
+
+test.serial('should cancel watchers on shutdown and refuse new operations', async (t) => {
+  const rootHandle = new SmbDirectoryHandle(smbURL);
+  const file = await (await rootHandle.getFileHandle('annar')).getFile();
+  const watcher = rootHandle.watch(() => {});
+  await rootHandle.shutdown();
+  await watcher.wait();
+  await t.throwsAsync(file.arrayBuffer(), {message: 'Connection has been shut down'});
+  t.false(rootHandle.isConnected());
+  await t.throwsAsync(rootHandle.getFileHandle('annar'), {message: 'Connection has been shut down'});
+  t.throws(() => rootHandle.watch(() => {}), {message: 'Connection has been shut down'});
+  await rootHandle.shutdown();
+})
+
+test.serial('should enumerate only directories', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const names: string[] = [];
+  for await (const handle of rootHandle.directories()) {
+    t.is(handle.kind, 'directory');
+    names.push(handle.name);
+  }
+  t.deepEqual(names.sort(), ['first', 'quatre']);
+})
+
+test.serial('should enumerate only files', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const names: string[] = [];
+  for await (const handle of rootHandle.files({prefetch: 1})) {
+    t.is(handle.kind, 'file');
+    names.push(handle.name);
+  }
+  t.deepEqual(names.sort(), ['3', 'annar']);
+})
+
+test.serial('should append atomically at end of file', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('appended', {create: true}) as SmbFileHandle;
+  const appendHandle = await fileHandle.openAppend();
+  await Promise.all([appendHandle.append('abc'), appendHandle.append(new TextEncoder().encode('abc'))]);
+  await appendHandle.append('def');
+  t.is(await (await fileHandle.getFile()).text(), 'abcabcdef');
+  await rootHandle.removeEntry('appended');
+})
+
+test.serial('should report bytes remaining on file stream source', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const file = await (await rootHandle.getFileHandle('annar')).getFile();
+  const stream = file.stream() as ReadableStream<Uint8Array> & { smbSource: { bytesRemaining: number, done: boolean } };
+  t.is(stream.smbSource.bytesRemaining, 123);
+  t.false(stream.smbSource.done);
+  const reader = stream.getReader();
+  while (!(await reader.read()).done);
+  t.is(stream.smbSource.bytesRemaining, 0);
+  t.true(stream.smbSource.done);
+})
+
+test.serial('should report entry type in stats', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const dirStats = await (await rootHandle.getDirectoryHandle('first')).stat();
+  t.true(dirStats.isDirectory);
+  t.false(dirStats.isFile);
+  t.false(dirStats.isSymlink);
+  const fileHandle = await rootHandle.getFileHandle('annar') as any as SmbFileHandle;
+  const fileStats = await fileHandle.lstat();
+  t.true(fileStats.isFile);
+  t.false(fileStats.isDirectory);
+  t.false(fileStats.isSymlink);
+})
+
+test.serial('should convert timestamps from the unix epoch', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('epoch-file', {create: true}) as any as SmbFileHandle;
+  await fileHandle.setTimes({modifiedTime: 1700000000123456000});
+  t.is((await fileHandle.stat()).modifiedTime, 1700000000123456000n);
+  t.is((await fileHandle.getFile()).lastModified, 1700000000123);
+  await rootHandle.removeEntry('epoch-file');
+})
+
+test.serial('should set creation time', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('born-file', {create: true}) as any as SmbFileHandle;
+  await fileHandle.setTimes({creationTime: 1600000000000000000});
+  t.is((await fileHandle.stat()).creationTime, 1600000000000000000n);
+  await rootHandle.removeEntry('born-file');
+})
+
+test.serial('should reject times out of range', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('annar') as any as SmbFileHandle;
+  const mtime = (await fileHandle.stat()).modifiedTime;
+  const negative = await t.throwsAsync(fileHandle.setTimes({modifiedTime: -1}));
+  t.is(negative?.message, 'Property modifiedTime is out of range');
+  const late = await t.throwsAsync(fileHandle.setTimes({creationTime: 5000000000 * 1000000000}));
+  t.is(late?.message, 'Property creationTime is out of range');
+  t.is((await fileHandle.stat()).modifiedTime, mtime);
+})
+
+test.serial('should accept empty writes as no-ops', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('empty-writes', {create: true}) as any as SmbFileHandle;
+  const writable = await fileHandle.createWritable() as any as SmbWritableFileStream;
+  await writable.write('abc');
+  await writable.write('');
+  await writable.write(new Uint8Array(0));
+  await writable.write(new ArrayBuffer(0));
+  await writable.write(new Blob([]));
+  await writable.write({type: 'write', data: ''});
+  await writable.write('def');
+  await writable.close();
+  t.is(await (await fileHandle.getFile()).text(), 'abcdef');
+  await rootHandle.removeEntry('empty-writes');
+})
+
+test.serial('should rename file in place', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('before-rename', {create: true}) as any as SmbFileHandle;
+  await fileHandle.rename('after-rename');
+  t.is(fileHandle.name, 'after-rename');
+  t.is((await fileHandle.getFile()).name, 'after-rename');
+  await t.throwsAsync(rootHandle.getFileHandle('before-rename'), {message: 'File "before-rename" not found'});
+  await t.throwsAsync(fileHandle.rename('sub/dir'), {message: 'Invalid name "sub/dir"'});
+  await t.throwsAsync(fileHandle.rename('annar'));
+  await rootHandle.removeEntry('after-rename');
+})
+
+test.serial('should rename directory in place', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const dirHandle = await rootHandle.getDirectoryHandle('dir-before-rename', {create: true}) as any as SmbDirectoryHandle;
+  await dirHandle.getFileHandle('inside', {create: true});
+  await dirHandle.rename('dir-after-rename');
+  t.is(dirHandle.name, 'dir-after-rename');
+  const keys: string[] = [];
+  for await (const key of dirHandle.keys()) {
+    keys.push(key);
+  }
+  t.deepEqual(keys, ['inside']);
+  await t.throwsAsync(rootHandle.getDirectoryHandle('dir-before-rename'));
+  await rootHandle.removeEntry('dir-after-rename', {recursive: true});
+})
+
+test.serial('should preserve non-BMP characters in file names', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const name = 'crab-\u{1F980}-漢字';
+  const fileHandle = await rootHandle.getFileHandle(name, {create: true});
+  t.is(fileHandle.name, name);
+  const names: string[] = [];
+  for await (const key of rootHandle.keys()) {
+    names.push(key);
+  }
+  t.true(names.includes(name), `${name} not listed in ${JSON.stringify(names)}`);
+  t.is((await rootHandle.getFileHandle(name)).name, name);
+  await rootHandle.removeEntry(name);
+})
+
+test.serial('should compare with serialized handles by url and path as well as name', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('annar');
+  t.true(await fileHandle.isSameEntry({kind: 'file', name: 'annar', url: smbURL, path: '/annar'} as any));
+  t.false(await fileHandle.isSameEntry({kind: 'file', name: 'annar', url: smbURL} as any));
+  t.false(await fileHandle.isSameEntry({kind: 'file', name: 'annar'} as any));
+  t.false(await fileHandle.isSameEntry({kind: 'file', name: 'annar', url: 'smb://elsewhere/share'} as any));
+  t.false(await fileHandle.isSameEntry({kind: 'file', name: 'annar', url: smbURL, path: '/first/annar'} as any));
+  t.false(await fileHandle.isSameEntry({kind: 'file', name: '3', url: smbURL} as any));
+})
+
+test.serial('should tell apart same-named entries in sibling directories', async (t) => {
+  const rootHandle = await getRootHandle();
+  const firstHandle = await rootHandle.getDirectoryHandle('first');
+  const quatreHandle = await rootHandle.getDirectoryHandle('quatre');
+  const firstTwin = await firstHandle.getFileHandle('twin', {create: true});
+  const quatreTwin = await quatreHandle.getFileHandle('twin', {create: true});
+  t.false(await firstTwin.isSameEntry(quatreTwin));
+  t.false(await quatreTwin.isSameEntry(firstTwin));
+  t.false(await firstTwin.isSameEntry({kind: 'file', name: 'twin', url: smbURL} as any));
+  t.false(await quatreTwin.isSameEntry({kind: 'file', name: 'twin', url: smbURL} as any));
+  t.true(await firstTwin.isSameEntry({kind: 'file', name: 'twin', url: smbURL, path: '/first/twin'} as any));
+  t.false(await quatreTwin.isSameEntry({kind: 'file', name: 'twin', url: smbURL, path: '/first/twin'} as any));
+  await firstHandle.removeEntry('twin');
+  await quatreHandle.removeEntry('twin');
+})
+
+test.serial('should report the requested signing mode in connection info', async (t) => {
+  t.is(new SmbDirectoryHandle(smbURL).connectionInfo().signing, 'enabled');
+  t.is(new SmbDirectoryHandle(smbURL, {signing: 'required'}).connectionInfo().signing, 'required');
+  t.is(new SmbDirectoryHandle(smbURL, {signing: 'disabled'}).connectionInfo().signing, 'disabled');
+  t.throws(() => new SmbDirectoryHandle(smbURL, {signing: 'sometimes' as any}), {message: 'Unknown signing mode "sometimes"'});
+})
+
+test.serial('should accept workstation name and client GUID connect options', async (t) => {
+  const rootHandle = new SmbDirectoryHandle(smbURL, {workstationName: 'SMBJS-TEST', clientGuid: '0f8fad5b-d9cb-469f-a165-70867728950e'});
+  t.true(rootHandle.isConnected());
+  t.throws(() => new SmbDirectoryHandle(smbURL, {clientGuid: '0f8fad5b-d9cb-469f-a165'}), {message: 'Invalid client GUID "0f8fad5b-d9cb-469f-a165"'});
+  t.throws(() => new SmbDirectoryHandle(smbURL, {clientGuid: '0f8fad5b-d9cb-469f-a165-70867728950g'}), {message: 'Invalid client GUID "0f8fad5b-d9cb-469f-a165-70867728950g"'});
+})
+
+test.serial('should sync directories', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const dirHandle = await rootHandle.getDirectoryHandle('first') as any as SmbDirectoryHandle;
+  await t.notThrowsAsync(rootHandle.sync());
+  await t.notThrowsAsync(dirHandle.sync());
+})
+
+test.serial('should resume writing from the committed offset', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('resumable', {create: true}) as SmbFileHandle;
+  const upload = await fileHandle.resumableWrite();
+  t.is(await upload.committedOffset(), 0);
+  await upload.write('first chunk,');
+  await upload.write(new TextEncoder().encode('second chunk,'));
+  t.is(await upload.committedOffset(), 25);
+  const resumed = await fileHandle.resumableWrite();
+  t.is(await resumed.committedOffset(), 25);
+  await resumed.write('third chunk');
+  t.is(await (await fileHandle.getFile()).text(), 'first chunk,second chunk,third chunk');
+  await rootHandle.removeEntry('resumable');
+})
+
+test.serial('should reject a resumable write issued while another is in progress', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('resumable-overlapping', {create: true}) as SmbFileHandle;
+  const upload = await fileHandle.resumableWrite();
+  const first = upload.write('first');
+  await t.throwsAsync(upload.write('second'), {message: /Write in progress/});
+  await first;
+  await upload.write(' then third');
+  t.is(await upload.committedOffset(), 16);
+  t.is(await (await fileHandle.getFile()).text(), 'first then third');
+  await rootHandle.removeEntry('resumable-overlapping');
+})
+
+test.serial('should hash file contents and byte ranges', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('hashed', {create: true}) as SmbFileHandle;
+  const writable = await fileHandle.createWritable();
+  await writable.write('123456789');
+  await writable.close();
+  t.is(await fileHandle.hash('crc32'), 'cbf43926');
+  t.is(await fileHandle.hash('sha256'), createHash('sha256').update('123456789').digest('hex'));
+  t.is(await fileHandle.hash('sha256', {offset: 2, length: 4}), createHash('sha256').update('3456').digest('hex'));
+  t.is(await fileHandle.hash('sha256', {offset: 5, length: 100}), createHash('sha256').update('6789').digest('hex'));
+  t.is(await fileHandle.hash('sha256', {offset: 100}), createHash('sha256').digest('hex'));
+  await t.throwsAsync(fileHandle.hash('md5' as any), {message: 'Unknown hash algorithm "md5"'});
+  await rootHandle.removeEntry('hashed');
+})
+
+test.serial('should report offline attributes as unset on local entries', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('annar') as SmbFileHandle;
+  const attributes = await fileHandle.attributes();
+  t.false(attributes.offline);
+  t.false(attributes.recallOnOpen);
+  t.false(attributes.recallOnDataAccess);
+  t.false((await rootHandle.attributes()).offline);
+})
+
+test.serial('should list directory entries with their stats', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const entries = await rootHandle.list({sort: 'name'});
+  const names = entries.map((entry) => entry.name);
+  t.deepEqual(names, [...names].sort());
+  const annar = entries.find((entry) => entry.name === 'annar');
+  t.is(annar?.kind, 'file');
+  t.is(annar?.stat.size, 123n);
+  t.true(annar?.stat.isFile);
+  const dirs = await rootHandle.list({kind: 'directory'});
+  t.true(dirs.length > 0);
+  t.true(dirs.every((entry) => entry.kind === 'directory' && entry.stat.isDirectory));
+  t.true(dirs.some((entry) => entry.name === 'first'));
+  const files = await rootHandle.list({kind: 'file', sort: 'size'});
+  const sizes = files.map((entry) => entry.stat.size);
+  t.deepEqual(sizes, [...sizes].sort((a, b) => (a < b ? -1 : a > b ? 1 : 0)));
+  await t.throwsAsync(rootHandle.list({sort: 'color' as any}), {message: 'Unknown sort order "color"'});
+})
+
+test.serial('should open another share on the same server', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const otherRoot = await rootHandle.withShare('other');
+  t.is(otherRoot.kind, 'directory');
+  t.true(otherRoot.isConnected());
+  t.false(await rootHandle.isSameEntry(otherRoot as any));
+  await t.throwsAsync(rootHandle.withShare('nested/share'), {message: 'Invalid share name "nested/share"'});
+  await otherRoot.shutdown();
+  t.true(rootHandle.isConnected());
+})
+
+test.serial('should refuse reading a file handle whose entry became a directory', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('file-then-dir', {create: true}) as SmbFileHandle;
+  await rootHandle.removeEntry('file-then-dir');
+  await rootHandle.getDirectoryHandle('file-then-dir', {create: true});
+  await t.throwsAsync(fileHandle.head(10), {message: /is a directory/});
+  await t.throwsAsync(fileHandle.hash('crc32'), {message: /is a directory/});
+  await rootHandle.removeEntry('file-then-dir', {recursive: true});
+})
+
+test.serial('should reject with typed errors carrying code, NT status and path', async (t) => {
+  const rootHandle = await getRootHandle();
+  const notFound = await t.throwsAsync(rootHandle.getFileHandle('unknown'), {instanceOf: SmbError, message: 'File "unknown" not found'}) as SmbError;
+  t.is(notFound.code, 'NOT_FOUND');
+  t.is(notFound.ntStatus, 0xC0000034);
+  t.is(notFound.path, '/unknown');
+  t.is(notFound.name, 'NotFoundError');
+
+  const notEmpty = await t.throwsAsync(rootHandle.removeEntry('first'), {instanceOf: SmbError}) as SmbError;
+  t.is(notEmpty.code, 'NOT_EMPTY');
+  t.is(notEmpty.ntStatus, 0xC0000101);
+  t.is(notEmpty.path, '/first/');
+
+  const mismatch = await t.throwsAsync(rootHandle.getDirectoryHandle('annar'), {instanceOf: SmbError}) as SmbError;
+  t.is(mismatch.code, 'TYPE_MISMATCH');
+  t.is(mismatch.ntStatus, 0xC0000103);
+
+  const invalid = await t.throwsAsync(rootHandle.getFileHandle('annar', {create: false, access: 'bogus' as any}), {instanceOf: SmbError, message: 'Unknown access "bogus"'}) as SmbError;
+  t.is(invalid.code, 'INVALID_ARGUMENT');
+
+  const fileHandle = await rootHandle.getFileHandle('typed-errors', {create: true}) as any as SmbFileHandle;
+  await rootHandle.removeEntry('typed-errors');
+  const removed = await t.throwsAsync(fileHandle.stat(), {instanceOf: SmbError}) as SmbError;
+  t.is(removed.code, 'NOT_FOUND');
+  t.is(removed.path, '/typed-errors');
+})
+
+test.serial('should find files by id after they are renamed', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const dirHandle = await rootHandle.getDirectoryHandle('by-file-id', {create: true}) as any as SmbDirectoryHandle;
+  const fileHandle = await dirHandle.getFileHandle('before', {create: true}) as any as SmbFileHandle;
+  const id = await fileHandle.fileId();
+  t.regex(id, /^[1-9][0-9]*$/);
+
+  await fileHandle.rename('after');
+  const reopened = await rootHandle.findByFileId(id) as any as SmbFileHandle;
+  t.is(reopened.name, 'after');
+  t.is(await reopened.fileId(), id);
+  await t.throwsAsync(rootHandle.findByFileId(id, {maxDepth: 1}), {message: `File id ${id} not found`});
+  t.is((await dirHandle.findByFileId(id, {maxDepth: 1})).name, 'after');
+
+  await t.throwsAsync(rootHandle.findByFileId('18446744073709551615'), {message: 'File id 18446744073709551615 not found'});
+  await t.throwsAsync(rootHandle.findByFileId('not-an-id'), {message: 'Invalid file id "not-an-id"'});
+  await t.throwsAsync(rootHandle.findByFileId(id, {maxDepth: 0}), {message: 'Property maxDepth must be at least 1'});
+  await rootHandle.removeEntry('by-file-id', {recursive: true});
+})
+
+test.serial('should reject URLs without a share', async (t) => {
+  const err = t.throws(() => new SmbDirectoryHandle('smb://127.0.0.1'), {instanceOf: SmbError}) as SmbError;
+  t.is(err.code, 'INVALID_ARGUMENT');
+  t.regex(err.message, /names no share/);
+})
+
+test.serial('should verify writes by reading them back', async (t) => {
+  const rootHandle = await getRootHandle();
+  for (const access of ['readwrite', 'write'] as const) {
+    const fileHandle = await rootHandle.getFileHandle(`verified-${access}`, {create: true}) as SmbFileHandle;
+    const writable = await fileHandle.createWritable({keepExistingData: false, access, verify: true});
+    await writable.write('verified');
+    await writable.write({type: 'write', position: 2, data: 'RIF'});
+    await writable.close();
+    t.is(await (await fileHandle.getFile()).text(), 'veRIFied');
+    await rootHandle.removeEntry(fileHandle.name);
+  }
+})
+
+test.serial('should return files shaped like Blobs', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('annar');
+  const file = await fileHandle.getFile();
+  t.is(file.name, 'annar');
+  t.is(typeof file.lastModified, 'number');
+  t.is(file.webkitRelativePath, '');
+  t.true(file.slice() instanceof Blob);
+  const bytes = await file.bytes();
+  t.true(bytes instanceof Uint8Array);
+  t.is(bytes.length, file.size);
+  t.deepEqual(new Uint8Array(await file.slice().arrayBuffer()), bytes);
+  const chunks: Uint8Array[] = [];
+  for await (const chunk of file.stream() as any) {
+    t.true(chunk instanceof Uint8Array);
+    chunks.push(chunk);
+  }
+  t.deepEqual(new Uint8Array(Buffer.concat(chunks)), bytes);
+})
+
+test.serial('should validate URL arguments given as connect options', async (t) => {
+  const rootHandle = new SmbDirectoryHandle(smbURL, {urlArgs: {'tcp-syncnt': '3'}});
+  t.truthy(await rootHandle.getFileHandle('annar'));
+  t.throws(() => new SmbDirectoryHandle(smbURL, {urlArgs: {'tcp-syncnt': ''}}), {message: 'Invalid URL argument "tcp-syncnt"=""'});
+  t.throws(() => new SmbDirectoryHandle(smbURL, {urlArgs: {'sec': 'ntlmssp&seal'}}), {message: 'Invalid URL argument "sec"="ntlmssp&seal"'});
+})
+
+test.serial('should read into caller-provided buffers', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('read-into', {create: true}) as any as SmbFileHandle;
+  const writable = await fileHandle.createWritable();
+  await writable.write('0123456789');
+  await writable.close();
+
+  const buffer = new Uint8Array(8).fill(42);
+  t.is(await fileHandle.readInto(buffer, 2n, 4), 4);
+  t.deepEqual(Array.from(buffer), [50, 51, 52, 53, 42, 42, 42, 42]);
+  t.is(await fileHandle.readInto(buffer, 6n), 4);
+  t.is(new TextDecoder().decode(buffer.subarray(0, 4)), '6789');
+  t.is(await fileHandle.readInto(buffer, 20n), 0);
+
+  await t.throwsAsync(fileHandle.readInto(buffer, 0n, 9), {message: 'Length 9 exceeds the buffer\'s 8 bytes'});
+  await t.throwsAsync(fileHandle.readInto(new Uint8Array(0), 0n), {message: 'Buffer is empty or detached'});
+  await t.throwsAsync(fileHandle.readInto(buffer, -1n), {message: 'Invalid offset -1'});
+  await rootHandle.removeEntry('read-into');
+})
+
+test.serial('should report the server time', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const serverTime = await rootHandle.serverTime();
+  t.is(typeof serverTime, 'bigint');
+  if (process.env.TEST_USING_MOCKS) {
+    t.is(serverTime, 1700000000000000000n);
+  } else {
+    t.true(serverTime > 1658159058723000000n);
+  }
+  const names: string[] = [];
+  for await (const [name] of rootHandle.entries()) {
+    names.push(name);
+  }
+  t.false(names.some((name) => name.startsWith('.smbjs-server-time-')));
+})
+
+test.serial('should replace the contents of larger files with smaller ones', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('replaced-contents', {create: true}) as any as SmbFileHandle;
+  await fileHandle.replaceContents('a much longer original content');
+  t.is((await fileHandle.getFile()).size, 30);
+  await fileHandle.replaceContents(new TextEncoder().encode('short'));
+  const file = await fileHandle.getFile();
+  t.is(file.size, 5);
+  t.is(await file.text(), 'short');
+  await fileHandle.replaceContents('');
+  t.is((await fileHandle.getFile()).size, 0);
+  await rootHandle.removeEntry('replaced-contents');
+})
+
+test.serial('should copy a directory tree into another directory', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const source = await rootHandle.getDirectoryHandle('copied-tree', {create: true}) as any as SmbDirectoryHandle;
+  const sub = await source.getDirectoryHandle('sub', {create: true});
+  const writable = await (await sub.getFileHandle('leaf', {create: true})).createWritable();
+  await writable.write('leaf contents');
+  await writable.close();
+  const dest = await rootHandle.getDirectoryHandle('copy-of-tree', {create: true}) as any as SmbDirectoryHandle;
+  const result = await source.copyTree(dest);
+  t.deepEqual(result, {directoriesCreated: 1, filesCopied: 1, bytesCopied: 13, failed: []});
+  const copied = await (await (await dest.getDirectoryHandle('sub')).getFileHandle('leaf')).getFile();
+  t.is(await copied.text(), 'leaf contents');
+  const again = await source.copyTree(dest);
+  t.is(again.filesCopied, 0);
+  t.is(again.failed.length, 1);
+  t.is(again.failed[0].path, '/copy-of-tree/sub/leaf');
+  t.is((await source.copyTree(dest, {overwrite: true})).filesCopied, 1);
+  await t.throwsAsync(rootHandle.copyTree(dest), {message: /into itself/});
+  await rootHandle.removeEntry('copied-tree', {recursive: true});
+  await rootHandle.removeEntry('copy-of-tree', {recursive: true});
+})
+
+test.serial('should report plain entries as not being reparse points', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('annar') as SmbFileHandle;
+  t.false(await fileHandle.isReparsePoint());
+  const dirHandle = await rootHandle.getDirectoryHandle('first') as any as SmbDirectoryHandle;
+  t.false(await dirHandle.isReparsePoint());
+  t.false(await rootHandle.isReparsePoint());
+})
+
+test.serial('should create files with the attributes asked for', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('created-hidden', {create: true, attributes: {hidden: true}} as any) as SmbFileHandle;
+  const attributes = await fileHandle.attributes();
+  t.true(attributes.hidden);
+  t.false(attributes.readOnly);
+  t.false(attributes.system);
+  const existing = await rootHandle.getFileHandle('created-hidden', {create: true, attributes: {system: true}} as any) as SmbFileHandle;
+  t.false((await existing.attributes()).system);
+  await rootHandle.removeEntry('created-hidden');
+})
+
+test.serial('should only list entries matching a pattern', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const names: string[] = [];
+  for await (const name of rootHandle.keys({pattern: 'A*'})) {
+    names.push(name);
+  }
+  t.deepEqual(names, ['annar']);
+  const dirNames: string[] = [];
+  for await (const dirHandle of rootHandle.directories({pattern: '*r*'})) {
+    dirNames.push(dirHandle.name);
+  }
+  t.deepEqual(dirNames.sort(), ['first', 'quatre']);
+  await t.throwsAsync(async () => {
+    for await (const _ of rootHandle.keys({pattern: ''})) {}
+  }, {message: 'Pattern is empty'});
+})
+
+test.serial('should reject a write issued while another is in progress', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('overlapping-writes', {create: true});
+  const writable = await fileHandle.createWritable() as SmbWritableFileStream;
+  const first = writable.write('first');
+  await t.throwsAsync(writable.write('second'), {message: /Write in progress/});
+  await t.throwsAsync(writable.finish(), {message: /Write in progress/});
+  await first;
+  await writable.write(' then third');
+  await writable.close();
+  t.is(await (await fileHandle.getFile()).text(), 'first then third');
+  await rootHandle.removeEntry('overlapping-writes');
+})
+
+test.serial('should list active operations and refuse to cancel unknown ones', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const hashing = (await rootHandle.getFileHandle('annar') as SmbFileHandle).hash('sha256');
+  const operations = rootHandle.activeOperations();
+  t.true(Array.isArray(operations));
+  for (const operation of operations) {
+    t.is(typeof operation.id, 'number');
+    t.false(operation.cancelled);
+  }
+  await hashing;
+  t.false(rootHandle.cancelOperation(999999));
+  t.deepEqual(rootHandle.activeOperations(), []);
+})
+
+test.serial('should write across a barrier', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('write-barrier', {create: true});
+  const writable = await fileHandle.createWritable() as SmbWritableFileStream;
+  await writable.write('journal ');
+  await writable.barrier();
+  await writable.write('commit');
+  await writable.close();
+  t.is(await (await fileHandle.getFile()).text(), 'journal commit');
+  await rootHandle.removeEntry('write-barrier');
+})
+
+test.serial('should watch by polling when asked to', async (t) => {
+  const rootHandle = await getRootHandle();
+  const smbHandle = rootHandle as any as SmbDirectoryHandle;
+  t.throws(() => smbHandle.watch(() => {}, {mode: 'inotify' as any}), {message: 'Unknown watch mode "inotify"'});
+  const caught: {path: string, action: string}[] = [];
+  const watcher = smbHandle.watch((change) => {
+    caught.push(...(Array.isArray(change) ? change : [change]));
+  }, {mode: 'poll', intervalMs: 20});
+  await rootHandle.getFileHandle('polled_file', {create: true});
+  await sleep(200);
+  await rootHandle.removeEntry('polled_file');
+  await sleep(200);
+  watcher.cancel();
+  await watcher.wait();
+  t.deepEqual(caught.filter(change => change.path == 'polled_file').map(change => change.action), ['create', 'remove']);
+})
+
+test.serial('should fail to poll a directory that no longer exists', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const dirHandle = await rootHandle.getDirectoryHandle('unpollable', {create: true}) as any as SmbDirectoryHandle;
+  await rootHandle.removeEntry('unpollable');
+  t.throws(() => dirHandle.watch(() => {}, {mode: 'poll', intervalMs: 20}), {instanceOf: SmbError, code: 'NOT_FOUND'});
+  t.throws(() => dirHandle.watch(() => {}, {mode: 'poll', intervalMs: 20}), {instanceOf: SmbError, code: 'NOT_FOUND'});
+})
+
+test.serial('should not resolve descendants deeper than maxDepth', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const topHandle = await rootHandle.getDirectoryHandle('deep', {create: true});
+  let dirHandle = topHandle;
+  for (const name of ['d1', 'd2', 'd3']) {
+    dirHandle = await dirHandle.getDirectoryHandle(name, {create: true});
+  }
+  const fileHandle = await dirHandle.getFileHandle('leaf', {create: true});
+  t.deepEqual(await topHandle.resolve(fileHandle), ['deep', 'd1', 'd2', 'd3', 'leaf']);
+  t.deepEqual(await topHandle.resolve(fileHandle, {maxDepth: 4}), ['deep', 'd1', 'd2', 'd3', 'leaf']);
+  t.is(await topHandle.resolve(fileHandle, {maxDepth: 3}), null);
+  await t.throwsAsync(topHandle.resolve(fileHandle, {maxDepth: 0}), {message: 'Property maxDepth must be at least 1'});
+  await rootHandle.removeEntry('deep', {recursive: true});
+})
+
+test.serial('should resolve handles by path and plain objects by searching', async (t) => {
+  const rootHandle = await getRootHandle();
+  const dirHandle = await rootHandle.getDirectoryHandle('first');
+  const siblingHandle = await rootHandle.getDirectoryHandle('quatre');
+  const fileHandle = await dirHandle.getFileHandle('comment');
+  t.deepEqual(await dirHandle.resolve(fileHandle), ['first', 'comment']);
+  t.is(await siblingHandle.resolve(fileHandle), null);
+  t.is(await dirHandle.resolve(dirHandle), null);
+  t.deepEqual(await dirHandle.resolve({kind: 'file', name: 'comment'} as any), ['first', 'comment']);
+  t.is(await siblingHandle.resolve({kind: 'file', name: 'comment'} as any), null);
+})
+
+test.serial('should encode strings written with an encoding and newline', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('encoded', {create: true});
+  const writable = await fileHandle.createWritable({encoding: 'utf-16le', newline: 'crlf'} as any);
+  await writable.write('a\nb\r\n');
+  await writable.close();
+  t.deepEqual(Buffer.from(await (await fileHandle.getFile()).arrayBuffer()), Buffer.from('a\r\nb\r\n', 'utf16le'));
+  const latin1 = await fileHandle.createWritable({encoding: 'latin1'} as any);
+  await latin1.write('café');
+  await t.throwsAsync(latin1.write('€'), {message: "Character '€' can't be encoded in latin1"});
+  await latin1.close();
+  t.deepEqual(Buffer.from(await (await fileHandle.getFile()).arrayBuffer()), Buffer.from('café', 'latin1'));
+  await t.throwsAsync(fileHandle.createWritable({encoding: 'ebcdic'} as any), {message: 'Unsupported encoding "ebcdic"'});
+  await t.throwsAsync(fileHandle.createWritable({newline: 'cr'} as any), {message: 'Unsupported newline "cr"'});
+  await rootHandle.removeEntry('encoded');
+})
+
+test.serial('should transfer a file within a share by renaming it', async (t) => {
+  const rootHandle = await getRootHandle();
+  const dirHandle = await rootHandle.getDirectoryHandle('first');
+  const fileHandle = await rootHandle.getFileHandle('transferred', {create: true}) as SmbFileHandle;
+  const writable = await fileHandle.createWritable();
+  await writable.write('moving');
+  await writable.close();
+  t.deepEqual(await fileHandle.transferTo(dirHandle, 'moved'), {strategy: 'rename', bytesCopied: 0, name: 'moved'});
+  t.is(await (await (await dirHandle.getFileHandle('moved')).getFile()).text(), 'moving');
+  await t.throwsAsync(rootHandle.getFileHandle('transferred'));
+  const other = await rootHandle.getFileHandle('transferred', {create: true}) as SmbFileHandle;
+  await t.throwsAsync(other.transferTo(dirHandle, 'moved'));
+  await t.throwsAsync(other.transferTo(dirHandle, '../escape'), {message: 'Invalid name "../escape"'});
+  await rootHandle.removeEntry('transferred');
+  await dirHandle.removeEntry('moved');
+})
+
+test.serial('should copy zero filled regions of a file', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const source = await rootHandle.getDirectoryHandle('holey-tree', {create: true}) as any as SmbDirectoryHandle;
+  const writable = await (await source.getFileHandle('holey', {create: true})).createWritable();
+  await writable.write({type: 'write', position: 8, data: 'end'});
+  await writable.close();
+  const dest = await rootHandle.getDirectoryHandle('copy-of-holey-tree', {create: true}) as any as SmbDirectoryHandle;
+  t.is((await source.copyTree(dest)).bytesCopied, 11);
+  const copied = await (await dest.getFileHandle('holey')).getFile();
+  t.deepEqual(new Uint8Array(await copied.arrayBuffer()), new Uint8Array([0, 0, 0, 0, 0, 0, 0, 0, 101, 110, 100]));
+  await rootHandle.removeEntry('holey-tree', {recursive: true});
+  await rootHandle.removeEntry('copy-of-holey-tree', {recursive: true});
+})
+
+test.serial('should wait for a file to reach a size', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('growing', {create: true}) as SmbFileHandle;
+  t.is(await fileHandle.waitForSize(4n, {intervalMs: 10, timeoutMs: 50}), 0n);
+  const waiting = fileHandle.waitForSize(4n, {intervalMs: 10});
+  const writable = await fileHandle.createWritable();
+  await writable.write('grown');
+  await writable.close();
+  t.is(await waiting, 5n);
+  t.is(await fileHandle.waitForSize(0n), 5n);
+  await t.throwsAsync(fileHandle.waitForSize(-1n), {message: 'Invalid size -1'});
+  await rootHandle.removeEntry('growing');
+})
+
+test.serial('should cancel waiting for a size at once, on cancelOperation and on shutdown', async (t) => {
+  const rootHandle = new SmbDirectoryHandle(smbURL);
+  const fileHandle = await rootHandle.getFileHandle('never-growing', {create: true}) as SmbFileHandle;
+  const waiting = fileHandle.waitForSize(4n, {intervalMs: 60000, timeoutMs: 120000});
+  await new Promise((resolve) => setTimeout(resolve, 50));
+  const [operation] = rootHandle.activeOperations().filter((operation) => operation.kind === 'waitForSize');
+  const cancelling = Date.now();
+  t.true(rootHandle.cancelOperation(operation.id));
+  const cancelled = await t.throwsAsync(waiting, {instanceOf: SmbError}) as SmbError;
+  t.is(cancelled.code, 'CANCELLED');
+  t.true(Date.now() - cancelling < 5000);
+  const stranded = fileHandle.waitForSize(4n, {intervalMs: 60000, timeoutMs: 120000});
+  await new Promise((resolve) => setTimeout(resolve, 50));
+  await rootHandle.removeEntry('never-growing');
+  const shuttingDown = Date.now();
+  await rootHandle.shutdown();
+  t.true(Date.now() - shuttingDown < 5000);
+  await t.throwsAsync(stranded, {instanceOf: SmbError});
+})
+
+test.serial('should tail a growing file', async (t) => {
+  const rootHandle = await getRootHandle();
+  const fileHandle = await rootHandle.getFileHandle('tailed', {create: true}) as SmbFileHandle;
+  await (await fileHandle.openAppend()).append('before\n');
+  const lines = fileHandle.tail({delimiter: '\n', intervalMs: 10});
+  const first = lines.next();
+  await new Promise((resolve) => setTimeout(resolve, 50)); // XXX: the end of the file is looked up by the first next() in the background
+  const appender = await fileHandle.openAppend();
+  await appender.append('after');
+  await appender.append(' the start\nand more\n');
+  t.deepEqual(await first, {value: 'after the start', done: false});
+  t.deepEqual(await lines.next(), {value: 'and more', done: false});
+  await lines.return(undefined);
+  const chunks = fileHandle.tail({fromStart: true, intervalMs: 10});
+  t.deepEqual(new TextDecoder().decode((await chunks.next()).value as Uint8Array), 'before\nafter the start\nand more\n');
+  await chunks.return(undefined);
+  await t.throwsAsync(fileHandle.tail({delimiter: ''}).next(), {message: 'Delimiter must not be empty'});
+  await rootHandle.removeEntry('tailed');
+})
+
+test.serial('should stop a waiting tail at once on close and shutdown', async (t) => {
+  const rootHandle = new SmbDirectoryHandle(smbURL);
+  const fileHandle = await rootHandle.getFileHandle('tailed-idle', {create: true}) as SmbFileHandle;
+  const tail = (fileHandle as any)._js.tail({intervalMs: 60000});
+  const waiting = tail.next();
+  await new Promise((resolve) => setTimeout(resolve, 50));
+  const closing = Date.now();
+  tail.close();
+  t.is(await waiting, null);
+  t.true(Date.now() - closing < 5000);
+  const stranded = (fileHandle as any)._js.tail({intervalMs: 60000}).next();
+  await new Promise((resolve) => setTimeout(resolve, 50));
+  await rootHandle.removeEntry('tailed-idle');
+  const shuttingDown = Date.now();
+  await rootHandle.shutdown();
+  t.true(Date.now() - shuttingDown < 5000);
+  const cancelled = await t.throwsAsync(stranded, {instanceOf: SmbError}) as SmbError;
+  t.is(cancelled.code, 'CANCELLED');
+})
+
+test.serial('should find entries matching criteria', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const found = await rootHandle.find({minSize: 100, kind: 'file'});
+  t.deepEqual(found.map((entry) => entry.name), ['annar']);
+  t.is(found[0].stat.size, 123n);
+  t.deepEqual((await rootHandle.find({namePattern: 'FIR*'})).map((entry) => entry.name), ['first']);
+  const nested = await rootHandle.findRecursive({namePattern: 'p*', kind: 'file'});
+  t.deepEqual(nested.map((entry) => entry.name), ['quatre/points']);
+  t.deepEqual(await rootHandle.find({modifiedAfter: Date.now() * 1_000_000 + 60e9}), []);
+  await t.throwsAsync(rootHandle.find({minSize: -1}), {message: 'Property minSize must not be negative'});
+  await t.throwsAsync(rootHandle.find({modifiedAfter: -1}), {message: 'Property modifiedAfter is out of range'});
+  await t.throwsAsync(rootHandle.find({kind: 'socket' as any}), {message: 'Unknown kind "socket"'});
+})
+
+test.serial('should apply conflict policies to copies and moves', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const source = await rootHandle.getDirectoryHandle('conflict-source', {create: true}) as any as SmbDirectoryHandle;
+  const dest = await rootHandle.getDirectoryHandle('conflict-dest', {create: true}) as any as SmbDirectoryHandle;
+  t.true((await source.uploadBatch([{name: 'a.txt', data: 'new'}])).committed);
+  t.true((await dest.uploadBatch([{name: 'a.txt', data: 'old'}])).committed);
+  t.deepEqual(await source.copyTree(dest, {onConflict: 'skip'}), {directoriesCreated: 0, filesCopied: 0, bytesCopied: 0, failed: []});
+  t.is((await source.copyTree(dest)).failed[0].error, 'Entry "a.txt" already exists');
+  t.is((await source.copyTree(dest, {onConflict: 'rename'})).filesCopied, 1);
+  t.is(await (await (await dest.getFileHandle('a (1).txt')).getFile()).text(), 'new');
+  t.is(await (await (await dest.getFileHandle('a.txt')).getFile()).text(), 'old');
+  const renamed = await dest.uploadBatch([{name: 'a.txt', data: 'newer'}, {name: 'b', data: 'b'}], {onConflict: 'rename'});
+  t.deepEqual(renamed.files.map((f) => [f.status, f.committedName]), [['committed', 'a (2).txt'], ['committed', 'b']]);
+  const skipped = await dest.uploadBatch([{name: 'a.txt', data: 'newest'}, {name: 'c', data: 'c'}], {onConflict: 'skip'});
+  t.true(skipped.committed);
+  t.deepEqual(skipped.files.map((f) => f.status), ['skipped', 'committed']);
+  t.is(await (await (await dest.getFileHandle('a.txt')).getFile()).text(), 'old');
+  const fileHandle = await source.getFileHandle('a.txt') as SmbFileHandle;
+  t.deepEqual(await fileHandle.transferTo(dest, undefined, {onConflict: 'skip'}), {strategy: 'skipped', bytesCopied: 0, name: 'a.txt'});
+  t.deepEqual(await fileHandle.transferTo(dest, undefined, {onConflict: 'overwrite'}), {strategy: 'rename', bytesCopied: 0, name: 'a.txt'});
+  t.is(await (await (await dest.getFileHandle('a.txt')).getFile()).text(), 'new');
+  await t.throwsAsync(source.copyTree(dest, {onConflict: 'clobber' as any}), {message: 'Unknown conflict policy "clobber"'});
+  await rootHandle.removeEntry('conflict-source', {recursive: true});
+  await rootHandle.removeEntry('conflict-dest', {recursive: true});
+})
+
+test.serial('should copy with permissions preserved', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const source = await rootHandle.getDirectoryHandle('moded-source', {create: true}) as any as SmbDirectoryHandle;
+  const dest = await rootHandle.getDirectoryHandle('moded-dest', {create: true}) as any as SmbDirectoryHandle;
+  const restricted = new SmbDirectoryHandle(smbURL, {defaultFileMode: 0o640});
+  const restrictedSource = await restricted.getDirectoryHandle('moded-source') as any as SmbDirectoryHandle;
+  const writable = await (await restrictedSource.getFileHandle('a.txt', {create: true})).createWritable();
+  await writable.write('moded');
+  await writable.close();
+  t.is((await (await source.getFileHandle('a.txt') as any as SmbFileHandle).stat()).mode, 0o640);
+  t.like(await source.copyTree(dest, {preservePermissions: true}), {filesCopied: 1, bytesCopied: 5, failed: []});
+  t.is(await (await (await dest.getFileHandle('a.txt')).getFile()).text(), 'moded');
+  t.is((await (await dest.getFileHandle('a.txt') as any as SmbFileHandle).stat()).mode, 0o640);
+  await dest.removeEntry('a.txt');
+  t.like(await source.copyTree(dest), {filesCopied: 1, failed: []});
+  t.is((await (await dest.getFileHandle('a.txt') as any as SmbFileHandle).stat()).mode, 0o666);
+  await rootHandle.removeEntry('moded-source', {recursive: true});
+  await rootHandle.removeEntry('moded-dest', {recursive: true});
+})
+
+test.serial('should report permission bits in stats', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileStats = await (await rootHandle.getFileHandle('annar') as any as SmbFileHandle).stat();
+  t.is(fileStats.mode & ~0o777, 0);
+  t.is(fileStats.mode & 0o444, 0o444);
+  const dirStats = await (await rootHandle.getDirectoryHandle('first')).stat();
+  t.is(dirStats.mode & 0o111, 0o111);
+})
+
+test.serial('should report connection capabilities', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const capabilities = rootHandle.capabilities();
+  t.deepEqual(Object.keys(capabilities).sort(), ['dfs', 'encryption']);
+  t.false(capabilities.dfs);
+})
+
+test.serial('should stat many entries at once', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const stats = await rootHandle.statMany(['annar', 'first', 'missing']);
+  t.is(stats.annar?.size, 123n);
+  t.true(stats.first?.isDirectory);
+  t.is(stats.missing, null);
+  t.deepEqual(await rootHandle.statMany([]), {});
+  await t.throwsAsync(rootHandle.statMany(['a/b']), {message: 'Invalid name "a/b"'});
+})
+
+test.serial('should refuse writes through read-only file handles', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('annar', {readOnly: true}) as any as SmbFileHandle;
+  await t.throwsAsync(fileHandle.createWritable(), {message: 'File "annar" was opened read-only'});
+  await t.throwsAsync(fileHandle.replaceContents('overwritten'), {message: /opened read-only/});
+  t.is((await fileHandle.getFile()).size, 123);
+  await t.throwsAsync(rootHandle.getFileHandle('annar', {readOnly: true, access: 'write'}), {message: 'Access "write" conflicts with readOnly'});
+})
+
+test.serial('should stat the share root as a directory', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  t.true((await rootHandle.stat()).isDirectory);
+  t.true((await rootHandle.lstat()).isDirectory);
+})
+
+test.serial('should rename over an existing file only with overwrite', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const fileHandle = await rootHandle.getFileHandle('rename-over', {create: true}) as any as SmbFileHandle;
+  await rootHandle.getFileHandle('rename-target', {create: true});
+  await t.throwsAsync(fileHandle.rename('rename-target'), {message: 'Entry "rename-target" already exists'});
+  t.is(fileHandle.name, 'rename-over');
+  await fileHandle.rename('rename-target', {overwrite: true});
+  t.is(fileHandle.name, 'rename-target');
+  await t.throwsAsync(rootHandle.getFileHandle('rename-over'), {message: 'File "rename-over" not found'});
+  await rootHandle.removeEntry('rename-target');
+})
+
+test.serial('should accept TCP connect options', async (t) => {
+  const rootHandle = new SmbDirectoryHandle(smbURL, {tcpNoDelay: true, tcpKeepAlive: true});
+  t.true(rootHandle.isConnected());
+  t.is((await rootHandle.getFileHandle('annar').then((handle) => handle.getFile())).size, 123);
+  t.true(new SmbDirectoryHandle(smbURL, {tcpNoDelay: false, tcpKeepAlive: false}).isConnected());
+})
+
+test.serial('should snapshot the directory tree', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const dirHandle = await rootHandle.getDirectoryHandle('first') as any as SmbDirectoryHandle;
+  const tree = await dirHandle.snapshot();
+  t.is(tree.name, 'first');
+  t.is(tree.kind, 'directory');
+  const comment = tree.children?.find((node) => node.name === 'comment');
+  t.is(comment?.kind, 'file');
+  t.is(comment?.size, 0);
+  t.is(typeof comment?.mtime, 'number');
+  t.is(comment?.children, undefined);
+  t.is(JSON.parse(JSON.stringify(tree)).children.length, tree.children?.length);
+  const bare = await rootHandle.snapshot({maxDepth: 1, includeStats: false});
+  t.is(bare.children?.find((node) => node.name === 'annar')?.size, undefined);
+  t.is(bare.children?.find((node) => node.name === 'first')?.children, undefined);
+  await t.throwsAsync(rootHandle.snapshot({maxDepth: 0}), {message: 'Property maxDepth must be at least 1'});
+})
+
+test.serial('should move files to another share on the same server', async (t) => {
+  const rootHandle = await getRootHandle() as any as SmbDirectoryHandle;
+  const otherRoot = await rootHandle.withShare('other');
+  const fileHandle = await rootHandle.getFileHandle('cross-share', {create: true}) as any as SmbFileHandle;
+  await fileHandle.replaceContents('moved across shares');
+  const result = await fileHandle.transferTo(otherRoot as any, 'cross-share', {onConflict: 'overwrite'});
+  t.true(['serverCopy', 'copy'].includes(result.strategy));
+  t.is(result.bytesCopied, 19);
+  t.is(await (await (await otherRoot.getFileHandle('cross-share')).getFile()).text(), 'moved across shares');
+  await t.throwsAsync(rootHandle.getFileHandle('cross-share'), {message: 'File "cross-share" not found'});
+  await otherRoot.removeEntry('cross-share');
+  await otherRoot.shutdown();
+})
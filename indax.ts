@@ -18,24 +18,130 @@
 
 import {
   JsSmbHandlePermissionDescriptor,
+  JsSmbEntriesOptions,
   JsSmbGetDirectoryOptions,
   JsSmbGetFileOptions,
   JsSmbRemoveOptions,
+  JsSmbRenameOptions,
+  JsSmbResolveOptions,
+  JsSmbFindByFileIdOptions,
+  JsSmbRemoveEntriesResult,
+  JsSmbUploadFile,
+  JsSmbCopyTreeOptions,
+  JsSmbCopyTreeProgress,
+  JsSmbCopyTreeResult,
+  JsSmbActiveOperation,
+  JsSmbTransferResult,
+  JsSmbUploadBatchOptions,
+  JsSmbTransferOptions,
+  JsSmbUploadBatchResult,
   JsSmbCreateWritableOptions,
+  JsSmbWriteFromStreamOptions,
+  JsSmbLinesOptions,
+  JsSmbWaitForSizeOptions,
+  JsSmbTailOptions,
   JsSmbStat,
+  JsSmbConnectOptions,
+  JsSmbConnectionInfo,
+  JsSmbCapabilities,
+  JsSmbMetrics,
+  JsSmbMetricsOptions,
+  JsSmbWatchOptions,
+  JsSmbNotifyChange,
+  JsSmbWriteSummary,
+  JsSmbSetTimesOptions,
+  JsSmbListOptions,
+  JsSmbFindCriteria,
+  JsSmbFindOptions,
+  JsSmbListEntry,
+  JsSmbSnapshotOptions,
+  JsSmbTreeNode,
+  JsSmbAttributes,
+  JsSmbHashOptions,
+  JsSmbAcl,
+  JsSmbError,
   JsSmbHandle,
   JsSmbDirectoryHandle,
   JsSmbFileHandle,
+  JsSmbAppendHandle,
+  JsSmbResumableWriteHandle,
   JsSmbWritableFileStream,
+  JsSmbFile,
+  JsSmbDebugEvent,
+  setMaxConcurrentOperations as jsSetMaxConcurrentOperations,
+  setDebugLogger as jsSetDebugLogger,
+  toUncPath as jsToUncPath,
+  fromUncPath as jsFromUncPath,
+  setSmbErrorClass,
 } from './index';
 
 type SmbStat = JsSmbStat;
+type SmbAttributes = JsSmbAttributes;
+type SmbLinesOptions = JsSmbLinesOptions;
+type SmbWaitForSizeOptions = JsSmbWaitForSizeOptions;
+type SmbTailOptions = JsSmbTailOptions;
+type SmbWriteFromStreamOptions = JsSmbWriteFromStreamOptions;
+type SmbConnectOptions = JsSmbConnectOptions;
+type SmbConnectionInfo = JsSmbConnectionInfo;
+type SmbCapabilities = JsSmbCapabilities;
+type SmbMetrics = JsSmbMetrics;
+type SmbMetricsOptions = JsSmbMetricsOptions;
+type SmbWatchOptions = JsSmbWatchOptions;
+type SmbNotifyChange = JsSmbNotifyChange;
+type SmbDebugEvent = JsSmbDebugEvent;
+type SmbWriteSummary = JsSmbWriteSummary;
 type SmbHandlePermissionDescriptor = JsSmbHandlePermissionDescriptor;
+type SmbRemoveEntriesResult = JsSmbRemoveEntriesResult;
+type SmbUploadFile = JsSmbUploadFile;
+type SmbCopyTreeOptions = JsSmbCopyTreeOptions;
+type SmbCopyTreeProgress = JsSmbCopyTreeProgress;
+type SmbCopyTreeResult = JsSmbCopyTreeResult;
+type SmbActiveOperation = JsSmbActiveOperation;
+type SmbTransferResult = JsSmbTransferResult;
+type SmbUploadBatchOptions = JsSmbUploadBatchOptions;
+type SmbTransferOptions = JsSmbTransferOptions;
+type SmbUploadBatchResult = JsSmbUploadBatchResult;
+type SmbSetTimesOptions = JsSmbSetTimesOptions;
+type SmbHashOptions = JsSmbHashOptions;
+type SmbAcl = JsSmbAcl;
+type SmbAppendHandle = JsSmbAppendHandle;
+type SmbResumableWriteHandle = JsSmbResumableWriteHandle;
+type SmbEntriesOptions = JsSmbEntriesOptions;
+type SmbListOptions = JsSmbListOptions;
+type SmbListEntry = JsSmbListEntry;
+type SmbSnapshotOptions = JsSmbSnapshotOptions;
+type SmbTreeNode = JsSmbTreeNode;
+type SmbFindCriteria = JsSmbFindCriteria;
+type SmbFindOptions = JsSmbFindOptions;
+type SmbResolveOptions = JsSmbResolveOptions;
+type SmbFindByFileIdOptions = JsSmbFindByFileIdOptions;
+type SmbRenameOptions = JsSmbRenameOptions;
 // @ts-ignore
-type SmbCreateWritableOptions = FileSystemCreateWritableOptions;
+type SmbGetFileOptions = FileSystemGetFileOptions & Pick<JsSmbGetFileOptions, 'access' | 'attributes' | 'readOnly'>;
+// @ts-ignore
+type SmbRemoveOptions = FileSystemRemoveOptions & Pick<JsSmbRemoveOptions, 'symlinks'>;
+// @ts-ignore
+type SmbCreateWritableOptions = FileSystemCreateWritableOptions & Pick<JsSmbCreateWritableOptions, 'access' | 'verify' | 'encoding' | 'newline'>;
 // @ts-ignore
 type FileSystemWritableFileStream = FileSystemWritableFileStream;
 
+type SmbErrorCode = JsSmbError['code'];
+
+export class SmbError extends Error implements JsSmbError {
+  readonly code: SmbErrorCode
+  readonly ntStatus: number
+  readonly path: string
+  constructor(message: string, code: SmbErrorCode, ntStatus: number, path: string) {
+    super(message);
+    this.name = 'SmbError';
+    this.code = code;
+    this.ntStatus = ntStatus;
+    this.path = path;
+  }
+}
+
+setSmbErrorClass(SmbError);
+
 type TypedArray = Int8Array | Uint8Array | Uint8ClampedArray | Int16Array | Uint16Array | Int32Array | Uint32Array | Float32Array | Float64Array | BigInt64Array | BigUint64Array;
 
 export class SmbHandle implements FileSystemHandle {
@@ -47,6 +153,10 @@ export class SmbHandle implements FileSystemHandle {
     this.kind = _jsh.kind;
     this.name = _jsh.name;
   }
+  protected renamed(_jsh: JsSmbHandle) {
+    this._jsh = _jsh;
+    (this as { name: string }).name = _jsh.name;
+  }
   isSameEntry(other: FileSystemHandle): Promise<boolean> {
     return new Promise(async (resolve, reject) => {
       try {
@@ -56,6 +166,12 @@ export class SmbHandle implements FileSystemHandle {
       }
     });
   }
+  isConnected(): boolean {
+    return this._jsh.isConnected();
+  }
+  connectionInfo(): SmbConnectionInfo {
+    return this._jsh.connectionInfo();
+  }
   async queryPermission(perm: SmbHandlePermissionDescriptor): Promise<PermissionState> {
     return this._jsh.queryPermission(perm) as Promise<PermissionState>;
   }
@@ -65,6 +181,19 @@ export class SmbHandle implements FileSystemHandle {
   async stat(): Promise<SmbStat> {
     return this._jsh.stat() as Promise<SmbStat>;
   }
+  async lstat(): Promise<SmbStat> {
+    return this._jsh.lstat() as Promise<SmbStat>;
+  }
+  async attributes(): Promise<SmbAttributes> {
+    return this._jsh.attributes();
+  }
+  async isReparsePoint(): Promise<boolean> {
+    return this._jsh.isReparsePoint();
+  }
+  async getParent(): Promise<FileSystemDirectoryHandle | null> {
+    const parent = await this._jsh.getParent();
+    return parent ? new SmbDirectoryHandle(parent) as any as FileSystemDirectoryHandle : null;
+  }
 }
 
 export class SmbDirectoryHandle extends SmbHandle implements FileSystemDirectoryHandle {
@@ -72,11 +201,11 @@ export class SmbDirectoryHandle extends SmbHandle implements FileSystemDirectory
   [Symbol.asyncIterator]: SmbDirectoryHandle['entries'] = this.entries
   declare readonly kind: 'directory'
   private _js: JsSmbDirectoryHandle
-  constructor(url: string);
+  constructor(url: string, options?: SmbConnectOptions);
   constructor(toWrap: JsSmbDirectoryHandle);
-  constructor(param: string | JsSmbDirectoryHandle) {
+  constructor(param: string | JsSmbDirectoryHandle, options?: SmbConnectOptions) {
     const [url, toWrap] = typeof param === 'string' ? [param] : ['', param];
-    const _js = toWrap || new JsSmbDirectoryHandle(url);
+    const _js = toWrap || connect(url, options);
     super(_js.toHandle());
     this[Symbol.asyncIterator] = this.entries;
     this._js = _js;
@@ -84,24 +213,74 @@ export class SmbDirectoryHandle extends SmbHandle implements FileSystemDirectory
     this.getDirectory = this.getDirectoryHandle;
     this.getEntries = this.values;
   }
+  metrics(options?: SmbMetricsOptions): SmbMetrics {
+    return this._js.metrics(options);
+  }
+  capabilities(): SmbCapabilities {
+    return this._js.capabilities();
+  }
+  async rename(newName: string, options?: SmbRenameOptions): Promise<void> {
+    await this._js.rename(newName, options);
+    this.renamed(this._js.toHandle());
+  }
+  async shutdown(): Promise<void> {
+    return this._js.shutdown();
+  }
+  activeOperations(): SmbActiveOperation[] {
+    return this._js.activeOperations();
+  }
+  cancelOperation(id: number): boolean {
+    return this._js.cancelOperation(id);
+  }
+  async serverTime(): Promise<bigint> {
+    return this._js.serverTime();
+  }
+  async withShare(shareName: string): Promise<SmbDirectoryHandle> {
+    return new SmbDirectoryHandle(await this._js.withShare(shareName));
+  }
   // @ts-ignore
-  async *entries(): AsyncIterableIterator<[string, FileSystemDirectoryHandle | FileSystemFileHandle]> {
-    for await (const [key, value] of this._js.entries()) {
+  async *entries(options?: SmbEntriesOptions): AsyncIterableIterator<[string, FileSystemDirectoryHandle | FileSystemFileHandle]> {
+    for await (const [key, value] of this._js.entries(options)) {
       yield [key, value instanceof JsSmbDirectoryHandle ? new SmbDirectoryHandle(value) as any as FileSystemDirectoryHandle : new SmbFileHandle(value) as FileSystemFileHandle];
     }
   }
   // @ts-ignore
-  async *keys(): AsyncIterableIterator<string> {
-    for await (const key of this._js.keys()) {
+  async *keys(options?: SmbEntriesOptions): AsyncIterableIterator<string> {
+    for await (const key of this._js.keys(options)) {
       yield key;
     }
   }
   // @ts-ignore
-  async *values(): AsyncIterableIterator<FileSystemDirectoryHandle | FileSystemFileHandle> {
-    for await (const value of this._js.values()) {
+  async *values(options?: SmbEntriesOptions): AsyncIterableIterator<FileSystemDirectoryHandle | FileSystemFileHandle> {
+    for await (const value of this._js.values(options)) {
       yield value instanceof JsSmbDirectoryHandle ? new SmbDirectoryHandle(value) as any as FileSystemDirectoryHandle : new SmbFileHandle(value) as FileSystemFileHandle;
     }
   }
+  async *directories(options?: SmbEntriesOptions): AsyncIterableIterator<FileSystemDirectoryHandle> {
+    for await (const value of this._js.directories(options)) {
+      yield new SmbDirectoryHandle(value) as any as FileSystemDirectoryHandle;
+    }
+  }
+  async *files(options?: SmbEntriesOptions): AsyncIterableIterator<FileSystemFileHandle> {
+    for await (const value of this._js.files(options)) {
+      yield new SmbFileHandle(value) as FileSystemFileHandle;
+    }
+  }
+  async list(options?: SmbListOptions): Promise<SmbListEntry[]> {
+    return this._js.list(options);
+  }
+  async find(criteria: SmbFindCriteria): Promise<SmbListEntry[]> {
+    return this._js.find(criteria);
+  }
+  async findRecursive(criteria: SmbFindCriteria, options?: SmbFindOptions): Promise<SmbListEntry[]> {
+    return this._js.findRecursive(criteria, options);
+  }
+  async snapshot(options?: SmbSnapshotOptions): Promise<SmbTreeNode> {
+    return this._js.snapshot(options);
+  }
+  async statMany(names: string[]): Promise<Record<string, SmbStat | null>> {
+    return this._js.statMany(names);
+  }
   async getDirectoryHandle(name: string, options?: FileSystemGetDirectoryOptions): Promise<FileSystemDirectoryHandle> {
     //console.log("getDirectoryHandle: ", name);
     return new Promise(async (resolve, reject) => {
@@ -120,7 +299,7 @@ export class SmbDirectoryHandle extends SmbHandle implements FileSystemDirectory
         });
     });
   }
-  async getFileHandle(name: string, options?: FileSystemGetFileOptions): Promise<FileSystemFileHandle> {
+  async getFileHandle(name: string, options?: SmbGetFileOptions): Promise<FileSystemFileHandle> {
     return new Promise(async (resolve, reject) => {
       await this._js.getFileHandle(name, options as JsSmbGetFileOptions)
         .then((handle) => resolve(new SmbFileHandle(handle) as FileSystemFileHandle))
@@ -137,11 +316,33 @@ export class SmbDirectoryHandle extends SmbHandle implements FileSystemDirectory
         });
     });
   }
-  async removeEntry(name: string, options?: FileSystemRemoveOptions): Promise<void> {
+  async touch(name: string): Promise<FileSystemFileHandle> {
+    return new SmbFileHandle(await this._js.touch(name)) as FileSystemFileHandle;
+  }
+  async sync(): Promise<void> {
+    return this._js.sync();
+  }
+  async getOrCreateFile(name: string): Promise<{ handle: FileSystemFileHandle, created: boolean }> {
+    const { handle, created } = await this._js.getOrCreateFile(name);
+    return { handle: new SmbFileHandle(handle) as FileSystemFileHandle, created };
+  }
+  async findByFileId(id: string, options?: SmbFindByFileIdOptions): Promise<FileSystemFileHandle> {
+    return new SmbFileHandle(await this._js.findByFileId(id, options)) as FileSystemFileHandle;
+  }
+  async removeEntry(name: string, options?: SmbRemoveOptions): Promise<void> {
     return this._js.removeEntry(name, options as JsSmbRemoveOptions);
   }
-  async resolve(possibleDescendant: FileSystemHandle): Promise<Array<string> | null> {
-    return this._js.resolve((possibleDescendant as any)._jsh || possibleDescendant);
+  async removeEntries(names: string[], options?: SmbRemoveOptions): Promise<SmbRemoveEntriesResult> {
+    return this._js.removeEntries(names, options as JsSmbRemoveOptions);
+  }
+  async uploadBatch(files: SmbUploadFile[], options?: SmbUploadBatchOptions): Promise<SmbUploadBatchResult> {
+    return this._js.uploadBatch(files, options);
+  }
+  async copyTree(dest: FileSystemDirectoryHandle, options?: SmbCopyTreeOptions, progress?: (progress: SmbCopyTreeProgress) => void): Promise<SmbCopyTreeResult> {
+    return this._js.copyTree((dest as any)._js || dest, options, progress);
+  }
+  async resolve(possibleDescendant: FileSystemHandle, options?: SmbResolveOptions): Promise<Array<string> | null> {
+    return this._js.resolve((possibleDescendant as any)._jsh || possibleDescendant, options);
   }
 
   /**
@@ -158,8 +359,8 @@ export class SmbDirectoryHandle extends SmbHandle implements FileSystemDirectory
   getEntries: SmbDirectoryHandle['values']
 
 
-  watch(callback: (...args: any[]) => any) {
-    return this._js.watch(callback)
+  watch(callback: (change: SmbNotifyChange | SmbNotifyChange[]) => void, options?: SmbWatchOptions) {
+    return this._js.watch(callback, options)
   }
  }
 
@@ -192,6 +393,65 @@ export class SmbFileHandle extends SmbHandle implements FileSystemFileHandle {
         });
     });
   }
+  async head(count: number): Promise<ArrayBuffer> {
+    return this._js.head(count);
+  }
+  async readInto(buffer: Uint8Array, offset: bigint, length?: number): Promise<number> {
+    return this._js.readInto(buffer, offset, length);
+  }
+  async waitForSize(minSize: bigint, options?: SmbWaitForSizeOptions): Promise<bigint> {
+    return this._js.waitForSize(minSize, options);
+  }
+  // @ts-ignore
+  async *lines(options?: SmbLinesOptions): AsyncIterableIterator<string> {
+    for await (const line of this._js.lines(options)) {
+      yield line;
+    }
+  }
+  async *tail(options?: SmbTailOptions): AsyncIterableIterator<string | Uint8Array> {
+    const tail = this._js.tail(options);
+    try {
+      for (let chunk = await tail.next(); chunk !== null; chunk = await tail.next()) {
+        yield chunk;
+      }
+    } finally {
+      tail.close();
+    }
+  }
+  async rename(newName: string, options?: SmbRenameOptions): Promise<void> {
+    await this._js.rename(newName, options);
+    this.renamed(this._js.toHandle());
+  }
+  async transferTo(destDir: FileSystemDirectoryHandle, newName?: string, options?: SmbTransferOptions): Promise<SmbTransferResult> {
+    return this._js.transferTo((destDir as any)._js || destDir, newName, options);
+  }
+  async setTimes(times: SmbSetTimesOptions): Promise<void> {
+    return this._js.setTimes(times);
+  }
+  async replaceContents(data: string | Uint8Array): Promise<void> {
+    return this._js.replaceContents(data);
+  }
+  async preallocate(size: bigint): Promise<void> {
+    return this._js.preallocate(size);
+  }
+  async openAppend(): Promise<SmbAppendHandle> {
+    return this._js.openAppend();
+  }
+  async hash(algorithm: 'crc32' | 'sha256', options?: SmbHashOptions): Promise<string> {
+    return this._js.hash(algorithm, options);
+  }
+  async resumableWrite(): Promise<SmbResumableWriteHandle> {
+    return this._js.resumableWrite();
+  }
+  async fileId(): Promise<string> {
+    return this._js.fileId();
+  }
+  async getAcl(): Promise<SmbAcl> {
+    return this._js.getAcl();
+  }
+  async setAcl(acl: SmbAcl): Promise<void> {
+    return this._js.setAcl(acl);
+  }
   async createWritable(options?: SmbCreateWritableOptions): Promise<FileSystemWritableFileStream> {
     return new Promise(async (resolve, reject) => {
       await this._js.createWritable(options as JsSmbCreateWritableOptions)
@@ -208,6 +468,9 @@ export class SmbFileHandle extends SmbHandle implements FileSystemFileHandle {
         });
     });
   }
+  async writeFromStream(stream: ReadableStream, options?: SmbWriteFromStreamOptions): Promise<void> {
+    return this._js.writeFromStream(stream, options);
+  }
 }
 
 interface SmbWritableFileStreamLock { locked: boolean }
@@ -218,7 +481,7 @@ export class SmbWritableFileStream implements SmbWritableFileStreamLock {
     this._js = _js;
     this.locked = _js.locked;
   }
-  async write(data: ArrayBuffer | TypedArray | DataView | Blob | String | string | {type: 'write' | 'seek' | 'truncate', data?: ArrayBuffer | TypedArray | DataView | Blob | String | string, position?: number, size?: number}): Promise<void> {
+  async write(data: ArrayBuffer | TypedArray | DataView | Blob | String | string | {type: 'write' | 'seek' | 'truncate', data?: ArrayBuffer | TypedArray | DataView | Blob | String | string, position?: number, size?: number, whence?: 'start' | 'current' | 'end'}): Promise<void> {
     return new Promise(async (resolve, reject) => {
       if (data instanceof Blob) {
         data = await data.arrayBuffer();
@@ -244,9 +507,15 @@ export class SmbWritableFileStream implements SmbWritableFileStreamLock {
   async truncate(size: number): Promise<void> {
     return this._js.truncate(size);
   }
+  async barrier(): Promise<void> {
+    return this._js.barrier();
+  }
   async close(): Promise<void> {
     return this._js.close();
   }
+  async finish(): Promise<SmbWriteSummary> {
+    return this._js.finish();
+  }
   async abort(reason: string): Promise<void> {
     return new Promise(async (resolve, reject) => {
       await this._js.abort(reason)
@@ -270,3 +539,23 @@ export class SmbWritableFileStream implements SmbWritableFileStreamLock {
 interface WritableStreamDefaultWriterEx extends WritableStreamDefaultWriter {
   _releaseLock: () => void
 }
+
+export function setMaxConcurrentOperations(max: number): void {
+  jsSetMaxConcurrentOperations(max);
+}
+
+export function setDebugLogger(callback: ((event: SmbDebugEvent) => void) | null): void {
+  jsSetDebugLogger(callback);
+}
+
+export function toUncPath(handle: FileSystemHandle): string {
+  return jsToUncPath((handle as any)._jsh || handle);
+}
+
+export function fromUncPath(unc: string): string {
+  return jsFromUncPath(unc);
+}
+
+function connect(url: string, options?: SmbConnectOptions): JsSmbDirectoryHandle {
+  return new JsSmbDirectoryHandle(url, options);
+}
@@ -286,13 +286,22 @@ export const {
   JsSmbDirectoryHandleEntries,
   JsSmbDirectoryHandleKeys,
   JsSmbDirectoryHandleValues,
+  JsSmbDirectoryHandleDirectories,
+  JsSmbDirectoryHandleFiles,
   JsSmbHandle,
   JsSmbDirectoryHandle,
   Cancellable,
   JsSmbFileHandle,
+  JsSmbFileTail,
+  JsSmbAppendHandle,
+  JsSmbResumableWriteHandle,
   JsSmbFile,
   JsSmbReadableStreamSource,
   JsSmbWritableFileStream,
   JsSmbWritableStreamSink,
+  setMaxConcurrentOperations,
+  setDebugLogger,
+  toUncPath,
+  fromUncPath,
 } = nativeBinding
 
@@ -6,30 +6,476 @@
 export interface JsSmbHandlePermissionDescriptor {
   mode: 'read' | 'readwrite'
 }
+/**
+ * Options for the streaming `entries()`, `keys()` and `values()` iterators.
+ *
+ * `prefetch` bounds how many entries are read ahead of the consumer (default 64).
+ * The listing is queried from the server a page of up to 64 KiB at a time, as entries are read,
+ * so a page is fetched only once the entries of the one before have all been read ahead.
+ *
+ * `pattern` only lists entries with matching names, `*` standing for any run of characters and `?`
+ * for any one, ignoring case. It is passed on to the server, which then sends only those entries.
+ */
+export interface JsSmbEntriesOptions {
+  prefetch?: number
+  pattern?: string
+}
+/**
+ * Options for `list()`. `kind` only lists entries of that kind, and `sort` orders the entries
+ * (by ascending name, size or modification time) rather than leaving them in the server's order.
+ */
+export interface JsSmbListOptions {
+  kind?: 'file' | 'directory'
+  sort?: 'name' | 'size' | 'modifiedTime'
+}
+/**
+ * Criteria for `find()`, entries having to meet all those given. `namePattern` is a wildcard pattern
+ * (`*` and `?`) matched against names ignoring case, sizes are in bytes and inclusive, and
+ * `modifiedAfter` is in nanoseconds since the Unix epoch.
+ */
+export interface JsSmbFindCriteria {
+  namePattern?: string
+  minSize?: number
+  maxSize?: number
+  modifiedAfter?: number
+  kind?: 'file' | 'directory'
+}
+/**
+ * `symlinks` is as for `JsSmbRemoveOptions`: 'skip' (default) doesn't search symlinked directories,
+ * 'follow' searches them, within the share, and 'error' fails the search at the first symlink.
+ */
+export interface JsSmbFindOptions {
+  symlinks?: 'skip' | 'follow' | 'error'
+}
+/**
+ * Options for `snapshot()`. `maxDepth` is how many levels of directories to list (default all of them),
+ * `1` listing only the entries of the directory itself. `includeStats` (default true) gives each node
+ * its `size` and `mtime`. `symlinks` is as for `JsSmbFindOptions`.
+ */
+export interface JsSmbSnapshotOptions {
+  maxDepth?: number
+  includeStats?: boolean
+  symlinks?: 'skip' | 'follow' | 'error'
+}
+/**
+ * A node of the tree returned by `snapshot()`, made of plain values so that it can be passed to
+ * `JSON.stringify()` as is. `size` is in bytes and `mtime` in milliseconds since the Unix epoch.
+ * Only directories listed have `children`, those beyond `maxDepth` or symlinks left out having none.
+ */
+export interface JsSmbTreeNode {
+  name: string
+  kind: 'file' | 'directory'
+  size?: number
+  mtime?: number
+  children?: Array<JsSmbTreeNode>
+}
+export interface JsSmbListEntry {
+  name: string
+  kind: 'file' | 'directory'
+  stat: JsSmbStat
+}
 export interface JsSmbGetDirectoryOptions {
   create: boolean
 }
+/**
+ * Desired access when opening a file. Maps to the open flags passed to libsmb2, which in turn
+ * picks the SMB desired access mask:
+ *
+ * - `read` - `O_RDONLY` (FILE_READ_DATA | FILE_READ_EA | FILE_READ_ATTRIBUTES)
+ * - `write` - `O_WRONLY` (FILE_WRITE_DATA | FILE_WRITE_EA | FILE_WRITE_ATTRIBUTES)
+ * - `readwrite` - `O_RDWR` (union of the above)
+ * - `readAttributes` - no data access, the file is only stat'ed (created read-only if missing)
+ *
+ * `attributes` are sent with the create request, so a new file is never visible without them.
+ * They are ignored when the file already exists.
+ *
+ * `readOnly` makes the handle refuse to write the file's contents, `createWritable()` and the other
+ * writing methods throwing right away rather than the server failing the first write. It can't be
+ * combined with `write` or `readwrite` access.
+ */
 export interface JsSmbGetFileOptions {
   create: boolean
+  access?: 'read' | 'write' | 'readwrite' | 'readAttributes'
+  attributes?: JsSmbCreateAttributes
+  readOnly?: boolean
+}
+/** DOS attributes to give a file when creating it. */
+export interface JsSmbCreateAttributes {
+  hidden?: boolean
+  readOnly?: boolean
+  system?: boolean
+}
+/**
+ * `overwrite` replaces an existing entry of the new name, which is only done for files; without it
+ * (the default) the rename fails with `ALREADY_EXISTS` if the name is taken.
+ */
+export interface JsSmbRenameOptions {
+  overwrite?: boolean
 }
 export interface JsSmbRemoveOptions {
   recursive: boolean
+  /**
+   * What a recursive removal does with symlinks: 'skip' (default) removes the link itself,
+   * 'follow' also removes the contents of symlinked directories, 'error' fails the removal.
+   */
+  symlinks?: 'skip' | 'follow' | 'error'
+}
+export interface JsSmbResolveOptions {
+  /** Whether resolve() descends into symlinked directories: 'skip' (default), 'follow' or 'error'. */
+  symlinks?: 'skip' | 'follow' | 'error'
+  /** Levels of the tree searched, entries of this directory being level 1 (default 64). */
+  maxDepth?: number
+}
+export interface JsSmbFindByFileIdOptions {
+  /** Levels of the tree searched, entries of this directory being level 1 (default 64). */
+  maxDepth?: number
+}
+export interface JsSmbRemoveEntriesFailure {
+  name: string
+  error: string
+}
+export interface JsSmbRemoveEntriesResult {
+  removed: Array<string>
+  failed: Array<JsSmbRemoveEntriesFailure>
+}
+/**
+ * `onConflict` says what happens to a file whose destination is taken, the same for every API that
+ * copies or moves files: 'overwrite' replaces it, 'skip' leaves it and goes on, 'fail' (default) fails
+ * the file, and 'rename' gives the new file the first free name with a numeric suffix, `name (1).ext`
+ * and so on. `overwrite: true` is a deprecated spelling of 'overwrite'.
+ * `symlinks` is as for `JsSmbRemoveOptions`: 'skip' (default) leaves symlinks out of the copy,
+ * 'follow' copies what they point to, within the share, and 'error' stops the copy at the first one.
+ * `preservePermissions` gives each file copied the POSIX mode bits of its source, failing the file, and
+ * removing its copy, if the destination can't take them. Setting mode bits takes the SMB3 POSIX
+ * extensions, which libsmb2 doesn't negotiate, so against real servers every file fails with NOT_SUPPORTED.
+ */
+export interface JsSmbCopyTreeOptions {
+  onConflict?: 'overwrite' | 'skip' | 'fail' | 'rename'
+  overwrite?: boolean
+  symlinks?: 'skip' | 'follow' | 'error'
+  preservePermissions?: boolean
+}
+/** Sent to the `copyTree()` progress callback after each file copied. */
+export interface JsSmbCopyTreeProgress {
+  path: string
+  filesCopied: number
+  bytesCopied: number
+}
+export interface JsSmbCopyTreeFailure {
+  path: string
+  error: string
+}
+export interface JsSmbCopyTreeResult {
+  directoriesCreated: number
+  filesCopied: number
+  bytesCopied: number
+  failed: Array<JsSmbCopyTreeFailure>
+}
+/**
+ * Outcome of `transferTo()`: `strategy` tells whether the file was renamed on the server (`rename`),
+ * copied by the server (`serverCopy`) or through this client (`copy`) and then removed, in which case
+ * `bytesCopied` is its size, or left where it was for its destination being taken (`skipped`). `name`
+ * is the name it was given in `destDir`, or the name that was taken if skipped.
+ */
+export interface JsSmbTransferResult {
+  strategy: 'rename' | 'serverCopy' | 'copy' | 'skipped'
+  bytesCopied: number
+  name: string
+}
+/**
+ * An operation in flight on a connection, as listed by `activeOperations()`. `kind` names the method
+ * that started it, and `startedAt` is in nanoseconds since the epoch.
+ */
+export interface JsSmbActiveOperation {
+  id: number
+  kind: string
+  path: string
+  startedAt: number
+  cancelled: boolean
+}
+export interface JsSmbUploadFile {
+  name: string
+  data: string | Uint8Array
+}
+/**
+ * `onConflict` is as for `JsSmbCopyTreeOptions`, applied when the batch is staged: a file that fails
+ * rolls back the whole batch, while skipped files are left out of it. `overwrite` is a deprecated
+ * spelling of 'overwrite' when true and 'fail' when false.
+ */
+export interface JsSmbUploadBatchOptions {
+  onConflict?: 'overwrite' | 'skip' | 'fail' | 'rename'
+  overwrite?: boolean
+}
+/**
+ * `onConflict` and `preservePermissions` are as for `JsSmbCopyTreeOptions`, the latter only mattering
+ * when the file is copied, as a rename keeps its mode bits anyway.
+ */
+export interface JsSmbTransferOptions {
+  onConflict?: 'overwrite' | 'skip' | 'fail' | 'rename'
+  preservePermissions?: boolean
+}
+/**
+ * `committedName` is the name the file was committed under, which differs from `name` when the
+ * 'rename' conflict policy had to pick another.
+ */
+export interface JsSmbUploadFileResult {
+  name: string
+  status: 'committed' | 'failed' | 'rolledBack' | 'skipped'
+  error?: string
+  committedName?: string
+}
+export interface JsSmbUploadBatchResult {
+  committed: boolean
+  files: Array<JsSmbUploadFileResult>
 }
+/**
+ * `access` defaults to `readwrite`; `write` opens the file write-only (see `JsSmbGetFileOptions` for the mapping).
+ *
+ * `verify` reads each write back from the server and compares it with what was written, failing the
+ * write with `VERIFY_MISMATCH` if they differ. This doubles the traffic of every write, plus an extra
+ * open per write for `write` access.
+ *
+ * Strings are written in `encoding`, `utf-8` (default), `latin1` or `utf-16le`, with `newline: 'crlf'`
+ * turning each `\n` not already preceded by `\r` into `\r\n`. Binary data is written as is.
+ */
 export interface JsSmbCreateWritableOptions {
   keepExistingData: boolean
+  access?: 'write' | 'readwrite'
+  verify?: boolean
+  encoding?: 'utf-8' | 'utf8' | 'latin1' | 'utf-16le'
+  newline?: 'lf' | 'crlf'
 }
+/**
+ * `highWaterMark` is the number of chunks read ahead of the write in progress (default 1).
+ * Unless `keepExistingData` is set, the file is truncated before the first chunk is written.
+ */
+export interface JsSmbWriteFromStreamOptions {
+  keepExistingData?: boolean
+  highWaterMark?: number
+}
+/**
+ * `encoding` is `utf-8` (default) or `latin1`; invalid UTF-8 sequences are replaced.
+ * `delimiter` defaults to `\n` and is not included in the yielded lines.
+ */
+export interface JsSmbLinesOptions {
+  encoding?: 'utf-8' | 'utf8' | 'latin1'
+  delimiter?: string
+}
+/**
+ * `fromStart` reads the file from its start rather than from its end, `intervalMs` is the time between
+ * checks for growth (default 1000), and a `delimiter` has lines yielded, without it, instead of chunks.
+ */
+export interface JsSmbTailOptions {
+  fromStart?: boolean
+  intervalMs?: number
+  delimiter?: string
+}
+/** `intervalMs` is the time between stats (default 250), `timeoutMs` how long to wait in all (default 60000). */
+export interface JsSmbWaitForSizeOptions {
+  intervalMs?: number
+  timeoutMs?: number
+}
+/**
+ * `mode` holds the POSIX permission bits, without the file type bits. Servers without the SMB3 POSIX
+ * extensions have them synthesized from the DOS attributes: 0o644 for files and 0o755 for directories,
+ * without the write bits if read-only.
+ */
 export interface JsSmbStat {
   readonly inode?: bigint
   readonly size: bigint
   readonly creationTime: bigint
   readonly modifiedTime: bigint
   readonly accessedTime: bigint
+  readonly isSymlink: boolean
+  readonly isDirectory: boolean
+  readonly isFile: boolean
+  readonly mode: number
+}
+/**
+ * DOS attributes of an entry. On cloud-tiered shares, `offline`, `recallOnOpen` and `recallOnDataAccess`
+ * mark entries whose data is not stored locally on the server, reading (or with `recallOnOpen`, opening)
+ * them triggering a potentially slow and costly recall.
+ */
+export interface JsSmbAttributes {
+  readOnly: boolean
+  hidden: boolean
+  system: boolean
+  archive: boolean
+  offline: boolean
+  recallOnOpen: boolean
+  recallOnDataAccess: boolean
+}
+/**
+ * Options applied to the whole connection.
+ *
+ * `defaultFileMode` (default `0o666`) and `defaultDirMode` (default `0o775`) are the modes requested
+ * when creating files and directories. SMB has no notion of POSIX modes, so only the write bits
+ * count: a mode without any creates the entry read-only. Otherwise the share's settings apply.
+ *
+ * `debug` makes every operation on the connection emit a debug event, see `setDebugLogger()`.
+ *
+ * `signing` (default `'enabled'`) controls SMB message signing: `'required'` refuses sessions
+ * the server will not sign, `'disabled'` only signs when the server insists on it.
+ *
+ * `workstationName` is the client machine name the server sees in NTLM authentication, it is
+ * not sent when authenticating with Kerberos. `clientGuid`, as `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`,
+ * replaces the random client GUID sent when negotiating, which servers use to recognize a client
+ * across connections. Neither is a NetBIOS name: libsmb2 connects over direct TCP only.
+ *
+ * `urlArgs` are added to the query of the URL, replacing arguments of the same name already there,
+ * for libsmb2 URL arguments without an option of their own (e.g. `{'tcp-syncnt': '3'}`).
+ * Names and values must be non-empty and made of unreserved URL characters.
+ *
+ * `uid` and `gid` are the user and group ids sent to servers with the POSIX extensions, which
+ * own the files and directories created. They default to those of the process (65534 on Windows),
+ * so a service running as root can pass them to create files owned by another user.
+ *
+ * `tcpNoDelay` sets `TCP_NODELAY` on the socket once connected, sending small requests right away
+ * rather than waiting on Nagle's algorithm, and `tcpKeepAlive` sets `SO_KEEPALIVE`, so that a dead
+ * peer on an idle connection is noticed. Both are left to the system when not given. They work on
+ * Linux and macOS alike, where the system settings decide how often keepalive probes are sent
+ * (`net.ipv4.tcp_keepalive_time` on Linux, `net.inet.tcp.keepidle` on macOS).
+ */
+export interface JsSmbConnectOptions {
+  defaultFileMode?: number
+  defaultDirMode?: number
+  debug?: boolean
+  signing?: 'required' | 'enabled' | 'disabled'
+  workstationName?: string
+  clientGuid?: string
+  urlArgs?: Record<string, string>
+  uid?: number
+  gid?: number
+  tcpNoDelay?: boolean
+  tcpKeepAlive?: boolean
+}
+/** Snapshot of the connection state. `signing` is the mode requested when connecting, see `JsSmbConnectOptions`. */
+export interface JsSmbConnectionInfo {
+  connected: boolean
+  signing: 'required' | 'enabled' | 'disabled'
+}
+/**
+ * What the connection supports, for callers to use features only where they work.
+ * libsmb2 keeps the server's negotiate reply to itself, so each is told by what it negotiates:
+ * - `encryption`: messages are encrypted, which holds when connecting with the `seal` URL argument,
+ *   as libsmb2 then fails to connect to servers that don't encrypt.
+ * - `dfs`: DFS referrals are followed, which libsmb2 doesn't do.
+ *
+ * The SMB3 POSIX extensions (mode bits, chmod) aren't reported: they need a negotiate context libsmb2
+ * doesn't send, so no server enables them. Nor are leases: libsmb2 can't answer a lease break, so
+ * they aren't requested, and files are opened without any caching granted.
+ */
+export interface JsSmbCapabilities {
+  encryption: boolean
+  dfs: boolean
+}
+/** Traffic on the connection since it was made or `metrics({ reset: true })` was last called. */
+export interface JsSmbMetrics {
+  bytesRead: number
+  bytesWritten: number
+  statOps: number
+  openOps: number
+  readdirOps: number
+}
+export interface JsSmbWatchOptions {
+  multiple?: boolean
+  debounceMs?: number
+  mode?: 'notify' | 'poll'
+  intervalMs?: number
+}
+export interface JsSmbMetricsOptions {
+  reset?: boolean
+}
+/** Outcome of `finish()`: bytes written through the stream and the file size once flushed. */
+export interface JsSmbWriteSummary {
+  bytesWritten: number
+  size: bigint
+}
+/**
+ * Times in nanoseconds since the Unix epoch, those left out being unchanged.
+ * `creationTime` is stored by SMB servers as the file's birth time; servers not tracking it ignore it.
+ */
+export interface JsSmbSetTimesOptions {
+  accessedTime?: number
+  modifiedTime?: number
+  creationTime?: number
 }
+/** Byte range to hash, `offset` defaulting to the start of the file and `length` to the rest of it. */
+export interface JsSmbHashOptions {
+  offset?: number
+  length?: number
+}
+/**
+ * Shape of the errors operations fail with, thrown as `SmbError`s once `indax.ts` registered that class
+ * with `setSmbErrorClass()`, and as plain errors with these properties until then.
+ * `ntStatus` is the NT status the server returned, or the one matching `code` if the failure was detected locally.
+ */
+export interface JsSmbError {
+  code: 'NOT_FOUND' | 'ALREADY_EXISTS' | 'ACCESS_DENIED' | 'DISK_FULL' | 'TIMEOUT' | 'NOT_EMPTY' | 'TYPE_MISMATCH' | 'INVALID_ARGUMENT' | 'VERIFY_MISMATCH' | 'CANCELLED' | 'NOT_SUPPORTED' | 'UNKNOWN'
+  ntStatus: number
+  path: string
+  message: string
+}
+/**
+ * Registers the class errors are thrown as, which `indax.ts` does once loaded. Its constructor
+ * takes the message, code, NT status and path, see `JsSmbError`.
+ */
+export function setSmbErrorClass(errorClass: new (message: string, code: JsSmbError['code'], ntStatus: number, path: string) => Error): void
+export interface JsSmbAce {
+  sid: string
+  type: 'allowed' | 'denied' | 'audit' | 'alarm'
+  flags: number
+  mask: number
+}
+/**
+ * Owner, group and DACL entries of a file's NT security descriptor.
+ * Only allow, deny, audit and alarm ACEs are listed; other ACE types are skipped.
+ */
+export interface JsSmbAcl {
+  owner?: string
+  group?: string
+  aces: Array<JsSmbAce>
+}
+/**
+ * `kind` tells a file from a directory where the notification shows which it is, which on SMB servers
+ * is for entries created, removed or renamed, but not for modified ones.
+ */
 export interface JsSmbNotifyChange {
   path: string
   action: string
   fromPath?: string
+  kind?: 'file' | 'directory'
+}
+/**
+ * Caps how many operations run blocking SMB calls at once, 0 (the default) lifting the cap.
+ *
+ * Operations over the cap queue up in order without holding any thread, neither of libuv's thread pool
+ * nor of the runtime's blocking threads, and start as running ones finish. Lowering the cap doesn't
+ * interrupt operations already running over it.
+ */
+export function setMaxConcurrentOperations(max: number): void
+/**
+ * Debug event passed to the callback given to `setDebugLogger()`. Events traced for connections
+ * made with `debug` set carry the operation, its path, how long it took and its result.
+ */
+export interface JsSmbDebugEvent {
+  level: string
+  target: string
+  message: string
+  op?: string
+  path?: string
+  durationUs?: number
+  result?: string
 }
+/**
+ * Routes debug events to `callback`, or stops routing them if it is null.
+ * Besides traced operations this includes diagnostics from the underlying SMB client.
+ */
+export function setDebugLogger(callback: ((event: JsSmbDebugEvent) => void) | null): void
+/** Formats the location of a handle as a UNC path, e.g. `\\server\share\dir\file`. */
+export function toUncPath(handle: JsSmbHandle): string
+/** Converts a UNC path, e.g. `\\server\share\dir\file`, into an smb:// URL. */
+export function fromUncPath(unc: string): string
 export declare class JsSmbDirectoryHandleEntries {
   [Symbol.asyncIterator]: AsyncIterableIterator<[string, JsSmbDirectoryHandle | JsSmbFileHandle]>
 }
@@ -39,31 +485,175 @@ export declare class JsSmbDirectoryHandleKeys {
 export declare class JsSmbDirectoryHandleValues {
   [Symbol.asyncIterator]: AsyncIterableIterator<JsSmbDirectoryHandle | JsSmbFileHandle>
 }
+export declare class JsSmbDirectoryHandleDirectories {
+  [Symbol.asyncIterator]: AsyncIterableIterator<JsSmbDirectoryHandle>
+}
+export declare class JsSmbDirectoryHandleFiles {
+  [Symbol.asyncIterator]: AsyncIterableIterator<JsSmbFileHandle>
+}
 export declare class JsSmbHandle {
   readonly kind: 'directory' | 'file'
   readonly name: string
   isSameEntry(other: JsSmbHandle): boolean
+  isConnected(): boolean
+  connectionInfo(): JsSmbConnectionInfo
   queryPermission(perm: JsSmbHandlePermissionDescriptor): Promise<string>
   requestPermission(perm: JsSmbHandlePermissionDescriptor): Promise<string>
   stat(): Promise<JsSmbStat>
+  /** Same as `stat()`, but describes a symlink itself rather than the entry it points to. */
+  lstat(): Promise<JsSmbStat>
+  /** Reads the entry's DOS attributes without recalling its data from cloud tiering. */
+  attributes(): Promise<JsSmbAttributes>
+  /**
+   * Whether the entry is a reparse point, going by its FILE_ATTRIBUTE_REPARSE_POINT (0x400) DOS attribute.
+   * Besides symlinks this covers junctions, mount points and other reparse tags, which tree walkers
+   * may want to stay out of so as not to cross into another filesystem.
+   */
+  isReparsePoint(): Promise<boolean>
+  getParent(): Promise<JsSmbDirectoryHandle | null>
 }
 export declare class JsSmbDirectoryHandle {
   [Symbol.asyncIterator]: JsSmbDirectoryHandle['entries']
   readonly kind: 'directory'
   readonly name: string
-  constructor(url: string)
+  constructor(url: string, options?: JsSmbConnectOptions)
   toHandle(): JsSmbHandle
   isSameEntry(other: JsSmbHandle): boolean
+  isConnected(): boolean
+  connectionInfo(): JsSmbConnectionInfo
+  /** Counters are kept per connection, so they include traffic from every handle sharing it. */
+  metrics(options?: JsSmbMetricsOptions): JsSmbMetrics
+  /** What the connection supports, see `JsSmbCapabilities`. Nothing is sent to the server. */
+  capabilities(): JsSmbCapabilities
+  /**
+   * Renames the directory within its parent directory, updating `name` once done.
+   * An existing entry of the new name is only replaced with `overwrite`, see `JsSmbRenameOptions`.
+   */
+  rename(newName: string, options?: JsSmbRenameOptions): Promise<void>
+  /**
+   * Connects to another share on the same server, with the same credentials and connect options,
+   * returning its root directory. SMB ties tree connects to sessions, so this makes a connection
+   * (and session) of its own, shut down independently of this one.
+   */
+  withShare(shareName: string): Promise<JsSmbDirectoryHandle>
+  /**
+   * Shuts the connection down for good: operations on any handle sharing it fail from now on,
+   * its watchers are cancelled, and once the async operations underway have finished it is released.
+   */
+  shutdown(): Promise<void>
+  /** Lists the async operations in flight on the connection, from any handle sharing it. */
+  activeOperations(): Array<JsSmbActiveOperation>
+  /**
+   * Asks the operation with `id` to stop, returning false if it is not in flight (or already asked).
+   * Cancellation is best effort: the operation stops at its next chunk or entry boundary and fails
+   * with code CANCELLED, but a call already sent to the server runs to completion, and an operation
+   * made of a single call is never interrupted.
+   */
+  cancelOperation(id: number): boolean
   queryPermission(perm: JsSmbHandlePermissionDescriptor): Promise<string>
   requestPermission(perm: JsSmbHandlePermissionDescriptor): Promise<string>
-  entries(): AsyncIterableIterator<[string, JsSmbDirectoryHandle | JsSmbFileHandle]>
-  keys(): AsyncIterableIterator<string>
-  values(): AsyncIterableIterator<JsSmbDirectoryHandle | JsSmbFileHandle>
+  getParent(): Promise<JsSmbDirectoryHandle | null>
+  entries(options?: JsSmbEntriesOptions): AsyncIterableIterator<[string, JsSmbDirectoryHandle | JsSmbFileHandle]>
+  keys(options?: JsSmbEntriesOptions): AsyncIterableIterator<string>
+  values(options?: JsSmbEntriesOptions): AsyncIterableIterator<JsSmbDirectoryHandle | JsSmbFileHandle>
+  /** Like `values()` but yields only subdirectories, skipping other entries before any handle is made for them. */
+  directories(options?: JsSmbEntriesOptions): AsyncIterableIterator<JsSmbDirectoryHandle>
+  /** Like `values()` but yields only files, skipping other entries before any handle is made for them. */
+  files(options?: JsSmbEntriesOptions): AsyncIterableIterator<JsSmbFileHandle>
+  /**
+   * Lists the whole directory at once, each entry along with its stat. Stats come from the directory
+   * listing itself, so this takes no more round trips than iterating over the directory.
+   */
+  list(options?: JsSmbListOptions): Promise<JsSmbListEntry[]>
+  /**
+   * Lists the entries meeting `criteria`, each along with its stat. The criteria are checked against the
+   * stats of the directory listing as it is read, so only the matching entries are passed to JS.
+   */
+  find(criteria: JsSmbFindCriteria): Promise<JsSmbListEntry[]>
+  /**
+   * Like `find()` but searches subdirectories too, the `name` of each entry found being its path
+   * relative to this directory.
+   */
+  findRecursive(criteria: JsSmbFindCriteria, options?: JsSmbFindOptions): Promise<JsSmbListEntry[]>
+  /**
+   * Lists the whole tree below this directory in one go, as nested nodes sorted by name, e.g. to keep
+   * a manifest of a share or compare it with an earlier one. Stats come from the directory listings.
+   * The tree is built in memory as a whole before being passed to JS, so for trees too large for
+   * that walk the directories with `entries()` instead, which streams them.
+   */
+  snapshot(options?: JsSmbSnapshotOptions): Promise<JsSmbTreeNode>
+  /**
+   * Stats the entries `names` of this directory, keyed by name, with null for those not found.
+   * The requests are all sent before any reply is awaited, so a UI prefetching a few known entries
+   * waits about one round trip rather than one per entry. Other failures reject the whole call.
+   */
+  statMany(names: Array<string>): Promise<Record<string, JsSmbStat | null>>
   getDirectoryHandle(name: string, options?: JsSmbGetDirectoryOptions): Promise<JsSmbDirectoryHandle>
   getFileHandle(name: string, options?: JsSmbGetFileOptions): Promise<JsSmbFileHandle>
+  touch(name: string): Promise<JsSmbFileHandle>
+  /**
+   * Flushes the directory so that entries created, removed or renamed in it survive a server crash.
+   * Resolves without doing anything on servers that do not support flushing directories.
+   */
+  sync(): Promise<void>
+  /**
+   * Creates the file exclusively, or opens it if it already exists, reporting which happened.
+   * Handy for lock files, as only one of several concurrent callers sees `created: true`.
+   */
+  getOrCreateFile(name: string): Promise<{ handle: JsSmbFileHandle, created: boolean }>
+  /**
+   * Searches this directory and those below it, breadth-first and to at most `maxDepth` levels, for the
+   * file with an id from `fileId()`, whatever it is named now. libsmb2 cannot send a file id in place
+   * of a name, so this lists directories rather than opening by id, which takes a while on large trees.
+   * Subdirectories that cannot be listed are skipped, and the connection is only held while listing
+   * each directory. Servers that report no file ids (see `JsSmbStat.inode`) never match.
+   */
+  findByFileId(id: string, options?: JsSmbFindByFileIdOptions): Promise<JsSmbFileHandle>
   removeEntry(name: string, options?: JsSmbRemoveOptions): Promise<void>
-  resolve(possibleDescendant: JsSmbHandle): Promise<Array<string> | null>
-  watch(callback: (...args: any[]) => any): Cancellable
+  removeEntries(names: Array<string>, options?: JsSmbRemoveOptions): Promise<JsSmbRemoveEntriesResult>
+  /**
+   * Uploads all files or none: each file is written and fsynced under a temporary name, and only once
+   * all are staged are they renamed into place. This is all-or-nothing staging, not a filesystem
+   * transaction: should a rename fail, the files renamed before it stay committed.
+   */
+  uploadBatch(files: Array<JsSmbUploadFile>, options?: JsSmbUploadBatchOptions): Promise<JsSmbUploadBatchResult>
+  /**
+   * Copies the contents of this directory into `dest`, which may be on another connection or share.
+   * When both are on the same server, even on different shares, each file is first copied by the
+   * server itself (server-side copy), and only read and written through this client if it refuses.
+   * Failures to copy a file or create a directory are reported in `failed` without stopping the copy;
+   * `progress` is called with a `JsSmbCopyTreeProgress` after each file copied.
+   */
+  copyTree(dest: JsSmbDirectoryHandle, options?: JsSmbCopyTreeOptions, progress?: (progress: JsSmbCopyTreeProgress) => void): Promise<JsSmbCopyTreeResult>
+  /**
+   * Searches at most `maxDepth` levels deep, so that a deep or cyclic tree resolves to null
+   * rather than being searched without bound. A handle obtained from this connection is resolved
+   * by comparing paths, without listing anything; the tree is only searched for handles whose
+   * path is unknown, like plain `{kind, name}` objects.
+   */
+  resolve(possibleDescendant: JsSmbHandle, options?: JsSmbResolveOptions): Promise<Array<string> | null>
+  /**
+   * Current time on the server in nanoseconds since the Unix epoch, for telling how far its clock is
+   * from the local one before comparing modification times. libsmb2 does not expose the time the server
+   * sends when negotiating, so it is read from a probe file created and removed in this directory,
+   * which takes write access and a few round trips.
+   *
+   * That is a write to the share: the probe, named `.smbjs-server-time-<pid>-<nanoseconds>`, bumps the
+   * directory's modification time, shows up to watchers of the directory as an added and a removed
+   * file, and is left behind if removing it fails, which this then fails with.
+   */
+  serverTime(): Promise<bigint>
+  /**
+   * Watching an already watched handle fails, as a second watcher would deliver every change twice
+   * on a connection of its own. Pass `multiple: true` to start another watcher regardless.
+   * With `debounceMs`, changes are held back until none has arrived for that long, and the callback
+   * then gets an array with the latest change for each path instead of one change per call.
+   * With `mode: 'poll'`, or once the server rejects change notify requests, the tree is listed every
+   * `intervalMs` (default 1000) instead, and the differences between listings are reported as
+   * changes: renames then show as a removal and a creation, and writes only when size or mtime changed.
+   * Fails if the directory can't be watched at all, as when it no longer exists.
+   */
+  watch(callback: (change: JsSmbNotifyChange | JsSmbNotifyChange[]) => void, options?: JsSmbWatchOptions): Cancellable
 }
 export declare class Cancellable {
   wait(): Promise<void>
@@ -74,11 +664,133 @@ export declare class JsSmbFileHandle {
   readonly name: string
   toHandle(): JsSmbHandle
   isSameEntry(other: JsSmbHandle): boolean
+  isConnected(): boolean
+  connectionInfo(): JsSmbConnectionInfo
   queryPermission(perm: JsSmbHandlePermissionDescriptor): Promise<string>
   requestPermission(perm: JsSmbHandlePermissionDescriptor): Promise<string>
+  getParent(): Promise<JsSmbDirectoryHandle | null>
   getFile(): Promise<File>
+  head(count: number): Promise<ArrayBuffer>
+  /**
+   * Reads up to `length` bytes (default the whole buffer) from `offset` in the file straight into
+   * `buffer`, like Node's `fileHandle.read()`, resolving to the number of bytes read, which is less
+   * than asked only at end-of-file. The buffer must not be transferred while the read is underway.
+   */
+  readInto(buffer: Uint8Array, offset: bigint, length?: number): Promise<number>
+  /**
+   * Waits for the file to grow to at least `minSize` bytes, as it's being written elsewhere, resolving to
+   * the size last seen, which is below `minSize` if `timeoutMs` elapsed first. The size is polled with a
+   * stat every `intervalMs`, so growth is noticed up to that late. Waiting holds no thread; `cancelOperation()`
+   * and `shutdown()` fail it with `ERROR_CANCELLED` at once.
+   */
+  waitForSize(minSize: bigint, options?: JsSmbWaitForSizeOptions): Promise<bigint>
+  lines(options?: JsSmbLinesOptions): AsyncIterableIterator<string>
+  /**
+   * Follows the file as it grows, like `tail -f`: each `next()` resolves to the bytes appended since the
+   * last one, or to the next line when there is a `delimiter`, a trailing line being held back until it
+   * is delimited. The size is checked every `intervalMs`; a file that shrinks is followed from its start.
+   * `next()` resolves to null once `close()` is called. Waiting holds no thread and doesn't hold up `shutdown()`.
+   */
+  tail(options?: JsSmbTailOptions): JsSmbFileTail
+  /**
+   * Renames the file within its directory, updating `name` once done.
+   * An existing entry of the new name is only replaced with `overwrite`, see `JsSmbRenameOptions`.
+   */
+  rename(newName: string, options?: JsSmbRenameOptions): Promise<void>
+  /**
+   * Moves the file into `destDir` as `newName` (default its name), what happens if that is taken
+   * being up to `onConflict`. Within a share the server renames it; to another share it is copied and
+   * then removed, the copy being removed again should the copy or the removal fail. The server copies it
+   * itself when both shares are on it, unless it refuses; otherwise it is copied through this client.
+   * This handle keeps pointing at the old location; get the moved file from `destDir`.
+   */
+  transferTo(destDir: JsSmbDirectoryHandle, newName?: string, options?: JsSmbTransferOptions): Promise<JsSmbTransferResult>
+  setTimes(times: JsSmbSetTimesOptions): Promise<void>
+  /**
+   * Replaces the contents of the file with `data` through a single handle: truncating, writing and
+   * flushing in one task leaves other clients a much smaller window to interleave than separate
+   * `truncate` and `write` calls do, though it is no transaction.
+   */
+  replaceContents(data: string | Uint8Array): Promise<void>
+  /**
+   * Reserves `size` bytes of disk space for the file up front without changing its size,
+   * failing fast if the volume doesn't have room.
+   */
+  preallocate(size: bigint): Promise<void>
+  /**
+   * Opens the file for atomic appends, each `append(data)` on the returned handle landing at end-of-file
+   * even with other writers appending concurrently. There is no position in this mode, as any write offset
+   * is ignored; true atomicity depends on the server honoring append-only access.
+   */
+  openAppend(): Promise<JsSmbAppendHandle>
+  /**
+   * Hashes the file's contents, or the byte range given, returning the digest as lowercase hex.
+   * The file is read in chunks of the server's max read size, never holding it all in memory.
+   */
+  hash(algorithm: 'crc32' | 'sha256', options?: JsSmbHashOptions): Promise<string>
+  /**
+   * Starts or resumes an upload written in sequential chunks, continuing from the current file size.
+   * After a failure, calling it again (or `committedOffset()` on the returned handle) tells how much
+   * of the upload reached the server, so the caller can carry on from there. This assumes the file is
+   * only ever written append-style, from start to end, while the upload is in progress.
+   */
+  resumableWrite(): Promise<JsSmbResumableWriteHandle>
+  /**
+   * The 64-bit id the server gave the file, as a decimal string. Unlike the path, it stays the same
+   * when the file is renamed or moved, so `findByFileId()` on a directory above it finds the file again.
+   * Fails with `NOT_SUPPORTED` on servers that report no file ids.
+   */
+  fileId(): Promise<string>
+  getAcl(): Promise<JsSmbAcl>
+  /**
+   * Replaces the file's DACL with `acl.aces` (and owner/group when given).
+   * Requires WRITE_DAC (and WRITE_OWNER for owner changes) on the file; how the server
+   * reconciles the result with POSIX permissions is server-dependent.
+   */
+  setAcl(acl: JsSmbAcl): Promise<void>
   createWritable(options?: JsSmbCreateWritableOptions): Promise<JsSmbWritableFileStream>
+  /**
+   * Pipes a ReadableStream into the file, e.g. a fetch() body. Chunks are written sequentially on the
+   * worker pool, the next chunk only being read once the previous write has completed or been queued.
+   */
+  writeFromStream(stream: ReadableStream, options?: JsSmbWriteFromStreamOptions): Promise<void>
+}
+export declare class JsSmbFileLines {
+  [Symbol.asyncIterator]: AsyncIterableIterator<string>
+}
+export declare class JsSmbFileTail {
+  /**
+   * Resolves once the file has grown, to the bytes appended or to the next line, or to null once closed.
+   * Fails with `ERROR_CANCELLED` if the connection is shut down meanwhile.
+   */
+  next(): Promise<string | Uint8Array | null>
+  /** Stops following the file, a `next()` that is waiting resolving to null at once. */
+  close(): void
+}
+export declare class JsSmbAppendHandle {
+  /**
+   * Appends `data` at end-of-file. Data larger than the server's max write size is sent in several
+   * requests, each appended atomically on its own.
+   */
+  append(data: string | Uint8Array): Promise<void>
+}
+export declare class JsSmbResumableWriteHandle {
+  /**
+   * Refreshes the offset writing continues from with the size of the file on the server,
+   * which after a failed `write()` tells how much of it was committed.
+   */
+  committedOffset(): Promise<number>
+  /**
+   * Writes `data` at the committed offset, advancing it by what the server has acknowledged, so
+   * after a failure part-way through it already points past the bytes that were written.
+   * Fails while a previous write is still in progress, as both would write at the same offset.
+   */
+  write(data: string | Uint8Array): Promise<void>
 }
+/**
+ * Has the properties and methods of a `File`, but reads its contents lazily so is no `Blob` subclass
+ * and fails `instanceof` checks for either; `slice()` gives a real `Blob` of (part of) the contents.
+ */
 export declare class JsSmbFile {
   readonly size: number
   readonly type: string
@@ -86,15 +798,27 @@ export declare class JsSmbFile {
   readonly name: string
   arrayBuffer(): Promise<ArrayBuffer>
   slice(start?: number, end?: number, contentType?: string): Blob
-  stream(): ReadableStream<Uint8Array>
+  /** The returned stream's `smbSource` property exposes the read progress as `bytesRemaining` and `done`. */
+  stream(): ReadableStream<Uint8Array> & { smbSource: JsSmbReadableStreamSource }
   text(): Promise<string>
+  bytes(): Promise<Uint8Array>
+  /** Always empty, as for any `File` not picked from a directory input. */
+  get webkitRelativePath(): string
 }
 export declare class JsSmbReadableStreamSource {
   readonly type: 'bytes'
+  /** Bytes left to read, from the file size cached when the stream was created (and refreshed on each pull). */
+  get bytesRemaining(): number
+  /** Whether the whole file has been read. */
+  get done(): boolean
   pull(controller: ReadableByteStreamController): void
 }
+/**
+ * A stream takes one write, seek or truncate at a time: the task running it holds the stream
+ * until it settles, so any other one issued before then fails with "Write in progress".
+ */
 export declare class JsSmbWritableFileStream {
-  readonly locked: boolean
+  get locked(): boolean
   write(
     data:
       | ArrayBuffer
@@ -108,11 +832,19 @@ export declare class JsSmbWritableFileStream {
           data?: ArrayBuffer | ArrayBufferView | DataView | Blob | String | string
           position?: number
           size?: number
+          whence?: 'start' | 'current' | 'end'
         },
   ): Promise<void>
   seek(position: number): Promise<void>
   truncate(size: number): Promise<void>
+  /**
+   * Flushes the file to stable storage, so that every write before the barrier is durable before
+   * any write after it is issued. Cheaper than syncing each write, while ordering them unlike close().
+   */
+  barrier(): Promise<void>
   close(): Promise<void>
+  /** Like close(), but flushes the file to stable storage first and reports what was written. */
+  finish(): Promise<JsSmbWriteSummary>
   abort(reason: string): Promise<string>
   releaseLock(): void
   getWriter(): WritableStreamDefaultWriter
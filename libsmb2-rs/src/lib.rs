@@ -7,14 +7,19 @@ use libsmb2_sys::*;
 use nix::fcntl::OFlag;
 use nix::sys::stat::Mode;
 
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use std::ffi::{c_void, CStr, CString};
 use std::io::{Error, ErrorKind, Result};
 use std::mem::zeroed;
 use std::os::raw::c_char;
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{Receiver, Sender};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 use bitflags::bitflags;
 
 
@@ -32,6 +37,14 @@ bitflags! {
     }
 }
 
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct SmbSecurityMode: u16 {
+        const SIGNING_ENABLED       = 0x0001;
+        const SIGNING_REQUIRED      = 0x0002;
+    }
+}
+
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct SmbChangeNotifyFileFilter: u32 {
@@ -94,6 +107,65 @@ impl ToString for SmbChangeNotifyAction {
     }
 }
 
+/// Size of the opaque key by which server-side copies name their source, see MS-SMB2 2.2.32.3.
+const RESUME_KEY_SIZE: usize = 24;
+
+/// Key naming an open file as the source of server-side copies, as returned by `SmbFile::request_resume_key`.
+pub type SmbResumeKey = [u8; RESUME_KEY_SIZE];
+
+/// Cancels a blocking notify_change loop. Cancelling writes to a pipe whose read end the loop polls
+/// along with the SMB socket, so the loop wakes up at once rather than at its next poll timeout.
+/// The byte written is never read, so the pipe stays readable and the cancellation sticks.
+#[derive(Debug)]
+pub struct SmbCancellation {
+    read_fd: OwnedFd,
+    write_fd: OwnedFd,
+    cancelled: AtomicBool,
+}
+
+impl SmbCancellation {
+    pub fn new() -> Result<Self> {
+        let mut fds = [0; 2];
+        unsafe {
+            if libc::pipe(fds.as_mut_ptr()) < 0 {
+                return Err(Error::last_os_error());
+            }
+            let (read_fd, write_fd) = (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1]));
+            for fd in fds {
+                if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) < 0 || libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK) < 0 {
+                    return Err(Error::last_os_error());
+                }
+            }
+            Ok(Self{read_fd, write_fd, cancelled: AtomicBool::new(false)})
+        }
+    }
+
+    /// Cancels, returning false if already cancelled.
+    pub fn cancel(&self) -> bool {
+        if self.cancelled.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        let byte = 1_u8;
+        unsafe {
+            let _ = libc::write(self.write_fd.as_raw_fd(), (&byte as *const u8).cast::<c_void>(), 1);
+        }
+        true
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Blocks until cancelled or `timeout` has passed, returning whether cancelled.
+    pub fn wait_timeout(&self, timeout: std::time::Duration) -> bool {
+        let mut pfd = libc::pollfd{fd: self.read_fd.as_raw_fd(), events: libc::POLLIN, revents: 0};
+        unsafe {
+            let _ = libc::poll(&mut pfd, 1, timeout.as_millis().min(i32::MAX as u128) as i32);
+        }
+        self.is_cancelled()
+    }
+}
+
 #[derive(Clone)]
 struct SmbPtr(Arc<Mutex<*mut smb2_context>>);
 // Safe because smb2_context in SmbPtr is enclosed within a Mutex
@@ -112,6 +184,32 @@ impl Drop for SmbPtr {
     }
 }
 
+/// Payload of the errors of failed SMB2 requests, keeping the NT status the server returned.
+/// Displays as the message alone, so the errors read as before.
+#[derive(Debug)]
+pub struct SmbNtStatusError {
+    pub status: u32,
+    message: String,
+}
+
+impl std::fmt::Display for SmbNtStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for SmbNtStatusError {}
+
+/// Overwrites `bytes` with zeros in a way the compiler cannot optimize away, for buffers that held secrets.
+pub fn zeroize(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        unsafe {
+            std::ptr::write_volatile(byte, 0);
+        }
+    }
+    std::sync::atomic::compiler_fence(Ordering::SeqCst);
+}
+
 fn check_mut_ptr<T>(ptr: *mut T) -> Result<*mut T> {
     if ptr.is_null() {
         Err(Error::last_os_error())
@@ -130,13 +228,602 @@ fn check_retcode(ctx: *mut smb2_context, code: i32) -> Result<()> {
             } else {
                 nix::errno::Errno::from_raw(-code).to_string()
             };
-            Err(Error::new(ErrorKind::Other, e))
+            let kind = Error::from_raw_os_error(-code).kind();
+            match smb2_get_nterror(ctx) as u32 {
+                0 => Err(Error::new(kind, e)),
+                status => Err(Error::new(kind, SmbNtStatusError{status, message: e})),
+            }
         }
     } else {
         Ok(())
     }
 }
 
+fn check_ntstatus(status: i32) -> Result<()> {
+    if status != 0 {
+        unsafe {
+            let err_ptr = nterror_to_str(status as u32);
+            let err_str = CStr::from_ptr(err_ptr).to_string_lossy().into_owned();
+            let kind = Error::from_raw_os_error(nterror_to_errno(status as u32)).kind();
+            Err(Error::new(kind, SmbNtStatusError{status: status as u32, message: err_str}))
+        }
+    } else {
+        Ok(())
+    }
+}
+
+struct SyncCbData {
+    is_finished: bool,
+    status: i32,
+}
+
+extern "C" fn sync_cb(_ctx: *mut smb2_context, status: i32, _command_data: *mut c_void, cb_data: *mut c_void) {
+    let cb_data = unsafe { &mut *cb_data.cast::<SyncCbData>() };
+    cb_data.is_finished = true;
+    cb_data.status = status;
+}
+
+/// Callback data tracking the completion of the pdu it was queued with.
+trait CbData {
+    fn sync(&self) -> &SyncCbData;
+}
+
+impl CbData for SyncCbData {
+    fn sync(&self) -> &SyncCbData {
+        self
+    }
+}
+
+/// Callback data handed to libsmb2 along with a queued pdu. libsmb2 runs the callbacks of pdus still
+/// queued when the context is disconnected or destroyed, so the data lives on the heap, and is leaked
+/// rather than freed while its pdu may still be queued, as libsmb2's own sync.c does.
+struct QueuedCbData<T: CbData>(*mut T);
+
+impl<T: CbData> QueuedCbData<T> {
+    fn is_finished(&self) -> bool {
+        unsafe { (*self.0).sync().is_finished }
+    }
+
+    /// Takes the callback data back once its pdu completed.
+    fn into_inner(self) -> T {
+        assert!(self.is_finished());
+        let cb_data = self.0;
+        std::mem::forget(self);
+        *unsafe { Box::from_raw(cb_data) }
+    }
+}
+
+impl<T: CbData> Drop for QueuedCbData<T> {
+    fn drop(&mut self) {
+        if self.is_finished() {
+            drop(unsafe { Box::from_raw(self.0) });
+        } else {
+            log::warn!("abandoning the callback data of a pdu still queued");
+        }
+    }
+}
+
+/// Queue the pdu `cmd` creates with a pointer to `cb_data` as its callback data.
+unsafe fn queue_pdu<T: CbData>(ctx: *mut smb2_context, cb_data: T, cmd: impl FnOnce(*mut c_void) -> *mut smb2_pdu) -> Result<QueuedCbData<T>> {
+    let cb_data = Box::into_raw(Box::new(cb_data));
+    let pdu = cmd(cb_data.cast::<c_void>());
+    if pdu.is_null() {
+        drop(Box::from_raw(cb_data));
+        check_retcode(ctx, -1)?
+    }
+    smb2_queue_pdu(ctx, pdu);
+    Ok(QueuedCbData(cb_data))
+}
+
+/// Drive the context's event loop until the pdu queued with `cb_data` completes.
+unsafe fn wait_for_reply<T: CbData>(ctx: *mut smb2_context, cb_data: QueuedCbData<T>) -> Result<T> {
+    wait_for_replies(ctx, &[&cb_data])?;
+    let cb_data = cb_data.into_inner();
+    check_ntstatus(cb_data.sync().status)?;
+    Ok(cb_data)
+}
+
+/// Drive the context's event loop until the pdus queued with each of `cb_data` all complete,
+/// leaving their statuses for the caller to check.
+unsafe fn wait_for_replies<T: CbData>(ctx: *mut smb2_context, cb_data: &[&QueuedCbData<T>]) -> Result<()> {
+    while cb_data.iter().any(|cb_data| !cb_data.is_finished()) {
+        let mut pfd = libc::pollfd{fd: smb2_get_fd(ctx), events: smb2_which_events(ctx) as libc::c_short, revents: 0};
+        if libc::poll(&mut pfd, 1, 1000) < 0 {
+            return Err(Error::last_os_error());
+        }
+        if pfd.revents == 0 {
+            continue;
+        }
+        check_retcode(ctx, smb2_service(ctx, pfd.revents.into()))?;
+    }
+    Ok(())
+}
+
+struct StatCbData {
+    sync: SyncCbData,
+    stat: Option<SmbStat>,
+}
+
+impl CbData for StatCbData {
+    fn sync(&self) -> &SyncCbData {
+        &self.sync
+    }
+}
+
+extern "C" fn all_information_cb(_ctx: *mut smb2_context, status: i32, command_data: *mut c_void, cb_data: *mut c_void) {
+    let cb_data = unsafe { &mut *cb_data.cast::<StatCbData>() };
+    if status == 0 && !command_data.is_null() {
+        let reply = unsafe { &*command_data.cast::<smb2_query_info_reply>() };
+        if !reply.output_buffer.is_null() {
+            cb_data.stat = Some(SmbStat::from_all_information(unsafe { &*reply.output_buffer.cast::<smb2_file_all_info>() }));
+        }
+    }
+    cb_data.sync.is_finished = true;
+    cb_data.sync.status = status;
+}
+
+/// Queue a query of the FILE_ALL_INFORMATION of an open file, see MS-FSCC 2.4.2.
+unsafe fn queue_query_all_information(ctx: *mut smb2_context, file_id: smb2_file_id) -> Result<QueuedCbData<StatCbData>> {
+    let mut req: smb2_query_info_request = zeroed();
+    req.info_type = SMB2_0_INFO_FILE as _;
+    req.file_info_class = SMB2_FILE_ALL_INFORMATION as _;
+    req.output_buffer_length = 65535;
+    req.file_id = file_id;
+    let cb_data = StatCbData{sync: SyncCbData{is_finished: false, status: 0}, stat: None};
+    queue_pdu(ctx, cb_data, |cb_data| smb2_cmd_query_info_async(ctx, &mut req, Some(all_information_cb), cb_data))
+}
+
+/// Queue the close of a file opened by a raw create request.
+unsafe fn queue_close(ctx: *mut smb2_context, file_id: smb2_file_id) -> Result<QueuedCbData<SyncCbData>> {
+    let mut req: smb2_close_request = zeroed();
+    req.file_id = file_id;
+    queue_pdu(ctx, SyncCbData{is_finished: false, status: 0}, |cb_data| smb2_cmd_close_async(ctx, &mut req, Some(sync_cb), cb_data))
+}
+
+struct CreateCbData {
+    sync: SyncCbData,
+    reply: Option<smb2_create_reply>,
+}
+
+impl CbData for CreateCbData {
+    fn sync(&self) -> &SyncCbData {
+        &self.sync
+    }
+}
+
+extern "C" fn create_cb(_ctx: *mut smb2_context, status: i32, command_data: *mut c_void, cb_data: *mut c_void) {
+    let cb_data = unsafe { &mut *cb_data.cast::<CreateCbData>() };
+    if status == 0 && !command_data.is_null() {
+        cb_data.reply = Some(unsafe { *command_data.cast::<smb2_create_reply>() });
+    }
+    cb_data.sync.is_finished = true;
+    cb_data.sync.status = status;
+}
+
+struct IoctlCbData {
+    sync: SyncCbData,
+    output: Vec<u8>,
+    /// Whether the server returned STATUS_BUFFER_OVERFLOW, the output being cut short at the maximum requested.
+    overflowed: bool,
+}
+
+impl CbData for IoctlCbData {
+    fn sync(&self) -> &SyncCbData {
+        &self.sync
+    }
+}
+
+extern "C" fn ioctl_cb(_ctx: *mut smb2_context, status: i32, command_data: *mut c_void, cb_data: *mut c_void) {
+    let cb_data = unsafe { &mut *cb_data.cast::<IoctlCbData>() };
+    // STATUS_BUFFER_OVERFLOW is a warning, its reply carrying as much output as fits
+    cb_data.overflowed = status as u32 == STATUS_BUFFER_OVERFLOW;
+    if (status == 0 || cb_data.overflowed) && !command_data.is_null() {
+        let reply = unsafe { &*command_data.cast::<smb2_ioctl_reply>() };
+        if !reply.output.is_null() && reply.output_count > 0 {
+            cb_data.output = unsafe { std::slice::from_raw_parts(reply.output.cast::<u8>(), reply.output_count as usize) }.to_vec();
+        }
+    }
+    cb_data.sync.is_finished = true;
+    cb_data.sync.status = if cb_data.overflowed { 0 } else { status };
+}
+
+/// Decode the FILE_ALLOCATED_RANGE_BUFFER array returned by FSCTL_QUERY_ALLOCATED_RANGES
+/// into (offset, length) pairs.
+fn decode_allocated_ranges(output: &[u8]) -> Vec<(u64, u64)> {
+    output.chunks_exact(ALLOCATED_RANGE_SIZE).map(|range| {
+        let offset = u64::from_le_bytes(range[0..8].try_into().unwrap());
+        let length = u64::from_le_bytes(range[8..16].try_into().unwrap());
+        (offset, length)
+    }).collect()
+}
+
+/// Encode an (offset, end) pair as the input of FSCTL_QUERY_ALLOCATED_RANGES or FSCTL_SET_ZERO_DATA,
+/// which differ in whether the second field is a length or an end offset.
+fn encode_range(first: u64, second: u64) -> [u8; 16] {
+    let mut input = [0u8; 16];
+    input[0..8].copy_from_slice(&first.to_le_bytes());
+    input[8..16].copy_from_slice(&second.to_le_bytes());
+    input
+}
+
+/// Encode an SRV_COPYCHUNK_COPY, the input of FSCTL_SRV_COPYCHUNK_WRITE, see MS-SMB2 2.2.31.1.
+fn encode_copychunk_copy(source_key: &SmbResumeKey, chunks: &[(u64, u64, u32)]) -> Vec<u8> {
+    let mut input = Vec::with_capacity(source_key.len() + 8 + chunks.len() * 24);
+    input.extend_from_slice(source_key); // SourceKey
+    input.extend_from_slice(&(chunks.len() as u32).to_le_bytes()); // ChunkCount
+    input.extend_from_slice(&0u32.to_le_bytes()); // Reserved
+    for (source_offset, target_offset, length) in chunks {
+        input.extend_from_slice(&source_offset.to_le_bytes());
+        input.extend_from_slice(&target_offset.to_le_bytes());
+        input.extend_from_slice(&length.to_le_bytes());
+        input.extend_from_slice(&0u32.to_le_bytes()); // Reserved
+    }
+    input
+}
+
+const DELETE: u32 = 0x0001_0000;
+const READ_CONTROL: u32 = 0x0002_0000;
+const WRITE_DAC: u32 = 0x0004_0000;
+const WRITE_OWNER: u32 = 0x0008_0000;
+const OWNER_SECURITY_INFORMATION: u32 = 0x0000_0001;
+const GROUP_SECURITY_INFORMATION: u32 = 0x0000_0002;
+const DACL_SECURITY_INFORMATION: u32 = 0x0000_0004;
+const SE_DACL_PRESENT: u16 = 0x0004;
+const SE_SELF_RELATIVE: u16 = 0x8000;
+const SMB2_FILE_ALLOCATION_INFORMATION: u32 = 0x13;
+/// Write offset meaning end-of-file, for handles with append but not write access (MS-FSA 2.1.5.3).
+const SMB2_WRITE_TO_EOF: u64 = u64::MAX;
+
+/// Bytes of directory entries requested per QUERY_DIRECTORY round trip, the minimum every server supports.
+const DIRECTORY_PAGE_SIZE: u32 = 65536;
+/// 100ns intervals between the NT epoch (1601-01-01) and the Unix epoch.
+const NT_TO_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+const FILE_ATTRIBUTE_READONLY: u32 = 0x01;
+const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+/// FILE_OPEN_NO_RECALL create option, keeping tiered storage from recalling the file's data.
+const SMB2_FILE_OPEN_NO_RECALL: u32 = 0x0040_0000;
+/// Filesystem control codes, see MS-FSCC 2.3.
+const FSCTL_SET_SPARSE: u32 = 0x0009_00C4;
+const FSCTL_SET_ZERO_DATA: u32 = 0x0009_80C8;
+const FSCTL_QUERY_ALLOCATED_RANGES: u32 = 0x0009_40CF;
+/// Server-side copy control codes, see MS-SMB2 2.2.31.
+const FSCTL_SRV_REQUEST_RESUME_KEY: u32 = 0x0014_0078;
+const FSCTL_SRV_COPYCHUNK_WRITE: u32 = 0x0014_80F2;
+/// SMB2_0_IOCTL_IS_FSCTL flag of IOCTL requests, see MS-SMB2 2.2.31.
+const SMB2_0_IOCTL_IS_FSCTL: u32 = 0x0000_0001;
+/// Size of a FILE_ALLOCATED_RANGE_BUFFER, see MS-FSCC 2.3.52.
+const ALLOCATED_RANGE_SIZE: usize = 16;
+/// Allocated ranges requested per FSCTL_QUERY_ALLOCATED_RANGES round trip.
+const ALLOCATED_RANGES_PER_QUERY: usize = 4096;
+/// Output requested of FSCTL_SRV_REQUEST_RESUME_KEY: the SRV_REQUEST_RESUME_KEY response, see MS-SMB2 2.2.32.3,
+/// with room for the context servers may append.
+const RESUME_KEY_RESPONSE_SIZE: u32 = 64;
+/// Size of an SRV_COPYCHUNK_RESPONSE, see MS-SMB2 2.2.32.1.
+const COPYCHUNK_RESPONSE_SIZE: u32 = 12;
+/// NT status of replies cut short to the output size requested, see MS-ERREF 2.3.1.
+const STATUS_BUFFER_OVERFLOW: u32 = 0x8000_0005;
+
+/// FILE_ALLOCATION_INFORMATION, see MS-FSCC 2.4.4.
+#[repr(C)]
+struct SmbFileAllocationInfo {
+    allocation_size: u64,
+}
+
+struct SecurityCbData {
+    sync: SyncCbData,
+    descriptor: Vec<u8>,
+}
+
+impl CbData for SecurityCbData {
+    fn sync(&self) -> &SyncCbData {
+        &self.sync
+    }
+}
+
+extern "C" fn security_cb(_ctx: *mut smb2_context, status: i32, command_data: *mut c_void, cb_data: *mut c_void) {
+    let cb_data = unsafe { &mut *cb_data.cast::<SecurityCbData>() };
+    if status == 0 && !command_data.is_null() {
+        let reply = unsafe { &*command_data.cast::<smb2_query_info_reply>() };
+        if !reply.output_buffer.is_null() {
+            cb_data.descriptor = unsafe { encode_security_descriptor(&*reply.output_buffer.cast::<smb2_security_descriptor>()) };
+        }
+    }
+    cb_data.sync.is_finished = true;
+    cb_data.sync.status = status;
+}
+
+struct QueryDirectoryCbData {
+    sync: SyncCbData,
+    output: Vec<u8>,
+}
+
+impl CbData for QueryDirectoryCbData {
+    fn sync(&self) -> &SyncCbData {
+        &self.sync
+    }
+}
+
+extern "C" fn query_directory_cb(_ctx: *mut smb2_context, status: i32, command_data: *mut c_void, cb_data: *mut c_void) {
+    let cb_data = unsafe { &mut *cb_data.cast::<QueryDirectoryCbData>() };
+    if status == 0 && !command_data.is_null() {
+        let reply = unsafe { &*command_data.cast::<smb2_query_directory_reply>() };
+        if !reply.output_buffer.is_null() {
+            cb_data.output = unsafe { std::slice::from_raw_parts(reply.output_buffer, reply.output_buffer_length as usize) }.to_vec();
+        }
+    }
+    cb_data.sync.is_finished = true;
+    cb_data.sync.status = status;
+}
+
+/// Split an NT timestamp (100ns intervals since 1601) into seconds and nanoseconds since the Unix epoch.
+fn nt_time_to_unix(nt_time: u64) -> (u64, u64) {
+    let since_epoch = nt_time.saturating_sub(NT_TO_UNIX_EPOCH);
+    (since_epoch / 10_000_000, (since_epoch % 10_000_000) * 100)
+}
+
+/// Decode a buffer of FILE_ID_FULL_DIR_INFORMATION entries, see MS-FSCC 2.4.18.
+/// Entries whose name can't be decoded are returned as errors in their place.
+fn decode_full_directory_information(buf: &[u8]) -> Result<Vec<Result<DirEntry>>> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "malformed directory information");
+    let u32_at = |offset: usize| buf.get(offset..offset + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap())).ok_or_else(invalid);
+    let u64_at = |offset: usize| buf.get(offset..offset + 8).map(|b| u64::from_le_bytes(b.try_into().unwrap())).ok_or_else(invalid);
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset < buf.len() {
+        let next_entry_offset = u32_at(offset)? as usize;
+        let name_len = u32_at(offset + 60)? as usize;
+        let name_bytes = buf.get(offset + 80..offset + 80 + name_len).ok_or_else(invalid)?;
+        let name: Vec<u16> = name_bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+        let attributes = u32_at(offset + 56)?;
+        let d_type = match () {
+            _ if attributes & FILE_ATTRIBUTE_REPARSE_POINT != 0 => EntryType::Symlink,
+            _ if attributes & FILE_ATTRIBUTE_DIRECTORY != 0 => EntryType::Directory,
+            _ => EntryType::File,
+        };
+        let (btime, btime_nsec) = nt_time_to_unix(u64_at(offset + 8)?);
+        let (atime, atime_nsec) = nt_time_to_unix(u64_at(offset + 16)?);
+        let (mtime, mtime_nsec) = nt_time_to_unix(u64_at(offset + 24)?);
+        let (ctime, ctime_nsec) = nt_time_to_unix(u64_at(offset + 32)?);
+        // XXX: names with unpaired surrogates are valid on Windows, but have no UTF-8 path to reopen them by,
+        // so such entries fail on their own rather than being listed under a name that doesn't exist
+        entries.push(match String::from_utf16(&name) {
+            Ok(name) => Ok(DirEntry {
+                path: PathBuf::from(name),
+                inode: u64_at(offset + 72)?,
+                mode: d_type.synthesized_mode(attributes),
+                d_type,
+                size: u64_at(offset + 40)?,
+                atime,
+                mtime,
+                ctime,
+                btime,
+                nlink: 0,
+                atime_nsec,
+                mtime_nsec,
+                ctime_nsec,
+                btime_nsec,
+            }),
+            Err(e) => Err(Error::new(ErrorKind::InvalidData, format!("directory entry name {:?} is not valid UTF-16: {}", String::from_utf16_lossy(&name), e))),
+        });
+        if next_entry_offset == 0 {
+            break;
+        }
+        offset += next_entry_offset;
+    }
+    Ok(entries)
+}
+
+unsafe fn encode_sid(buf: &mut Vec<u8>, sid: *const smb2_sid) {
+    let sid = &*sid;
+    buf.push(sid.revision);
+    buf.push(sid.sub_auth_count);
+    buf.extend_from_slice(&sid.id_auth);
+    for sub_auth in sid.sub_auth.as_slice(sid.sub_auth_count as usize) {
+        buf.extend_from_slice(&sub_auth.to_le_bytes());
+    }
+}
+
+/// Re-encode the descriptor libsmb2 decoded for us into the self-relative wire format (MS-DTYP 2.4.6).
+/// Only owner, group and the DACL's allow/deny/audit/alarm ACEs are carried over.
+unsafe fn encode_security_descriptor(sd: &smb2_security_descriptor) -> Vec<u8> {
+    let mut buf = vec![0u8; 20];
+    let mut control = SE_SELF_RELATIVE;
+    if !sd.owner.is_null() {
+        let offset = buf.len() as u32;
+        encode_sid(&mut buf, sd.owner);
+        buf[4..8].copy_from_slice(&offset.to_le_bytes());
+    }
+    if !sd.group.is_null() {
+        let offset = buf.len() as u32;
+        encode_sid(&mut buf, sd.group);
+        buf[8..12].copy_from_slice(&offset.to_le_bytes());
+    }
+    if !sd.dacl.is_null() {
+        control |= SE_DACL_PRESENT;
+        let acl_offset = buf.len();
+        buf.extend_from_slice(&[(*sd.dacl).revision, 0, 0, 0, 0, 0, 0, 0]);
+        let mut ace_count: u16 = 0;
+        let mut ace = (*sd.dacl).aces;
+        while !ace.is_null() {
+            let a = &*ace;
+            ace = a.next;
+            if a.ace_type > 3 || a.sid.is_null() {
+                continue;
+            }
+            let ace_offset = buf.len();
+            buf.extend_from_slice(&[a.ace_type, a.ace_flags, 0, 0]);
+            buf.extend_from_slice(&a.mask.to_le_bytes());
+            encode_sid(&mut buf, a.sid);
+            let ace_size = (buf.len() - ace_offset) as u16;
+            buf[ace_offset + 2..ace_offset + 4].copy_from_slice(&ace_size.to_le_bytes());
+            ace_count += 1;
+        }
+        let acl_size = (buf.len() - acl_offset) as u16;
+        buf[acl_offset + 2..acl_offset + 4].copy_from_slice(&acl_size.to_le_bytes());
+        buf[acl_offset + 4..acl_offset + 6].copy_from_slice(&ace_count.to_le_bytes());
+        buf[16..20].copy_from_slice(&(acl_offset as u32).to_le_bytes());
+    }
+    buf[0] = sd.revision;
+    buf[2..4].copy_from_slice(&control.to_le_bytes());
+    buf
+}
+
+/// libsmb2's decoded security descriptor structures, backed by memory owned on the Rust side.
+struct OwnedSecurityDescriptor {
+    _sids: Vec<Vec<u32>>,
+    _aces: Vec<smb2_ace>,
+    _acl: Option<Box<smb2_acl>>,
+    sd: Box<smb2_security_descriptor>,
+    additional_information: u32,
+}
+
+fn malformed_descriptor() -> Error {
+    Error::new(ErrorKind::InvalidInput, "malformed security descriptor")
+}
+
+fn descriptor_u16(buf: &[u8], offset: usize) -> Result<u16> {
+    buf.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or_else(malformed_descriptor)
+}
+
+fn descriptor_u32(buf: &[u8], offset: usize) -> Result<u32> {
+    buf.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok_or_else(malformed_descriptor)
+}
+
+/// Decode a SID into a u32 buffer laid out as smb2_sid (header word pair followed by sub authorities).
+fn decode_sid(buf: &[u8], offset: usize) -> Result<Vec<u32>> {
+    let header = buf.get(offset..offset + 8).ok_or_else(malformed_descriptor)?;
+    let sub_auth_count = header[1] as usize;
+    let mut sid = vec![0u32; 2 + sub_auth_count];
+    unsafe {
+        let sid_ptr = sid.as_mut_ptr().cast::<smb2_sid>();
+        (*sid_ptr).revision = header[0];
+        (*sid_ptr).sub_auth_count = header[1];
+        (*sid_ptr).id_auth.copy_from_slice(&header[2..8]);
+        let sub_auths = (*sid_ptr).sub_auth.as_mut_slice(sub_auth_count);
+        for (i, sub_auth) in sub_auths.iter_mut().enumerate() {
+            *sub_auth = descriptor_u32(buf, offset + 8 + i * 4)?;
+        }
+    }
+    Ok(sid)
+}
+
+/// Decode a self-relative security descriptor holding owner, group and/or a DACL of
+/// allow/deny/audit/alarm ACEs, as produced by get_security.
+fn decode_security_descriptor(buf: &[u8]) -> Result<OwnedSecurityDescriptor> {
+    if buf.len() < 20 {
+        return Err(malformed_descriptor());
+    }
+    let mut sids = Vec::new();
+    let mut sd: Box<smb2_security_descriptor> = Box::new(unsafe { zeroed() });
+    sd.revision = buf[0];
+    sd.control = descriptor_u16(buf, 2)?;
+    let mut additional_information = 0;
+    let owner_offset = descriptor_u32(buf, 4)? as usize;
+    if owner_offset != 0 {
+        let mut sid = decode_sid(buf, owner_offset)?;
+        sd.owner = sid.as_mut_ptr().cast::<smb2_sid>();
+        sids.push(sid);
+        additional_information |= OWNER_SECURITY_INFORMATION;
+    }
+    let group_offset = descriptor_u32(buf, 8)? as usize;
+    if group_offset != 0 {
+        let mut sid = decode_sid(buf, group_offset)?;
+        sd.group = sid.as_mut_ptr().cast::<smb2_sid>();
+        sids.push(sid);
+        additional_information |= GROUP_SECURITY_INFORMATION;
+    }
+    let mut aces: Vec<smb2_ace> = Vec::new();
+    let mut acl = None;
+    let dacl_offset = descriptor_u32(buf, 16)? as usize;
+    if dacl_offset != 0 {
+        let ace_count = descriptor_u16(buf, dacl_offset + 4)?;
+        let mut offset = dacl_offset + 8;
+        for _ in 0..ace_count {
+            let ace_type = *buf.get(offset).ok_or_else(malformed_descriptor)?;
+            let ace_size = descriptor_u16(buf, offset + 2)?;
+            if ace_type > 3 || ace_size < 8 {
+                return Err(malformed_descriptor());
+            }
+            let mut sid = decode_sid(buf, offset + 8)?;
+            let mut ace: smb2_ace = unsafe { zeroed() };
+            ace.ace_type = ace_type;
+            ace.ace_flags = buf[offset + 1];
+            ace.ace_size = ace_size;
+            ace.mask = descriptor_u32(buf, offset + 4)?;
+            ace.sid = sid.as_mut_ptr().cast::<smb2_sid>();
+            sids.push(sid);
+            aces.push(ace);
+            offset += ace_size as usize;
+        }
+        // link the ACEs only once the vector no longer moves
+        let ace_ptr = aces.as_mut_ptr();
+        for i in 1..aces.len() {
+            aces[i - 1].next = unsafe { ace_ptr.add(i) };
+        }
+        let mut dacl: Box<smb2_acl> = Box::new(unsafe { zeroed() });
+        dacl.revision = *buf.get(dacl_offset).ok_or_else(malformed_descriptor)?;
+        dacl.ace_count = ace_count;
+        dacl.aces = if aces.is_empty() { std::ptr::null_mut() } else { ace_ptr };
+        sd.dacl = &mut *dacl;
+        acl = Some(dacl);
+        additional_information |= DACL_SECURITY_INFORMATION;
+    }
+    Ok(OwnedSecurityDescriptor{_sids: sids, _aces: aces, _acl: acl, sd, additional_information})
+}
+
+/// Build a create request mirroring the access and disposition smb2_open derives from flags.
+fn create_request(path: &CString, flags: OFlag) -> smb2_create_request {
+    let mut req: smb2_create_request = unsafe { zeroed() };
+    req.impersonation_level = SMB2_IMPERSONATION_IMPERSONATION as _;
+    req.desired_access = match flags & OFlag::O_ACCMODE {
+        OFlag::O_WRONLY => SMB2_FILE_WRITE_DATA | SMB2_FILE_WRITE_EA | SMB2_FILE_WRITE_ATTRIBUTES,
+        OFlag::O_RDWR => SMB2_FILE_READ_DATA | SMB2_FILE_READ_EA | SMB2_FILE_READ_ATTRIBUTES | SMB2_FILE_WRITE_DATA | SMB2_FILE_WRITE_EA | SMB2_FILE_WRITE_ATTRIBUTES,
+        _ => SMB2_FILE_READ_DATA | SMB2_FILE_READ_EA | SMB2_FILE_READ_ATTRIBUTES,
+    } as _;
+    req.share_access = (SMB2_FILE_SHARE_READ | SMB2_FILE_SHARE_WRITE) as _;
+    req.create_disposition = match (flags.contains(OFlag::O_CREAT), flags.contains(OFlag::O_EXCL), flags.contains(OFlag::O_TRUNC)) {
+        (true, true, _) => SMB2_FILE_CREATE,
+        (true, false, true) => SMB2_FILE_OVERWRITE_IF,
+        (true, false, false) => SMB2_FILE_OPEN_IF,
+        (false, _, true) => SMB2_FILE_OVERWRITE,
+        (false, _, false) => SMB2_FILE_OPEN,
+    } as _;
+    req.create_options = SMB2_FILE_NON_DIRECTORY_FILE as _;
+    if flags.contains(OFlag::O_SYNC) {
+        req.create_options |= SMB2_FILE_WRITE_THROUGH as u32;
+    }
+    req.name = path.as_ptr();
+    req
+}
+
+/// Open a directory with `open`, given the desired access, for `Smb::fsync_dir`: with the rights to add
+/// entries if granted, else just to list it, telling which it got.
+fn open_for_fsync_dir<T>(mut open: impl FnMut(u32) -> Result<T>) -> Result<(T, bool)> {
+    match open(SMB2_FILE_LIST_DIRECTORY | SMB2_FILE_ADD_FILE | SMB2_FILE_ADD_SUBDIRECTORY | SMB2_FILE_READ_ATTRIBUTES) {
+        Ok(dir) => Ok((dir, true)),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => Ok((open(SMB2_FILE_LIST_DIRECTORY | SMB2_FILE_READ_ATTRIBUTES)?, false)),
+        Err(e) => Err(e),
+    }
+}
+
+/// Send a raw create request and wait for the reply.
+unsafe fn send_create(ctx: *mut smb2_context, req: &mut smb2_create_request) -> Result<smb2_create_reply> {
+    let cb_data = CreateCbData{sync: SyncCbData{is_finished: false, status: 0}, reply: None};
+    let cb_data = queue_pdu(ctx, cb_data, |cb_data| smb2_cmd_create_async(ctx, req, Some(create_cb), cb_data))?;
+    let cb_data = wait_for_reply(ctx, cb_data)?;
+    cb_data.reply.ok_or_else(|| Error::new(ErrorKind::Other, "missing create reply"))
+}
+
+fn to_smb2_timeval(time: Option<SystemTime>) -> smb2_timeval {
+    let mut tv: smb2_timeval = unsafe { zeroed() };
+    if let Some(since_epoch) = time.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+        tv.tv_sec = since_epoch.as_secs() as _;
+        tv.tv_usec = since_epoch.subsec_micros() as _;
+    }
+    tv
+}
+
 #[derive(Clone)]
 pub struct Smb {
     context: Arc<SmbPtr>,
@@ -156,6 +843,11 @@ pub enum EntryType {
 
 
 impl EntryType {
+    /// Entry type of a stat result, e.g. from `Smb::stat64` or `Smb::lstat64`.
+    pub fn from_stat(stat: &smb2_stat_64) -> Result<EntryType> {
+        Self::from(stat.smb2_type)
+    }
+
     fn from(smb_type: u32) -> Result<EntryType> {
         match smb_type {
             libsmb2_sys::SMB2_TYPE_DIRECTORY => Ok(EntryType::Directory),
@@ -167,6 +859,65 @@ impl EntryType {
             )),
         }
     }
+
+    /// POSIX permission bits for an entry of this type, synthesized from its DOS attributes
+    /// (MS-FSCC 2.6) the way Samba maps them: everyone may read, the owner may write unless the
+    /// entry is read-only, and directories may be searched.
+    pub fn synthesized_mode(&self, attributes: u32) -> u32 {
+        let mode = match self {
+            EntryType::Symlink => return 0o777,
+            EntryType::Directory => 0o755,
+            _ => 0o644,
+        };
+        if attributes & FILE_ATTRIBUTE_READONLY != 0 {
+            mode & !0o222
+        } else {
+            mode
+        }
+    }
+}
+
+/// A stat result along with the DOS attributes of the entry (MS-FSCC 2.6), which `smb2_stat_64` leaves out.
+#[derive(Clone, Copy)]
+pub struct SmbStat {
+    pub stat: smb2_stat_64,
+    pub attributes: u32,
+}
+
+impl SmbStat {
+    /// Decodes FILE_ALL_INFORMATION the way libsmb2's own stat does.
+    fn from_all_information(info: &smb2_file_all_info) -> SmbStat {
+        let attributes = info.basic.file_attributes;
+        let mut stat: smb2_stat_64 = unsafe { zeroed() };
+        stat.smb2_type = if attributes & FILE_ATTRIBUTE_REPARSE_POINT != 0 {
+            SMB2_TYPE_LINK
+        } else if attributes & FILE_ATTRIBUTE_DIRECTORY != 0 {
+            SMB2_TYPE_DIRECTORY
+        } else {
+            SMB2_TYPE_FILE
+        } as _;
+        stat.smb2_nlink = info.standard.number_of_links as _;
+        stat.smb2_ino = info.index_number as _;
+        stat.smb2_size = info.standard.end_of_file as _;
+        stat.smb2_atime = info.basic.last_access_time.tv_sec as _;
+        stat.smb2_atime_nsec = (info.basic.last_access_time.tv_usec as u64 * 1000) as _;
+        stat.smb2_mtime = info.basic.last_write_time.tv_sec as _;
+        stat.smb2_mtime_nsec = (info.basic.last_write_time.tv_usec as u64 * 1000) as _;
+        stat.smb2_ctime = info.basic.change_time.tv_sec as _;
+        stat.smb2_ctime_nsec = (info.basic.change_time.tv_usec as u64 * 1000) as _;
+        stat.smb2_btime = info.basic.creation_time.tv_sec as _;
+        stat.smb2_btime_nsec = (info.basic.creation_time.tv_usec as u64 * 1000) as _;
+        SmbStat{stat, attributes}
+    }
+
+    pub fn entry_type(&self) -> Result<EntryType> {
+        EntryType::from_stat(&self.stat)
+    }
+
+    /// POSIX permission bits synthesized from the attributes, see `EntryType::synthesized_mode`.
+    pub fn mode(&self) -> Result<u32> {
+        Ok(self.entry_type()?.synthesized_mode(self.attributes))
+    }
 }
 
 
@@ -175,7 +926,9 @@ pub struct DirEntry {
     pub path: PathBuf,
     pub inode: u64,
     pub d_type: EntryType,
-    //pub mode: Mode,
+    /// POSIX permission bits. libsmb2 doesn't negotiate the SMB3 POSIX extensions that would carry
+    /// them, so they are synthesized from the entry's DOS attributes.
+    pub mode: u32,
     pub size: u64,
     pub atime: u64,
     pub mtime: u64,
@@ -188,10 +941,40 @@ pub struct DirEntry {
     pub btime_nsec: u64,
 }
 
-#[derive(Clone)]
+/// An open directory whose entries are fetched from the server a page at a time as it is iterated,
+/// so that listing huge directories doesn't buffer them whole.
 pub struct SmbDirectory {
     smb: Arc<SmbPtr>,
-    handle: *mut smb2dir,
+    handle: *mut smb2fh,
+    file_id: smb2_file_id,
+    pattern: CString,
+    page: VecDeque<Result<DirEntry>>,
+    finished: bool,
+}
+
+impl SmbDirectory {
+    /// Fetch the next page of entries, returning false once the server has none left.
+    fn fetch_page(&mut self) -> Result<bool> {
+        let ctx_ref = using_mutex!(self.smb);
+        let ctx = *ctx_ref;
+        unsafe {
+            let mut req: smb2_query_directory_request = zeroed();
+            req.file_information_class = SMB2_FILE_ID_FULL_DIRECTORY_INFORMATION as _;
+            req.file_id = self.file_id;
+            req.name = self.pattern.as_ptr();
+            req.output_buffer_length = DIRECTORY_PAGE_SIZE;
+            let cb_data = QueryDirectoryCbData{sync: SyncCbData{is_finished: false, status: 0}, output: Vec::new()};
+            let cb_data = queue_pdu(ctx, cb_data, |cb_data| smb2_cmd_query_directory_async(ctx, &mut req, Some(query_directory_cb), cb_data))?;
+            wait_for_replies(ctx, &[&cb_data])?;
+            let cb_data = cb_data.into_inner();
+            match check_ntstatus(cb_data.sync.status) {
+                Err(_) if cb_data.sync.status as u32 == SMB2_STATUS_NO_MORE_FILES => return Ok(false),
+                res => res?,
+            }
+            self.page.extend(decode_full_directory_information(&cb_data.output)?);
+            Ok(true)
+        }
+    }
 }
 
 impl Drop for SmbDirectory {
@@ -200,7 +983,7 @@ impl Drop for SmbDirectory {
             unsafe {
                 let ctx_ref = using_mutex!(self.smb);
                 let ctx = *ctx_ref;
-                smb2_closedir(ctx, self.handle);
+                smb2_close(ctx, self.handle);
             }
         }
     }
@@ -226,7 +1009,13 @@ impl Drop for SmbFile {
 
 extern "C" fn smb_notify_change_callback(ctx: *mut smb2_context, status: i32, info_handle: *mut c_void, cb_data: *mut c_void) {
     if status as u32 == SMB2_STATUS_CANCELLED {
-        println!("smb_notify_change_callback - cancelled");
+        log::debug!("smb_notify_change_callback - cancelled");
+        return;
+    }
+    if status != 0 {
+        log::error!("smb_notify_change_callback - status = {:#x}", status as u32);
+        let cb = unsafe { &*cb_data.cast::<NotifyChangeCallback>() };
+        cb.status.set(status);
         return;
     }
 
@@ -234,23 +1023,107 @@ extern "C" fn smb_notify_change_callback(ctx: *mut smb2_context, status: i32, in
     let cb = unsafe { Box::from_raw(cb_ptr) };
     let change_handle = info_handle.cast::<smb2_file_notify_change_information>();
     let change_vec: NotifyChangeInformationVec = change_handle.into();
-    change_vec.changes.into_iter().for_each(|info| cb.call(info.path, info.action.to_string(), info.from_path));
+    let mut changes = change_vec.changes;
+    // XXX: the old and new names of a rename may arrive in separate responses, so an old name ending
+    // one response is held back until the next one shows whether its new name follows
+    if let Some(old_name) = cb.pending_old_name.take() {
+        match changes.first_mut() {
+            Some(first) if first.action == SmbChangeNotifyAction::RenamedNewName => first.from_path = Some(old_name),
+            _ => cb.call_with_kind(old_name, SmbChangeNotifyAction::RenamedOldName, None),
+        }
+    }
+    if changes.last().is_some_and(|last| last.action == SmbChangeNotifyAction::RenamedOldName) {
+        cb.pending_old_name.replace(changes.pop().map(|last| last.path));
+    }
+    changes.into_iter().for_each(|info| cb.call_with_kind(info.path, info.action, info.from_path));
     unsafe { free_smb2_file_notify_change_information(ctx, change_handle); }
     std::mem::forget(cb); // XXX: prevent execution of NotifyChangeCallback::drop
 }
 
+/// Services the notify requests of `watches` until cancelled, the connection fails or the server
+/// rejects a request.
+unsafe fn watch_changes(ctx: *mut smb2_context, watches: &[(SmbChangeNotifyFileFilter, *mut NotifyChangeCallback)], notify_flags: SmbChangeNotifyFlags, ready_tx: &Sender<bool>, cancellation: &SmbCancellation) -> Result<()> {
+    let mut pfds = [
+        libc::pollfd{fd: 0, events: 0, revents: 0},
+        libc::pollfd{fd: cancellation.read_fd.as_raw_fd(), events: libc::POLLIN, revents: 0},
+    ];
+    let _ = ready_tx.send(true);
+    let mut count = 0_usize;
+    const ITERATIONS_BEFORE_REFRESH: usize = 16;
+    while !cancellation.is_cancelled() {
+        if count % ITERATIONS_BEFORE_REFRESH == 0 {
+            for (request_filter, cb_data_ptr) in watches {
+                let ret = smb2_notify_change_filehandle_async(ctx, (**cb_data_ptr).fh, notify_flags.bits(), request_filter.bits(), 1, Some(smb_notify_change_callback), cb_data_ptr.cast::<c_void>());
+                if ret < 0 {
+                    log::error!("Smb notify_change - called smb2_notify_change_filehandle_async - ret = {:?}", ret);
+                    return check_retcode(ctx, ret);
+                }
+            }
+        }
+
+        let fd = smb2_get_fd(ctx);
+        if fd < 0 {
+            log::error!("Smb notify_change - bad fd returned from smb2_get_fd");
+            break;
+        }
+
+        pfds[0].fd = fd;
+        pfds[0].events = smb2_which_events(ctx) as libc::c_short;
+        pfds[0].revents = 0;
+        pfds[1].revents = 0;
+        let ret = libc::poll(pfds.as_mut_ptr(), pfds.len() as libc::nfds_t, 1000);
+        if ret < 0 {
+            log::error!("Smb notify_change - called libc::poll - ret = {:?}", ret);
+            break;
+        }
+        if pfds[1].revents != 0 {
+            break;
+        }
+        if pfds[0].revents != 0 {
+            let ret = smb2_service(ctx, pfds[0].revents.into());
+            if ret < 0 {
+                log::error!("Smb notify_change - called smb2_service - ret = {:?}", ret);
+                break;
+            }
+            if let Some(status) = watches.iter().map(|(_, cb_data_ptr)| (**cb_data_ptr).status.get()).find(|status| *status != 0) {
+                return check_ntstatus(status);
+            }
+        }
+        count += 1;
+    }
+    Ok(())
+}
+
 pub trait SmbNotifyChangeCallback {
-    fn call(&self, path: String, action: String, from_path: Option<String>);
+    fn call(&self, path: String, action: String, from_path: Option<String>, kind: Option<EntryType>);
 }
 
 struct NotifyChangeCallback {
-    inner: Box<dyn SmbNotifyChangeCallback>,
+    inner: Arc<dyn SmbNotifyChangeCallback>,
     smb: Arc<SmbPtr>,
     fh: *mut smb2fh,
+    pending_old_name: RefCell<Option<String>>,
+    /// Kind of the entries whose names change in the notifications of this request, if known.
+    name_kind: Option<EntryType>,
+    /// NT status of the last response, should the server have failed the request.
+    status: Cell<i32>,
+}
+
+impl NotifyChangeCallback {
+    fn call_with_kind(&self, path: String, action: SmbChangeNotifyAction, from_path: Option<String>) {
+        let kind = match action {
+            SmbChangeNotifyAction::Added | SmbChangeNotifyAction::Removed | SmbChangeNotifyAction::RenamedOldName | SmbChangeNotifyAction::RenamedNewName => self.name_kind.clone(),
+            _ => None,
+        };
+        self.inner.call(path, action.to_string(), from_path, kind);
+    }
 }
 
 impl Drop for NotifyChangeCallback {
     fn drop(&mut self) {
+        if let Some(old_name) = self.pending_old_name.take() {
+            self.call_with_kind(old_name, SmbChangeNotifyAction::RenamedOldName, None);
+        }
         if !self.fh.is_null() {
             unsafe {
                 let ctx_ref = using_mutex!(self.smb);
@@ -261,27 +1134,22 @@ impl Drop for NotifyChangeCallback {
     }
 }
 
-impl SmbNotifyChangeCallback for NotifyChangeCallback {
-    fn call(&self, path: String, action: String, from_path: Option<String>) {
-        self.inner.call(path, action, from_path);
-    }
-}
-
 struct NotifyChangeInformation {
     path: String,
     action: SmbChangeNotifyAction,
     from_path: Option<String>,
 }
 
-impl Into<NotifyChangeInformation> for *mut smb2_file_notify_change_information {
-    fn into(self) -> NotifyChangeInformation {
-        let file_path = unsafe { CStr::from_ptr((*self).name) };
-        let mut path = file_path.to_string_lossy().into_owned();
-        path = path.replace("\\", "/");
+impl TryFrom<*mut smb2_file_notify_change_information> for NotifyChangeInformation {
+    type Error = std::str::Utf8Error;
 
-        let int_action = unsafe { (*self).action };
+    fn try_from(info: *mut smb2_file_notify_change_information) -> std::result::Result<Self, Self::Error> {
+        let file_path = unsafe { CStr::from_ptr((*info).name) };
+        let path = file_path.to_str()?.replace("\\", "/");
+
+        let int_action = unsafe { (*info).action };
         let action = SmbChangeNotifyAction::from(int_action).unwrap();
-        NotifyChangeInformation{path, action, from_path: None}
+        Ok(NotifyChangeInformation{path, action, from_path: None})
     }
 }
 
@@ -295,7 +1163,16 @@ impl Into<NotifyChangeInformationVec> for *mut smb2_file_notify_change_informati
         let mut next = self;
         while !next.is_null() {
             let len = changes.len();
-            let mut change: NotifyChangeInformation = next.into();
+            // XXX: names libsmb2 couldn't convert to UTF-8 can't be reopened, so such changes are dropped
+            // rather than reported under a name that doesn't exist
+            let mut change = match NotifyChangeInformation::try_from(next) {
+                Ok(change) => change,
+                Err(e) => {
+                    log::error!("dropping notify change for name {:?} that is not valid UTF-8: {}", unsafe { CStr::from_ptr((*next).name) }, e);
+                    next = unsafe { (*next).next };
+                    continue;
+                }
+            };
             if change.action == SmbChangeNotifyAction::RenamedNewName && len > 0 && changes[len-1].action == SmbChangeNotifyAction::RenamedOldName {
                 change.from_path = Some(changes.pop().unwrap().path);
             }
@@ -311,6 +1188,27 @@ pub struct SmbUrl {
 }
 
 
+impl SmbUrl {
+    fn component(ptr: *const c_char) -> Option<String> {
+        match ptr.is_null() {
+            true => None,
+            false => Some(unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned()),
+        }
+    }
+
+    pub fn server(&self) -> Option<String> {
+        Self::component(unsafe { (*self.url).server })
+    }
+
+    pub fn share(&self) -> Option<String> {
+        Self::component(unsafe { (*self.url).share })
+    }
+
+    pub fn path(&self) -> Option<String> {
+        Self::component(unsafe { (*self.url).path })
+    }
+}
+
 impl Drop for SmbUrl {
     fn drop(&mut self) {
         if !self.url.is_null() {
@@ -333,6 +1231,36 @@ impl Smb {
         }
     }
 
+    /// Whether the context still has a socket to the server.
+    /// This does not send anything over the wire.
+    pub fn is_connected(&self) -> bool {
+        let ctx_ref = using_mutex!(self.context);
+        let ctx = *ctx_ref;
+        unsafe { smb2_get_fd(ctx) >= 0 }
+    }
+
+    /// Sets `TCP_NODELAY` and `SO_KEEPALIVE` on the socket to the server, leaving those given as
+    /// `None` as they are. Must be called after connecting, as libsmb2 only opens the socket then.
+    pub fn set_tcp_options(&self, no_delay: Option<bool>, keep_alive: Option<bool>) -> Result<()> {
+        let ctx_ref = using_mutex!(self.context);
+        let ctx = *ctx_ref;
+        unsafe {
+            let fd = smb2_get_fd(ctx);
+            if fd < 0 {
+                return Err(Error::new(ErrorKind::NotConnected, "not connected"));
+            }
+            for (level, name, value) in [(libc::IPPROTO_TCP, libc::TCP_NODELAY, no_delay), (libc::SOL_SOCKET, libc::SO_KEEPALIVE, keep_alive)] {
+                let Some(value) = value else { continue };
+                let value = value as libc::c_int;
+                let len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+                if libc::setsockopt(fd, level, name, (&value as *const libc::c_int).cast::<c_void>(), len) < 0 {
+                    return Err(Error::last_os_error());
+                }
+            }
+            Ok(())
+        }
+    }
+
     pub fn set_version(&self, version: u32) -> Result<()> {
         unsafe {
             let ctx_ref = using_mutex!(self.context);
@@ -342,6 +1270,17 @@ impl Smb {
         }
     }
 
+    /// Sets the signing flags sent in the negotiate and session setup requests.
+    /// Must be called before connecting.
+    pub fn set_security_mode(&self, security_mode: SmbSecurityMode) -> Result<()> {
+        unsafe {
+            let ctx_ref = using_mutex!(self.context);
+            let ctx = *ctx_ref;
+            smb2_set_security_mode(ctx, security_mode.bits());
+            Ok(())
+        }
+    }
+
     /*
     pub fn access(&self, path: &Path, mode: i32) -> Result<()> {
         let path = self.get_path_cstr(path)?;
@@ -393,80 +1332,89 @@ impl Smb {
     */
 
     /// Supported flags:
-    /// O_APPEND
     /// O_SYNC
     /// O_EXCL
     /// O_TRUNC
-    pub fn create(&mut self, path: &Path, flags: OFlag, _mode: Mode) -> Result<SmbFile> {
+    /// Create a file, opening it if it exists unless `O_EXCL` is set, in which case an
+    /// existing file fails with `ErrorKind::AlreadyExists` and `O_TRUNC` has nothing to truncate.
+    /// Without `O_EXCL`, `O_TRUNC` empties an existing file.
+    ///
+    /// SMB has no permission bits, so `mode` only decides whether a newly created file gets the
+    /// read-only attribute, which it does when it grants no write permission. `attributes` are
+    /// further DOS attributes (MS-FSCC 2.6) sent with the create request, so that the file never
+    /// exists without them. Existing files keep their attributes.
+    pub fn create(&mut self, path: &Path, flags: OFlag, mode: Mode, attributes: u32) -> Result<SmbFile> {
         let path = self.get_resolved_path_cstr(path)?;
         let ctx_ref = using_mutex!(self.context);
         let ctx = *ctx_ref;
         unsafe {
             let mut smb_flags = flags;
             smb_flags.insert(OFlag::O_CREAT);
-            let file_handle = smb2_open(ctx, path.as_ptr(), smb_flags.bits());
-            if file_handle.is_null() {
+            // XXX: smb2_open only reports a message and takes no attributes, the raw reply carries the status the error kind derives from
+            let mut req = create_request(&path, smb_flags);
+            let mut file_attributes = attributes;
+            if !mode.intersects(Mode::S_IWUSR | Mode::S_IWGRP | Mode::S_IWOTH) {
+                file_attributes |= FILE_ATTRIBUTE_READONLY;
+            }
+            req.file_attributes = file_attributes as _;
+            let mut reply = send_create(ctx, &mut req)?;
+            let handle = smb2_fh_from_file_id(ctx, &mut reply.file_id);
+            if handle.is_null() {
                 check_retcode(ctx, -1)?
             }
-            Ok(SmbFile {
-                smb: Arc::clone(&self.context),
-                handle: file_handle,
-            })
+            Ok(SmbFile{smb: Arc::clone(&self.context), handle})
         }
     }
 
-    pub fn notify_change(&self, path: &Path, notify_flags: SmbChangeNotifyFlags, filter: SmbChangeNotifyFileFilter, cb: Box<dyn SmbNotifyChangeCallback>, ready_tx: &Sender<bool>, cancelled_rx: &Receiver<bool>) {
+    /// Delivers changes under `path` to `cb` until cancelled or the connection fails. Fails with the
+    /// NT status the server returned if it rejects the notify request, as servers lacking CHANGE_NOTIFY do.
+    pub fn notify_change(&self, path: &Path, notify_flags: SmbChangeNotifyFlags, filter: SmbChangeNotifyFileFilter, cb: Box<dyn SmbNotifyChangeCallback>, ready_tx: &Sender<bool>, cancellation: &SmbCancellation) -> Result<()> {
         let path = self.get_resolved_path_cstr(path).unwrap();
         let ctx_ref = using_mutex!(self.context);
         let ctx = *ctx_ref;
-        unsafe {
-            let fh = smb2_open(ctx, path.as_ptr(), libc::O_DIRECTORY);
+        // XXX: notifications do not tell files from directories, so directory name changes are watched
+        // through a handle of their own, leaving name changes reported through the other one to files
+        let mut requests = Vec::new();
+        let other_filter = filter - SmbChangeNotifyFileFilter::CHANGE_DIR_NAME;
+        if !other_filter.is_empty() {
+            requests.push((other_filter, filter.contains(SmbChangeNotifyFileFilter::CHANGE_FILE_NAME).then_some(EntryType::File)));
+        }
+        if filter.contains(SmbChangeNotifyFileFilter::CHANGE_DIR_NAME) {
+            requests.push((SmbChangeNotifyFileFilter::CHANGE_DIR_NAME, Some(EntryType::Directory)));
+        }
+        let cb: Arc<dyn SmbNotifyChangeCallback> = Arc::from(cb);
+        let mut watches: Vec<(SmbChangeNotifyFileFilter, *mut NotifyChangeCallback)> = Vec::new();
+        let mut result = Ok(());
+        for (request_filter, name_kind) in requests {
+            let fh = unsafe { smb2_open(ctx, path.as_ptr(), libc::O_DIRECTORY) };
             if fh.is_null() {
-                println!("Smb notify_change - smb2_open returned null - Error::last_os_error() = {:?}", Error::last_os_error());
-                return;
+                let err = Error::last_os_error();
+                log::error!("Smb notify_change - smb2_open returned null - Error::last_os_error() = {:?}", err);
+                let _ = ready_tx.send(false);
+                result = Err(err);
+                break;
             }
-
-            let cb_data = Box::new(NotifyChangeCallback{inner: cb, smb: Arc::clone(&self.context), fh});
-            let cb_data_ptr = Box::into_raw(cb_data);
-            let pfd = Box::new(libc::pollfd{fd: 0, events: 0, revents: 0});
-            let pfd_ptr = Box::into_raw(pfd);
-            let _ = ready_tx.send(true);
-            let mut count = 0_usize;
-            const ITERATIONS_BEFORE_REFRESH: usize = 16;
-            while cancelled_rx.try_recv().is_err() { // FIXME: more stringent check? (taking into account dropped sender?)
-                if count % ITERATIONS_BEFORE_REFRESH == 0 {
-                    let ret = smb2_notify_change_filehandle_async(ctx, fh, notify_flags.bits(), filter.bits(), 1, Some(smb_notify_change_callback), cb_data_ptr.cast::<c_void>());
-                    if ret < 0 {
-                        println!("Smb notify_change - called smb2_notify_change_filehandle_async - ret = {:?}", ret);
-                        break;
-                    }
-                }
-
-                let fd = smb2_get_fd(ctx);
-                if fd < 0 {
-                    println!("Smb notify_change - bad fd returned from smb2_get_fd");
-                    break;
-                }
-
-                (*pfd_ptr).fd = fd;
-                (*pfd_ptr).events = smb2_which_events(ctx) as libc::c_short;
-                (*pfd_ptr).revents = 0;
-                let ret = libc::poll(pfd_ptr, 1, 1000);
-                if ret < 0 {
-                    println!("Smb notify_change - called libc::poll - ret = {:?}", ret);
-                    break;
-                }
-                if (*pfd_ptr).revents != 0 {
-                    let ret = smb2_service(ctx, (*pfd_ptr).revents.into());
-                    if ret < 0 {
-                        println!("Smb notify_change - called smb2_service - ret = {:?}", ret);
-                        break;
-                    }
-                }
-                count += 1;
+            let cb_data = Box::new(NotifyChangeCallback{inner: Arc::clone(&cb), smb: Arc::clone(&self.context), fh, pending_old_name: RefCell::new(None), name_kind, status: Cell::new(0)});
+            watches.push((request_filter, Box::into_raw(cb_data)));
+        }
+        if result.is_ok() {
+            result = unsafe { watch_changes(ctx, &watches, notify_flags, ready_tx, cancellation) };
+        }
+        unsafe {
+            // XXX: the handles are closed while the callbacks are still alive, as closing one completes
+            // its pending notify request and so runs its callback
+            for (_, cb_data_ptr) in &watches {
+                let fh = std::mem::replace(&mut (**cb_data_ptr).fh, std::ptr::null_mut());
+                smb2_close(ctx, fh);
             }
-            let _pfd_revived = Box::from_raw(pfd_ptr); // XXX: so as to free up the memory when this falls out of scope
         }
+        // XXX: dropping a callback reports a rename whose new name never arrived, and must happen
+        // without holding the context, which its drop would otherwise lock again
+        drop(ctx_ref);
+        for (_, cb_data_ptr) in watches {
+            drop(unsafe { Box::from_raw(cb_data_ptr) });
+        }
+        result
     }
 
     /*
@@ -548,28 +1496,54 @@ impl Smb {
     }
     */
 
-    /*
-    pub fn lstat64(&self, path: &Path) -> Result<smb2_stat_64> {
-        let path = self.get_path_cstr(path)?;
+    /// Same as stat64, but describes a symlink itself rather than its target,
+    /// by opening the path as a reparse point.
+    pub fn lstat64(&self, path: &Path) -> Result<SmbStat> {
+        self.stat_paths(&[path], SMB2_FILE_OPEN_REPARSE_POINT)?.remove(0)
+    }
+
+    /// Returns the DOS attributes of a file or directory (MS-FSCC 2.6), as reported when opening it.
+    /// The path is opened without recalling offline data, so this is cheap on cloud-tiered shares,
+    /// and without following reparse points, so these describe a symlink or junction itself.
+    pub fn get_attributes(&self, path: &Path) -> Result<u32> {
+        let cpath = self.get_resolved_path_cstr(path)?;
         let ctx_ref = using_mutex!(self.context);
         let ctx = *ctx_ref;
         unsafe {
-            let mut stat_buf: smb2_stat_64 = zeroed();
-            check_retcode(
-                ctx,
-                smb2_lstat64(ctx, path.as_ptr(), &mut stat_buf),
-            )?;
-            Ok(stat_buf)
+            let mut create_req = create_request(&cpath, OFlag::O_RDONLY);
+            create_req.desired_access = SMB2_FILE_READ_ATTRIBUTES as _;
+            create_req.create_options = (SMB2_FILE_OPEN_NO_RECALL | SMB2_FILE_OPEN_REPARSE_POINT) as _;
+            let mut create_reply = send_create(ctx, &mut create_req)?;
+            let fh = smb2_fh_from_file_id(ctx, &mut create_reply.file_id);
+            if !fh.is_null() {
+                smb2_close(ctx, fh);
+            }
+            Ok(create_reply.file_attributes)
         }
     }
-    */
 
-    pub fn mkdir(&self, path: &Path) -> Result<()> {
+    /// Create a directory. Like `create`, the only part of the mode SMB2 can carry is the owner-write
+    /// bit: without it the directory gets FILE_ATTRIBUTE_READONLY, which Samba maps to a mode without
+    /// write bits but Windows only treats as a hint. Otherwise the share's permissions apply.
+    pub fn mkdir(&self, path: &Path, mode: Mode) -> Result<()> {
         let path = self.get_resolved_path_cstr(path)?;
         let ctx_ref = using_mutex!(self.context);
         let ctx = *ctx_ref;
         unsafe {
-            check_retcode(ctx, smb2_mkdir(ctx, path.as_ptr()))?;
+            // XXX: smb2_mkdir takes no attributes, so the create request it sends is made here
+            let mut create_req = create_request(&path, OFlag::O_CREAT | OFlag::O_EXCL);
+            create_req.desired_access = SMB2_FILE_READ_ATTRIBUTES as _;
+            create_req.create_options = SMB2_FILE_DIRECTORY_FILE as _;
+            let mut file_attributes = FILE_ATTRIBUTE_DIRECTORY;
+            if !mode.intersects(Mode::S_IWUSR | Mode::S_IWGRP | Mode::S_IWOTH) {
+                file_attributes |= FILE_ATTRIBUTE_READONLY;
+            }
+            create_req.file_attributes = file_attributes as _;
+            let mut create_reply = send_create(ctx, &mut create_req)?;
+            let fh = smb2_fh_from_file_id(ctx, &mut create_reply.file_id);
+            if !fh.is_null() {
+                smb2_close(ctx, fh);
+            }
             Ok(())
         }
     }
@@ -598,14 +1572,16 @@ impl Smb {
         }
     }
 
+    /// libsmb2 keeps a copy of the password until `smb2_destroy_context`, the one made here is zeroized.
     pub fn set_password(&self, password: &str) -> Result<()> {
-        let password = CString::new(password.as_bytes())?;
+        let mut password = CString::new(password.as_bytes())?.into_bytes_with_nul();
         let ctx_ref = using_mutex!(self.context);
         let ctx = *ctx_ref;
         unsafe {
-            smb2_set_password(ctx, password.as_ptr());
-            Ok(())
+            smb2_set_password(ctx, password.as_ptr() as *const c_char);
         }
+        zeroize(&mut password);
+        Ok(())
     }
 
     pub fn set_domain(&self, domain: &str) -> Result<()> {
@@ -618,6 +1594,29 @@ impl Smb {
         }
     }
     
+    pub fn set_workstation(&self, workstation: &str) -> Result<()> {
+        let workstation = CString::new(workstation.as_bytes())?;
+        let ctx_ref = using_mutex!(self.context);
+        let ctx = *ctx_ref;
+        unsafe {
+            smb2_set_workstation(ctx, workstation.as_ptr());
+            Ok(())
+        }
+    }
+
+    /// Replaces the random client GUID sent in the negotiate request, in its wire byte order.
+    /// Must be called before connecting.
+    pub fn set_client_guid(&self, guid: &[u8; 16]) -> Result<()> {
+        let ctx_ref = using_mutex!(self.context);
+        let ctx = *ctx_ref;
+        unsafe {
+            // libsmb2 has no setter, but hands out the 16 byte buffer it keeps in the context.
+            let client_guid = smb2_get_client_guid(ctx) as *mut u8;
+            std::ptr::copy_nonoverlapping(guid.as_ptr(), client_guid, guid.len());
+            Ok(())
+        }
+    }
+
     pub fn connect_share(&self, server: &str, share: &str, user: &str) -> Result<()> {
         let server = CString::new(server.as_bytes())?;
         let share = CString::new(share.as_bytes())?;
@@ -662,18 +1661,124 @@ impl Smb {
         }
     }
 
+    /// Open a file for atomic appends. The handle is granted FILE_APPEND_DATA but not FILE_WRITE_DATA,
+    /// so that `SmbFile::append` writes land at end-of-file on servers that honor this.
+    pub fn open_append(&mut self, path: &Path) -> Result<SmbFile> {
+        let path = self.get_resolved_path_cstr(path)?;
+        let ctx_ref = using_mutex!(self.context);
+        let ctx = *ctx_ref;
+        unsafe {
+            let mut create_req = create_request(&path, OFlag::O_WRONLY);
+            create_req.desired_access = (SMB2_FILE_APPEND_DATA | SMB2_FILE_READ_ATTRIBUTES | SMB2_FILE_WRITE_ATTRIBUTES) as _;
+            let mut create_reply = send_create(ctx, &mut create_req)?;
+            let handle = smb2_fh_from_file_id(ctx, &mut create_reply.file_id);
+            if handle.is_null() {
+                check_retcode(ctx, -1)?
+            }
+            Ok(SmbFile{smb: Arc::clone(&self.context), handle})
+        }
+    }
+
+    /// Open a file or directory with no access but FILE_WRITE_ATTRIBUTES, for setting its times,
+    /// which needs neither read nor write access to its data.
+    pub fn open_attributes(&mut self, path: &Path) -> Result<SmbFile> {
+        let path = self.get_resolved_path_cstr(path)?;
+        let ctx_ref = using_mutex!(self.context);
+        let ctx = *ctx_ref;
+        unsafe {
+            let mut create_req = create_request(&path, OFlag::O_RDONLY);
+            create_req.desired_access = SMB2_FILE_WRITE_ATTRIBUTES as _;
+            create_req.create_options = 0;
+            let mut create_reply = send_create(ctx, &mut create_req)?;
+            let handle = smb2_fh_from_file_id(ctx, &mut create_reply.file_id);
+            if handle.is_null() {
+                check_retcode(ctx, -1)?
+            }
+            Ok(SmbFile{smb: Arc::clone(&self.context), handle})
+        }
+    }
+
+    /// Read the security descriptor (owner, group and DACL) of a file or directory,
+    /// encoded in self-relative format.
+    pub fn get_security(&self, path: &Path) -> Result<Vec<u8>> {
+        let cpath = self.get_resolved_path_cstr(path)?;
+        let ctx_ref = using_mutex!(self.context);
+        let ctx = *ctx_ref;
+        unsafe {
+            let mut create_req = create_request(&cpath, OFlag::O_RDONLY);
+            create_req.desired_access = (READ_CONTROL | SMB2_FILE_READ_ATTRIBUTES) as _;
+            create_req.create_options = 0;
+            let create_reply = send_create(ctx, &mut create_req)?;
+            let mut req: smb2_query_info_request = zeroed();
+            req.info_type = SMB2_0_INFO_SECURITY as _;
+            req.output_buffer_length = 65535;
+            req.additional_information = OWNER_SECURITY_INFORMATION | GROUP_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION;
+            req.file_id = create_reply.file_id;
+            let cb_data = SecurityCbData{sync: SyncCbData{is_finished: false, status: 0}, descriptor: Vec::new()};
+            let res = queue_pdu(ctx, cb_data, |cb_data| smb2_cmd_query_info_async(ctx, &mut req, Some(security_cb), cb_data))
+                .and_then(|cb_data| wait_for_reply(ctx, cb_data));
+            let mut file_id = create_reply.file_id;
+            let fh = smb2_fh_from_file_id(ctx, &mut file_id);
+            if !fh.is_null() {
+                smb2_close(ctx, fh);
+            }
+            res.map(|cb_data| cb_data.descriptor)
+        }
+    }
+
+    /// Apply a self-relative security descriptor to a file or directory.
+    /// Only the parts present in the descriptor (owner, group, DACL) are changed.
+    pub fn set_security(&self, path: &Path, descriptor: &[u8]) -> Result<()> {
+        let mut decoded = decode_security_descriptor(descriptor)?;
+        let cpath = self.get_resolved_path_cstr(path)?;
+        let file = {
+            let ctx_ref = using_mutex!(self.context);
+            let ctx = *ctx_ref;
+            unsafe {
+                let mut create_req = create_request(&cpath, OFlag::O_RDONLY);
+                create_req.desired_access = (READ_CONTROL | WRITE_DAC | WRITE_OWNER | SMB2_FILE_READ_ATTRIBUTES) as _;
+                create_req.create_options = 0;
+                let mut create_reply = send_create(ctx, &mut create_req)?;
+                let handle = smb2_fh_from_file_id(ctx, &mut create_reply.file_id);
+                if handle.is_null() {
+                    check_retcode(ctx, -1)?
+                }
+                SmbFile{smb: Arc::clone(&self.context), handle}
+            }
+        };
+        let sd_ptr = (&mut *decoded.sd as *mut smb2_security_descriptor).cast::<c_void>();
+        file.set_info(SMB2_0_INFO_SECURITY, 0, decoded.additional_information, sd_ptr)
+    }
+
+    /// Open a directory for listing. Entries are queried from the server in pages as the
+    /// returned directory is iterated, rather than all up front as smb2_opendir does.
     pub fn opendir(&mut self, path: &Path) -> Result<SmbDirectory> {
+        self.opendir_pattern(path, "*")
+    }
+
+    /// Open a directory for listing only the entries whose names match `pattern`, which is sent
+    /// with each query-directory request so that the server does the filtering (MS-FSCC 2.1.4.4).
+    pub fn opendir_pattern(&mut self, path: &Path, pattern: &str) -> Result<SmbDirectory> {
+        let pattern = CString::new(pattern)?;
         let cpath = self.get_resolved_path_cstr(path)?;
         let ctx_ref = using_mutex!(self.context);
         let ctx = *ctx_ref;
         unsafe {
-            let dir_handle = check_mut_ptr(smb2_opendir(ctx, cpath.as_ptr()))?;
-            if dir_handle.is_null() {
+            let mut create_req = create_request(&cpath, OFlag::O_RDONLY);
+            create_req.desired_access = (SMB2_FILE_LIST_DIRECTORY | SMB2_FILE_READ_ATTRIBUTES) as _;
+            create_req.create_options = SMB2_FILE_DIRECTORY_FILE as _;
+            let mut create_reply = send_create(ctx, &mut create_req)?;
+            let handle = smb2_fh_from_file_id(ctx, &mut create_reply.file_id);
+            if handle.is_null() {
                 check_retcode(ctx, -1)?
             }
             Ok(SmbDirectory {
                 smb: Arc::clone(&self.context),
-                handle: dir_handle,
+                handle,
+                file_id: create_reply.file_id,
+                pattern,
+                page: VecDeque::new(),
+                finished: false,
             })
         }
     }
@@ -740,7 +1845,7 @@ impl Smb {
     }
     
 
-    pub fn parse_url_mount(&mut self, url: &str, user: Option<String>, password: Option<String>, domain: Option<String>) -> Result<()> {
+    pub fn parse_url_mount(&mut self, url: &str, user: Option<String>, password: Option<&str>, domain: Option<String>) -> Result<()> {
         unsafe {
             match user {
                 Some(user_string) => {
@@ -750,8 +1855,7 @@ impl Smb {
                 None => {},
             };
             match password {
-                Some(password_string) => {
-                    let pstr = password_string.as_str();
+                Some(pstr) => {
                     let _ = self.set_password(pstr);
                 },
                 None => {},
@@ -796,6 +1900,10 @@ impl Smb {
     pub fn get_resolved_path_cstr(&self, path: &Path) -> Result<CString> {
         let mut real_path = path;
         match &self.base_path {
+            // The empty path names the root, joining which would leave a trailing separator.
+            Some(parent_path) if path.as_os_str().is_empty() => {
+                return Ok(CString::new(parent_path.as_str())?);
+            },
             Some(parent_path) => {
                 let path_parent_path = Path::new(parent_path.as_str());
                 let real_path_pathbuf = path_parent_path.join(PathBuf::from(path));
@@ -843,6 +1951,31 @@ impl Smb {
         }
     }
 
+    /// Rename a file or directory, replacing an existing `newpath` only if `replace_if_exists` is set.
+    pub fn rename_replace(&self, oldpath: &Path, newpath: &Path, replace_if_exists: bool) -> Result<()> {
+        let old_path = self.get_resolved_path_cstr(oldpath)?;
+        let new_path = CString::new(self.get_resolved_path_cstr(newpath)?.as_bytes().iter().map(|b| if *b == b'/' { b'\\' } else { *b }).collect::<Vec<u8>>())?;
+        let file = {
+            let ctx_ref = using_mutex!(self.context);
+            let ctx = *ctx_ref;
+            unsafe {
+                let mut create_req = create_request(&old_path, OFlag::O_RDONLY);
+                create_req.desired_access = (DELETE | SMB2_FILE_READ_ATTRIBUTES) as _;
+                create_req.create_options = 0;
+                let mut create_reply = send_create(ctx, &mut create_req)?;
+                let handle = smb2_fh_from_file_id(ctx, &mut create_reply.file_id);
+                if handle.is_null() {
+                    check_retcode(ctx, -1)?
+                }
+                SmbFile{smb: Arc::clone(&self.context), handle}
+            }
+        };
+        let mut info: smb2_file_rename_info = unsafe { zeroed() };
+        info.replace_if_exist = replace_if_exists as u8;
+        info.file_name = new_path.as_ptr();
+        file.set_info(SMB2_0_INFO_FILE, SMB2_FILE_RENAME_INFORMATION, 0, (&mut info as *mut smb2_file_rename_info).cast::<c_void>())
+    }
+
     pub fn rmdir(&self, path: &Path) -> Result<()> {
         let path = self.get_resolved_path_cstr(path)?;
         let ctx_ref = using_mutex!(self.context);
@@ -864,17 +1997,72 @@ impl Smb {
     }
 
 
-    pub fn stat64(&self, path: &Path) -> Result<smb2_stat_64> {
-        let path = self.get_resolved_path_cstr(path)?;
+    /// Stats a path, following symlinks, along with its DOS attributes.
+    pub fn stat64(&self, path: &Path) -> Result<SmbStat> {
+        self.stat_paths(&[path], 0)?.remove(0)
+    }
+
+    /// Stats each of `paths`, sending all the requests before waiting for any reply so that their
+    /// round trips overlap rather than add up. Failing to stat one path doesn't fail the others;
+    /// the outer error is for the connection failing while waiting.
+    pub fn stat_many(&self, paths: &[&Path]) -> Result<Vec<Result<SmbStat>>> {
+        self.stat_paths(paths, 0)
+    }
+
+    /// Opens each of `paths` with `create_options`, queries its FILE_ALL_INFORMATION, which holds the
+    /// attributes along with what libsmb2's stat reports, and closes it again. Each step is sent for
+    /// all the paths before waiting for any of their replies.
+    fn stat_paths(&self, paths: &[&Path], create_options: u32) -> Result<Vec<Result<SmbStat>>> {
+        let paths: Vec<Result<CString>> = paths.iter().map(|path| self.get_resolved_path_cstr(path)).collect();
         let ctx_ref = using_mutex!(self.context);
         let ctx = *ctx_ref;
         unsafe {
-            let mut stat_buf: smb2_stat_64 = zeroed();
-            check_retcode(
-                ctx,
-                smb2_stat(ctx, path.as_ptr(), &mut stat_buf),
-            )?;
-            Ok(stat_buf)
+            let mut creates: Vec<Result<QueuedCbData<CreateCbData>>> = Vec::with_capacity(paths.len());
+            for path in paths {
+                creates.push(path.and_then(|path| {
+                    let mut create_req = create_request(&path, OFlag::O_RDONLY);
+                    create_req.desired_access = (SMB2_FILE_READ_ATTRIBUTES | SMB2_FILE_READ_EA) as _;
+                    create_req.create_options = create_options as _;
+                    let cb_data = CreateCbData{sync: SyncCbData{is_finished: false, status: 0}, reply: None};
+                    queue_pdu(ctx, cb_data, |cb_data| smb2_cmd_create_async(ctx, &mut create_req, Some(create_cb), cb_data))
+                }));
+            }
+            let waiting: Vec<&QueuedCbData<CreateCbData>> = creates.iter().flatten().collect();
+            wait_for_replies(ctx, &waiting)?;
+
+            let mut stats: Vec<Result<SmbStat>> = Vec::with_capacity(creates.len());
+            let mut opened: Vec<smb2_file_id> = Vec::new();
+            let mut queries: Vec<(usize, QueuedCbData<StatCbData>)> = Vec::new();
+            for create in creates {
+                let file_id = create.and_then(|cb_data| {
+                    let cb_data = cb_data.into_inner();
+                    check_ntstatus(cb_data.sync.status)?;
+                    cb_data.reply.map(|reply| reply.file_id).ok_or_else(|| Error::new(ErrorKind::Other, "missing create reply"))
+                });
+                match file_id.and_then(|file_id| {
+                    opened.push(file_id);
+                    queue_query_all_information(ctx, file_id)
+                }) {
+                    Ok(cb_data) => {
+                        queries.push((stats.len(), cb_data));
+                        stats.push(Err(Error::new(ErrorKind::Other, "missing query info reply")));
+                    },
+                    Err(err) => stats.push(Err(err)),
+                }
+            }
+            let waiting: Vec<&QueuedCbData<StatCbData>> = queries.iter().map(|(_, cb_data)| cb_data).collect();
+            wait_for_replies(ctx, &waiting)?;
+            for (index, cb_data) in queries {
+                let cb_data = cb_data.into_inner();
+                stats[index] = check_ntstatus(cb_data.sync.status)
+                    .and_then(|_| cb_data.stat.ok_or_else(|| Error::new(ErrorKind::Other, "missing query info reply")));
+            }
+
+            // Failing to close is no reason to fail stats already made.
+            let closes: Vec<QueuedCbData<SyncCbData>> = opened.into_iter().filter_map(|file_id| queue_close(ctx, file_id).ok()).collect();
+            let waiting: Vec<&QueuedCbData<SyncCbData>> = closes.iter().collect();
+            wait_for_replies(ctx, &waiting)?;
+            Ok(stats)
         }
     }
 
@@ -908,6 +2096,37 @@ impl Smb {
     }
     */
 
+    /// Flushes a directory so that metadata changes within it are durable. Windows only flushes
+    /// directories opened with the rights to add entries, and servers rejecting the flush as
+    /// invalid or unsupported are taken to have nothing to flush. Where those rights are denied,
+    /// e.g. on a read-only share, the directory is opened for listing instead, and a flush the
+    /// server then denies is taken to have nothing to flush too.
+    pub fn fsync_dir(&mut self, path: &Path) -> Result<()> {
+        let cpath = self.get_resolved_path_cstr(path)?;
+        let (dir, may_add) = open_for_fsync_dir(|desired_access| {
+            let ctx_ref = using_mutex!(self.context);
+            let ctx = *ctx_ref;
+            unsafe {
+                let mut create_req = create_request(&cpath, OFlag::O_RDONLY);
+                create_req.desired_access = desired_access as _;
+                create_req.create_options = SMB2_FILE_DIRECTORY_FILE as _;
+                let mut create_reply = send_create(ctx, &mut create_req)?;
+                let handle = smb2_fh_from_file_id(ctx, &mut create_reply.file_id);
+                if handle.is_null() {
+                    check_retcode(ctx, -1)?
+                }
+                Ok(SmbFile{smb: Arc::clone(&self.context), handle})
+            }
+        })?;
+        let ctx_ref = using_mutex!(self.context);
+        let ctx = *ctx_ref;
+        match unsafe { smb2_fsync(ctx, dir.handle) } {
+            code if [-libc::EINVAL, -libc::EOPNOTSUPP, -libc::ENOSYS].contains(&code) => Ok(()),
+            code if code == -libc::EACCES && !may_add => Ok(()),
+            code => check_retcode(ctx, code),
+        }
+    }
+
     pub fn truncate(&self, path: &Path, len: u64) -> Result<()> {
         let path = self.get_resolved_path_cstr(path)?;
         let ctx_ref = using_mutex!(self.context);
@@ -969,6 +2188,12 @@ impl SmbFile {
     }
     */
 
+    /// Reserves `len` bytes of disk space for the file without changing its size.
+    pub fn set_allocation_size(&self, len: u64) -> Result<()> {
+        let mut info = SmbFileAllocationInfo{allocation_size: len};
+        self.set_info(SMB2_0_INFO_FILE, SMB2_FILE_ALLOCATION_INFORMATION, 0, (&mut info as *mut SmbFileAllocationInfo).cast::<c_void>())
+    }
+
     pub fn ftruncate(&self, len: u64) -> Result<()> {
         let ctx_ref = using_mutex!(self.smb);
         let ctx = *ctx_ref;
@@ -978,17 +2203,114 @@ impl SmbFile {
         }
     }
 
-    /// 64 bit version of fstat. All fields are always 64bit.
-    pub fn fstat64(&self) -> Result<smb2_stat_64> {
+    /// 64 bit version of fstat, along with the DOS attributes of the file. All fields are always 64bit.
+    pub fn fstat64(&self) -> Result<SmbStat> {
         let ctx_ref = using_mutex!(self.smb);
         let ctx = *ctx_ref;
         unsafe {
-            let mut stat_buf: smb2_stat_64 = zeroed();
-            check_retcode(
-                ctx,
-                smb2_fstat(ctx, self.handle, &mut stat_buf),
-            )?;
-            Ok(stat_buf)
+            let cb_data = queue_query_all_information(ctx, *smb2_get_file_id(self.handle))?;
+            let cb_data = wait_for_reply(ctx, cb_data)?;
+            cb_data.stat.ok_or_else(|| Error::new(ErrorKind::Other, "missing query info reply"))
+        }
+    }
+
+    /// Set the access, modification and creation times of the file.
+    /// Times that are None are left unchanged.
+    pub fn futimes(&self, atime: Option<SystemTime>, mtime: Option<SystemTime>, btime: Option<SystemTime>) -> Result<()> {
+        let mut info: smb2_file_basic_info = unsafe { zeroed() };
+        info.last_access_time = to_smb2_timeval(atime);
+        info.last_write_time = to_smb2_timeval(mtime);
+        info.creation_time = to_smb2_timeval(btime);
+        self.set_info(SMB2_0_INFO_FILE, SMB2_FILE_BASIC_INFORMATION, 0, (&mut info as *mut smb2_file_basic_info).cast::<c_void>())
+    }
+
+    /// The key naming this file as the source of server-side copies (`copy_chunks`), which other
+    /// connections to the same server may use too.
+    pub fn request_resume_key(&self) -> Result<SmbResumeKey> {
+        let (output, _) = self.ioctl(FSCTL_SRV_REQUEST_RESUME_KEY, &mut [], RESUME_KEY_RESPONSE_SIZE)?;
+        output.get(..RESUME_KEY_SIZE).and_then(|key| key.try_into().ok())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "short SRV_REQUEST_RESUME_KEY reply"))
+    }
+
+    /// Has the server copy the (source offset, target offset, length) `chunks` of the file `source_key`
+    /// names into this one, which must be open for writing, returning the number of bytes written.
+    /// Servers limit the chunks per request and their length, failing requests beyond those.
+    pub fn copy_chunks(&self, source_key: &SmbResumeKey, chunks: &[(u64, u64, u32)]) -> Result<u64> {
+        let mut input = encode_copychunk_copy(source_key, chunks);
+        let (output, _) = self.ioctl(FSCTL_SRV_COPYCHUNK_WRITE, &mut input, COPYCHUNK_RESPONSE_SIZE)?;
+        // SRV_COPYCHUNK_RESPONSE: ChunksWritten, ChunkBytesWritten, TotalBytesWritten
+        match output.get(8..12) {
+            Some(total) => Ok(u32::from_le_bytes(total.try_into().unwrap()) as u64),
+            None => Err(Error::new(ErrorKind::InvalidData, "short SRV_COPYCHUNK_RESPONSE")),
+        }
+    }
+
+    /// Marks the file as sparse, so that ranges zeroed with set_zero_data stop taking up disk space.
+    pub fn set_sparse(&self) -> Result<()> {
+        self.ioctl(FSCTL_SET_SPARSE, &mut [], 0).map(|_| ())
+    }
+
+    /// Zeroes `len` bytes of the file starting at `offset`, deallocating them if the file is sparse.
+    pub fn set_zero_data(&self, offset: u64, len: u64) -> Result<()> {
+        let mut input = encode_range(offset, offset + len);
+        self.ioctl(FSCTL_SET_ZERO_DATA, &mut input, 0).map(|_| ())
+    }
+
+    /// The (offset, length) pairs of the ranges within `len` bytes from `offset` that have disk space
+    /// allocated, in ascending order. Files that are not sparse are reported as one allocated range.
+    pub fn query_allocated_ranges(&self, offset: u64, len: u64) -> Result<Vec<(u64, u64)>> {
+        let end = offset + len;
+        let mut ranges = Vec::new();
+        let mut next = offset;
+        while next < end {
+            let mut input = encode_range(next, end - next);
+            // servers answer a query whose ranges don't all fit with STATUS_BUFFER_OVERFLOW and those that do
+            let max_output = (ALLOCATED_RANGES_PER_QUERY * ALLOCATED_RANGE_SIZE) as u32;
+            let (output, overflowed) = self.ioctl(FSCTL_QUERY_ALLOCATED_RANGES, &mut input, max_output)?;
+            let found = decode_allocated_ranges(&output);
+            match found.last() {
+                Some(&(last_offset, last_length)) => next = last_offset + last_length,
+                None => break,
+            }
+            ranges.extend(found);
+            if !overflowed {
+                break;
+            }
+        }
+        Ok(ranges)
+    }
+
+    /// Sends an FSCTL, returning at most `max_output` bytes of output and whether the server had more.
+    fn ioctl(&self, ctl_code: u32, input: &mut [u8], max_output: u32) -> Result<(Vec<u8>, bool)> {
+        let ctx_ref = using_mutex!(self.smb);
+        let ctx = *ctx_ref;
+        unsafe {
+            let mut req: smb2_ioctl_request = zeroed();
+            req.ctl_code = ctl_code;
+            req.file_id = *smb2_get_file_id(self.handle);
+            req.input_count = input.len() as _;
+            req.input = input.as_mut_ptr().cast::<c_void>();
+            req.max_output_response = max_output;
+            req.flags = SMB2_0_IOCTL_IS_FSCTL;
+            let cb_data = IoctlCbData{sync: SyncCbData{is_finished: false, status: 0}, output: Vec::new(), overflowed: false};
+            let cb_data = queue_pdu(ctx, cb_data, |cb_data| smb2_cmd_ioctl_async(ctx, &mut req, Some(ioctl_cb), cb_data))?;
+            let cb_data = wait_for_reply(ctx, cb_data)?;
+            Ok((cb_data.output, cb_data.overflowed))
+        }
+    }
+
+    fn set_info(&self, info_type: u32, file_info_class: u32, additional_information: u32, input_data: *mut c_void) -> Result<()> {
+        let ctx_ref = using_mutex!(self.smb);
+        let ctx = *ctx_ref;
+        unsafe {
+            let mut req: smb2_set_info_request = zeroed();
+            req.info_type = info_type as _;
+            req.file_info_class = file_info_class as _;
+            req.additional_information = additional_information;
+            req.file_id = *smb2_get_file_id(self.handle);
+            req.input_data = input_data;
+            let cb_data = queue_pdu(ctx, SyncCbData{is_finished: false, status: 0}, |cb_data| smb2_cmd_set_info_async(ctx, &mut req, Some(sync_cb), cb_data))?;
+            wait_for_reply(ctx, cb_data).map(|_| ())
         }
     }
 
@@ -1080,6 +2402,24 @@ impl SmbFile {
         }
     }
 
+    /// Writes `buffer` at end-of-file, for handles from `Smb::open_append`. Each write request is
+    /// appended atomically, so buffers larger than the max write size may interleave with other writers.
+    pub fn append(&self, buffer: &[u8]) -> Result<i32> {
+        let max_write_size = self.get_max_write_size();
+        let ctx_ref = using_mutex!(self.smb);
+        let ctx = *ctx_ref;
+        unsafe {
+            let mut index = 0;
+            while index < buffer.len() {
+                let chunk = &buffer[index..(index + max_write_size).min(buffer.len())];
+                let write_size = smb2_pwrite(ctx, self.handle, chunk.as_ptr() as *mut _, chunk.len() as u32, SMB2_WRITE_TO_EOF);
+                check_retcode(ctx, write_size)?;
+                index += write_size as usize;
+            }
+            Ok(buffer.len() as i32)
+        }
+    }
+
     pub fn read(&self, count: u64) -> Result<Vec<u8>> {
         self.pread(count, 0)
     }
@@ -1101,40 +2441,188 @@ impl SmbFile {
 impl Iterator for SmbDirectory {
     type Item = Result<DirEntry>;
     fn next(&mut self) -> Option<Self::Item> {
-        unsafe {
-            let ctx_ref = using_mutex!(self.smb);
-            let ctx = *ctx_ref;
-            let dirent = smb2_readdir(ctx, self.handle);
-            if dirent.is_null() {
-                return None;
-            }
-
-            let file_name = CStr::from_ptr((*dirent).name);
-            let stat = (*dirent).st;
-
-            let d_type = match EntryType::from(stat.smb2_type) {
-                Ok(ty) => ty,
+        while self.page.is_empty() && !self.finished {
+            match self.fetch_page() {
+                Ok(more) => self.finished = !more,
                 Err(e) => {
+                    self.finished = true;
                     return Some(Err(e));
                 }
-            };
-            //let mode = Mode::from_bits_truncate(((*dirent).mode as u16).into());
-            Some(Ok(DirEntry {
-                path: PathBuf::from(file_name.to_string_lossy().into_owned()),
-                inode: (stat).smb2_ino,
-                d_type,
-                //mode,
-                size: (stat).smb2_size,
-                atime: (stat).smb2_atime,
-                mtime: (stat).smb2_mtime,
-                ctime: (stat).smb2_ctime,
-                btime: (stat).smb2_btime,
-                nlink: (stat).smb2_nlink,
-                atime_nsec: (stat).smb2_atime_nsec,
-                mtime_nsec: (stat).smb2_mtime_nsec,
-                ctime_nsec: (stat).smb2_ctime_nsec,
-                btime_nsec: (stat).smb2_btime_nsec,
-            }))
+            }
         }
+        self.page.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A FILE_ID_FULL_DIR_INFORMATION entry named `name`, padded to 8 bytes unless it is the `last`.
+    fn full_directory_entry(name: &[u16], attributes: u32, size: u64, last: bool) -> Vec<u8> {
+        let mut entry = vec![0u8; 80];
+        entry[8..16].copy_from_slice(&(NT_TO_UNIX_EPOCH + 10_000_000).to_le_bytes());
+        entry[40..48].copy_from_slice(&size.to_le_bytes());
+        entry[56..60].copy_from_slice(&attributes.to_le_bytes());
+        entry[60..64].copy_from_slice(&((name.len() * 2) as u32).to_le_bytes());
+        entry[72..80].copy_from_slice(&42u64.to_le_bytes());
+        entry.extend(name.iter().flat_map(|c| c.to_le_bytes()));
+        if !last {
+            entry.resize(entry.len().next_multiple_of(8), 0);
+            let next_entry_offset = entry.len() as u32;
+            entry[0..4].copy_from_slice(&next_entry_offset.to_le_bytes());
+        }
+        entry
+    }
+
+    fn utf16(name: &str) -> Vec<u16> {
+        name.encode_utf16().collect()
+    }
+
+    #[test]
+    fn decodes_full_directory_information() {
+        let mut buf = full_directory_entry(&utf16("sub"), FILE_ATTRIBUTE_DIRECTORY, 0, false);
+        buf.extend(full_directory_entry(&utf16("fïle.txt"), FILE_ATTRIBUTE_READONLY, 123, true));
+        let entries = decode_full_directory_information(&buf).unwrap().into_iter().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(entries.iter().map(|e| e.path.to_str().unwrap()).collect::<Vec<_>>(), ["sub", "fïle.txt"]);
+        assert!(matches!(entries[0].d_type, EntryType::Directory));
+        assert!(matches!(entries[1].d_type, EntryType::File));
+        assert_eq!(entries[1].size, 123);
+        assert_eq!(entries[1].mode, 0o444);
+        assert_eq!(entries[1].inode, 42);
+        assert_eq!((entries[1].btime, entries[1].btime_nsec), (1, 0));
+    }
+
+    #[test]
+    fn fails_only_entries_with_unpaired_surrogates_in_their_name() {
+        let mut buf = full_directory_entry(&utf16("before"), 0, 0, false);
+        buf.extend(full_directory_entry(&[0x0061, 0xD800, 0x0062], 0, 0, false));
+        buf.extend(full_directory_entry(&utf16("after"), 0, 0, true));
+        let entries = decode_full_directory_information(&buf).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].as_ref().unwrap().path.to_str(), Some("before"));
+        assert_eq!(entries[1].as_ref().unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(entries[2].as_ref().unwrap().path.to_str(), Some("after"));
+    }
+
+    #[test]
+    fn decodes_names_outside_the_basic_multilingual_plane() {
+        let buf = full_directory_entry(&utf16("\u{1F600}.txt"), 0, 0, true);
+        let entries = decode_full_directory_information(&buf).unwrap();
+        assert_eq!(entries[0].as_ref().unwrap().path.to_str(), Some("\u{1F600}.txt"));
+    }
+
+    #[test]
+    fn decodes_all_information_with_the_attributes() {
+        let mut info: smb2_file_all_info = unsafe { zeroed() };
+        info.basic.file_attributes = FILE_ATTRIBUTE_READONLY;
+        info.basic.last_write_time.tv_sec = 1658159058;
+        info.basic.last_write_time.tv_usec = 250;
+        info.standard.end_of_file = 123;
+        info.standard.number_of_links = 2;
+        info.index_number = 42;
+        let stat = SmbStat::from_all_information(&info);
+        assert_eq!(stat.stat.smb2_type, SMB2_TYPE_FILE as _);
+        assert_eq!(stat.stat.smb2_size, 123);
+        assert_eq!(stat.stat.smb2_nlink, 2);
+        assert_eq!(stat.stat.smb2_ino, 42);
+        assert_eq!(stat.stat.smb2_mtime, 1658159058);
+        assert_eq!(stat.stat.smb2_mtime_nsec, 250_000);
+        assert_eq!(stat.mode().unwrap(), 0o444);
+
+        info.basic.file_attributes = FILE_ATTRIBUTE_DIRECTORY;
+        let stat = SmbStat::from_all_information(&info);
+        assert!(matches!(stat.entry_type().unwrap(), EntryType::Directory));
+        assert_eq!(stat.mode().unwrap(), 0o755);
+
+        info.basic.file_attributes = FILE_ATTRIBUTE_DIRECTORY | FILE_ATTRIBUTE_REPARSE_POINT;
+        assert!(matches!(SmbStat::from_all_information(&info).entry_type().unwrap(), EntryType::Symlink));
+    }
+
+    #[test]
+    fn encodes_ranges() {
+        let input = encode_range(0x0102, 0x1_0000_0000);
+        assert_eq!(&input[0..8], &[0x02, 0x01, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&input[8..16], &[0, 0, 0, 0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn decodes_allocated_ranges_ignoring_a_partial_trailing_one() {
+        let mut output = Vec::new();
+        output.extend(encode_range(0, 4096));
+        output.extend(encode_range(65536, 8192));
+        output.extend(&[0u8; 8]);
+        assert_eq!(decode_allocated_ranges(&output), [(0, 4096), (65536, 8192)]);
+        assert_eq!(decode_allocated_ranges(&[]), []);
+    }
+
+    #[test]
+    fn keeps_the_output_of_ioctls_cut_short() {
+        let mut output = encode_range(0, 4096);
+        let mut reply: smb2_ioctl_reply = unsafe { zeroed() };
+        reply.output = output.as_mut_ptr().cast::<c_void>();
+        reply.output_count = output.len() as _;
+        for (status, overflowed) in [(0, false), (STATUS_BUFFER_OVERFLOW, true)] {
+            let mut cb_data = IoctlCbData{sync: SyncCbData{is_finished: false, status: 0}, output: Vec::new(), overflowed: false};
+            ioctl_cb(std::ptr::null_mut(), status as i32, (&mut reply as *mut smb2_ioctl_reply).cast(), (&mut cb_data as *mut IoctlCbData).cast());
+            assert!(cb_data.sync.is_finished);
+            assert_eq!(cb_data.sync.status, 0);
+            assert_eq!(cb_data.overflowed, overflowed);
+            assert_eq!(cb_data.output, output);
+        }
+    }
+
+    struct RecordingCallback(Mutex<Vec<(String, String, Option<String>)>>);
+
+    impl SmbNotifyChangeCallback for RecordingCallback {
+        fn call(&self, path: String, action: String, from_path: Option<String>, _kind: Option<EntryType>) {
+            self.0.lock().unwrap().push((path, action, from_path));
+        }
+    }
+
+    #[test]
+    fn reports_the_old_name_of_a_rename_whose_new_name_never_arrives() {
+        let recorded = Arc::new(RecordingCallback(Mutex::new(Vec::new())));
+        let cb = NotifyChangeCallback{
+            inner: Arc::clone(&recorded) as Arc<dyn SmbNotifyChangeCallback>,
+            smb: Arc::new(SmbPtr(Arc::new(Mutex::new(std::ptr::null_mut())))),
+            fh: std::ptr::null_mut(),
+            pending_old_name: RefCell::new(Some("old.txt".to_string())),
+            name_kind: Some(EntryType::File),
+            status: Cell::new(0),
+        };
+        drop(cb);
+        assert_eq!(*recorded.0.lock().unwrap(), [("old.txt".to_string(), "rename".to_string(), None)]);
+    }
+
+    #[test]
+    fn hands_back_callback_data_once_its_pdu_completes() {
+        let cb_data = QueuedCbData(Box::into_raw(Box::new(SyncCbData{is_finished: false, status: 0})));
+        assert!(!cb_data.is_finished());
+        sync_cb(std::ptr::null_mut(), 5, std::ptr::null_mut(), cb_data.0.cast());
+        assert!(cb_data.is_finished());
+        assert_eq!(cb_data.into_inner().status, 5);
+    }
+
+    #[test]
+    fn opens_directories_to_flush_for_listing_where_adding_entries_is_denied() {
+        let mut requested = Vec::new();
+        let opened = open_for_fsync_dir(|desired_access| {
+            requested.push(desired_access);
+            match desired_access & SMB2_FILE_ADD_FILE {
+                0 => Ok(desired_access),
+                _ => Err(Error::from_raw_os_error(libc::EACCES)),
+            }
+        });
+        assert_eq!(opened.unwrap(), (SMB2_FILE_LIST_DIRECTORY | SMB2_FILE_READ_ATTRIBUTES, false));
+        assert_eq!(requested.len(), 2);
+        let failed = open_for_fsync_dir(|_| Err::<(), _>(Error::from_raw_os_error(libc::ENOENT)));
+        assert_eq!(failed.unwrap_err().kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn rejects_truncated_directory_information() {
+        let buf = full_directory_entry(&utf16("cut"), 0, 0, true);
+        assert_eq!(decode_full_directory_information(&buf[..buf.len() - 1]).unwrap_err().kind(), ErrorKind::InvalidData);
     }
 }
@@ -0,0 +1,139 @@
+// Copyright 2025 NetApp Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{Error, ErrorKind};
+use std::sync::mpsc::Sender;
+
+use super::{Result, Time, VFSCancellation, VFSCapabilities, VFSConnectOptions, VFSConnectionInfo, VFSDirectory, VFSFileAttributes, VFSFile, VFSFileNotificationOperationFlags, VFSMetrics, VFSNotifyChangeCallback, VFSStat, VFSWatchMode, VFS};
+
+/// Stands in for a connection that has been shut down, failing every operation.
+#[derive(Debug)]
+pub struct ClosedVFS {
+    options: VFSConnectOptions,
+}
+
+impl ClosedVFS {
+    pub fn new(options: VFSConnectOptions) -> Self {
+        Self{options}
+    }
+}
+
+fn closed<T>() -> Result<T> {
+    Err(Error::new(ErrorKind::NotConnected, "Connection has been shut down"))
+}
+
+impl VFS for ClosedVFS {
+    fn is_connected(&self) -> bool {
+        false
+    }
+
+    fn connection_info(&self) -> VFSConnectionInfo {
+        VFSConnectionInfo{signing: self.options.signing, ..Default::default()}
+    }
+
+    fn capabilities(&self) -> VFSCapabilities {
+        VFSCapabilities::default()
+    }
+
+    fn metrics(&self, _reset: bool) -> VFSMetrics {
+        VFSMetrics::default()
+    }
+
+    fn connect_options(&self) -> VFSConnectOptions {
+        self.options.clone()
+    }
+
+    fn stat(&self, _path: &str) -> Result<VFSStat> {
+        closed()
+    }
+
+    fn lstat(&self, _path: &str) -> Result<VFSStat> {
+        closed()
+    }
+
+    fn exists(&self, _path: &str) -> Result<bool> {
+        closed()
+    }
+
+    fn attributes(&self, _path: &str) -> Result<VFSFileAttributes> {
+        closed()
+    }
+
+    fn chmod(&self, _path: &str, _mode: u32) -> Result<()> {
+        closed()
+    }
+
+    fn opendir(&mut self, _path: &str) -> Result<Box<dyn VFSDirectory>> {
+        closed()
+    }
+
+    fn mkdir(&self, _path: &str, _mode: u32) -> Result<()> {
+        closed()
+    }
+
+    fn create(&mut self, _path: &str, _flags: u32, _mode: u32, _attributes: VFSFileAttributes) -> Result<Box<dyn VFSFile>> {
+        closed()
+    }
+
+    fn rmdir(&self, _path: &str) -> Result<()> {
+        closed()
+    }
+
+    fn unlink(&self, _path: &str) -> Result<()> {
+        closed()
+    }
+
+    fn readlink(&self, _path: &str) -> Result<String> {
+        closed()
+    }
+
+    fn rename(&self, _from: &str, _to: &str, _replace: bool) -> Result<()> {
+        closed()
+    }
+
+    fn open(&mut self, _path: &str, _flags: u32) -> Result<Box<dyn VFSFile>> {
+        closed()
+    }
+
+    fn truncate(&self, _path: &str, _len: u64) -> Result<()> {
+        closed()
+    }
+
+    fn fsync_dir(&self, _path: &str) -> Result<()> {
+        closed()
+    }
+
+    fn set_times(&self, _path: &str, _atime: Option<Time>, _mtime: Option<Time>, _btime: Option<Time>) -> Result<()> {
+        closed()
+    }
+
+    fn get_security(&self, _path: &str) -> Result<Vec<u8>> {
+        closed()
+    }
+
+    fn set_security(&self, _path: &str, _descriptor: &[u8]) -> Result<()> {
+        closed()
+    }
+
+    fn server_time(&mut self, _path: &str) -> Result<Time> {
+        closed()
+    }
+
+    fn watch(&self, _path: &str, _mode: VFSWatchMode, _listen_events: VFSFileNotificationOperationFlags, _cb: Box<dyn VFSNotifyChangeCallback>, ready_tx: &Sender<bool>, _cancellation: &VFSCancellation) -> Result<()> {
+        let _ = ready_tx.send(true);
+        Ok(())
+    }
+}
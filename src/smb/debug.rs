@@ -0,0 +1,215 @@
+// Copyright 2025 NetApp Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::mpsc::Sender;
+use std::time::Instant;
+
+use super::{Result, Time, VFSCancellation, VFSCapabilities, VFSConnectOptions, VFSResumeKey, VFSConnectionInfo, VFSDirectory, VFSFileAttributes, VFSFile, VFSFileNotificationOperationFlags, VFSMetrics, VFSNotifyChangeCallback, VFSStat, VFSWatchMode, VFS};
+
+pub const LOG_TARGET: &str = "smb_js";
+
+/// Emits a debug event carrying the operation, path, duration and result of `f`.
+fn traced<T>(op: &str, path: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let start = Instant::now();
+    let res = f();
+    let duration_us = start.elapsed().as_micros() as u64;
+    match &res {
+        Ok(_) => log::debug!(target: LOG_TARGET, op = op, path = path, duration_us = duration_us, result = "ok"; "{} {}", op, path),
+        Err(err) => log::debug!(target: LOG_TARGET, op = op, path = path, duration_us = duration_us, result = err.to_string().as_str(); "{} {} failed", op, path),
+    }
+    res
+}
+
+/// Wraps a connection so that each operation emits a debug event, for connections made with `debug` set.
+#[derive(Debug)]
+pub(super) struct DebugVFS {
+    inner: Box<dyn VFS>,
+}
+
+impl DebugVFS {
+    pub(super) fn new(inner: Box<dyn VFS>) -> Self {
+        Self{inner}
+    }
+}
+
+impl VFS for DebugVFS {
+    fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+
+    fn connection_info(&self) -> VFSConnectionInfo {
+        self.inner.connection_info()
+    }
+
+    fn capabilities(&self) -> VFSCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn metrics(&self, reset: bool) -> VFSMetrics {
+        self.inner.metrics(reset)
+    }
+
+    fn connect_options(&self) -> VFSConnectOptions {
+        self.inner.connect_options()
+    }
+
+    fn stat(&self, path: &str) -> Result<VFSStat> {
+        traced("stat", path, || self.inner.stat(path))
+    }
+
+    fn lstat(&self, path: &str) -> Result<VFSStat> {
+        traced("lstat", path, || self.inner.lstat(path))
+    }
+
+    fn stat_many(&self, paths: &[String]) -> Result<Vec<Result<VFSStat>>> {
+        traced("stat_many", &paths.join(", "), || self.inner.stat_many(paths))
+    }
+
+    fn exists(&self, path: &str) -> Result<bool> {
+        traced("exists", path, || self.inner.exists(path))
+    }
+
+    fn attributes(&self, path: &str) -> Result<VFSFileAttributes> {
+        traced("attributes", path, || self.inner.attributes(path))
+    }
+
+    fn chmod(&self, path: &str, mode: u32) -> Result<()> {
+        traced("chmod", path, || self.inner.chmod(path, mode))
+    }
+
+    fn opendir(&mut self, path: &str) -> Result<Box<dyn VFSDirectory>> {
+        traced("opendir", path, || self.inner.opendir(path))
+    }
+
+    fn opendir_pattern(&mut self, path: &str, pattern: &str) -> Result<Box<dyn VFSDirectory>> {
+        traced("opendir", path, || self.inner.opendir_pattern(path, pattern))
+    }
+
+    fn mkdir(&self, path: &str, mode: u32) -> Result<()> {
+        traced("mkdir", path, || self.inner.mkdir(path, mode))
+    }
+
+    fn create(&mut self, path: &str, flags: u32, mode: u32, attributes: VFSFileAttributes) -> Result<Box<dyn VFSFile>> {
+        let file = traced("create", path, || self.inner.create(path, flags, mode, attributes))?;
+        Ok(Box::new(DebugVFSFile{inner: file, path: path.to_string()}))
+    }
+
+    fn rmdir(&self, path: &str) -> Result<()> {
+        traced("rmdir", path, || self.inner.rmdir(path))
+    }
+
+    fn unlink(&self, path: &str) -> Result<()> {
+        traced("unlink", path, || self.inner.unlink(path))
+    }
+
+    fn readlink(&self, path: &str) -> Result<String> {
+        traced("readlink", path, || self.inner.readlink(path))
+    }
+
+    fn rename(&self, from: &str, to: &str, replace: bool) -> Result<()> {
+        traced("rename", from, || self.inner.rename(from, to, replace))
+    }
+
+    fn open(&mut self, path: &str, flags: u32) -> Result<Box<dyn VFSFile>> {
+        let file = traced("open", path, || self.inner.open(path, flags))?;
+        Ok(Box::new(DebugVFSFile{inner: file, path: path.to_string()}))
+    }
+
+    fn truncate(&self, path: &str, len: u64) -> Result<()> {
+        traced("truncate", path, || self.inner.truncate(path, len))
+    }
+
+    fn fsync_dir(&self, path: &str) -> Result<()> {
+        traced("fsync_dir", path, || self.inner.fsync_dir(path))
+    }
+
+    fn set_times(&self, path: &str, atime: Option<Time>, mtime: Option<Time>, btime: Option<Time>) -> Result<()> {
+        traced("set_times", path, || self.inner.set_times(path, atime, mtime, btime))
+    }
+
+    fn get_security(&self, path: &str) -> Result<Vec<u8>> {
+        traced("get_security", path, || self.inner.get_security(path))
+    }
+
+    fn set_security(&self, path: &str, descriptor: &[u8]) -> Result<()> {
+        traced("set_security", path, || self.inner.set_security(path, descriptor))
+    }
+
+    fn server_time(&mut self, path: &str) -> Result<Time> {
+        traced("server_time", path, || self.inner.server_time(path))
+    }
+
+    fn watch(&self, path: &str, mode: VFSWatchMode, listen_events: VFSFileNotificationOperationFlags, cb: Box<dyn VFSNotifyChangeCallback>, ready_tx: &Sender<bool>, cancellation: &VFSCancellation) -> Result<()> {
+        log::debug!(target: LOG_TARGET, op = "watch", path = path; "watch {}", path);
+        self.inner.watch(path, mode, listen_events, cb, ready_tx, cancellation)
+    }
+}
+
+#[derive(Debug)]
+struct DebugVFSFile {
+    inner: Box<dyn VFSFile>,
+    path: String,
+}
+
+impl VFSFile for DebugVFSFile {
+    fn fstat(&self) -> Result<VFSStat> {
+        traced("fstat", &self.path, || self.inner.fstat())
+    }
+
+    fn get_max_read_size(&self) -> u64 {
+        self.inner.get_max_read_size()
+    }
+
+    fn pread_into(&self, count: u32, offset: u64, buffer: &mut [u8]) -> Result<u32> {
+        traced("pread", &self.path, || self.inner.pread_into(count, offset, buffer))
+    }
+
+    fn pwrite(&self, buffer: &[u8], offset: u64) -> Result<u32> {
+        traced("pwrite", &self.path, || self.inner.pwrite(buffer, offset))
+    }
+
+    fn fsync(&self) -> Result<()> {
+        traced("fsync", &self.path, || self.inner.fsync())
+    }
+
+    fn set_allocation_size(&self, len: u64) -> Result<()> {
+        traced("set_allocation_size", &self.path, || self.inner.set_allocation_size(len))
+    }
+
+    fn ftruncate(&self, len: u64) -> Result<()> {
+        traced("ftruncate", &self.path, || self.inner.ftruncate(len))
+    }
+
+    fn allocated_ranges(&self, offset: u64, len: u64) -> Result<Vec<(u64, u64)>> {
+        traced("allocated_ranges", &self.path, || self.inner.allocated_ranges(offset, len))
+    }
+
+    fn set_sparse(&self) -> Result<()> {
+        traced("set_sparse", &self.path, || self.inner.set_sparse())
+    }
+
+    fn set_zero_data(&self, offset: u64, len: u64) -> Result<()> {
+        traced("set_zero_data", &self.path, || self.inner.set_zero_data(offset, len))
+    }
+
+    fn resume_key(&self) -> Result<VFSResumeKey> {
+        traced("resume_key", &self.path, || self.inner.resume_key())
+    }
+
+    fn copy_chunks(&self, source_key: &VFSResumeKey, chunks: &[(u64, u64, u32)]) -> Result<u64> {
+        traced("copy_chunks", &self.path, || self.inner.copy_chunks(source_key, chunks))
+    }
+}
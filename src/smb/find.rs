@@ -0,0 +1,68 @@
+// Copyright 2025 NetApp Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::Result;
+
+use super::{matches_pattern, Time, VFSDirEntry, VFSEntryType, VFSTraversal, VFS};
+
+/// What `find` matches entries against, every criterion left out matching all entries.
+/// `directory` picks directories when true and all other entries when false.
+#[derive(Clone, Debug, Default)]
+pub struct VFSFindCriteria {
+    pub name_pattern: Option<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub modified_after: Option<Time>,
+    pub directory: Option<bool>,
+}
+
+impl VFSFindCriteria {
+    /// Whether the entry, as reported by its directory listing, meets all the criteria.
+    pub fn matches(&self, entry: &VFSDirEntry) -> bool {
+        let stat = entry.stat();
+        self.name_pattern.as_ref().is_none_or(|pattern| matches_pattern(&entry.path, pattern))
+            && self.min_size.is_none_or(|min_size| stat.size >= min_size)
+            && self.max_size.is_none_or(|max_size| stat.size <= max_size)
+            && self.modified_after.as_ref().is_none_or(|after| stat.modified().as_nanoseconds() > after.as_nanoseconds())
+            && self.directory.is_none_or(|directory| directory == (entry.d_type == VFSEntryType::Directory))
+    }
+}
+
+/// Lists the entries of the directory at `path` that meet `criteria`, by their path relative to it,
+/// with the stat data of the listing so that no entry takes a round trip of its own. Given a
+/// `traversal`, subdirectories are searched as well, symlinks being handled as its policy says.
+pub fn find(vfs: &mut dyn VFS, path: &str, criteria: &VFSFindCriteria, mut traversal: Option<&mut VFSTraversal>) -> Result<Vec<(String, VFSDirEntry)>> {
+    let mut found = Vec::new();
+    let mut pending = vec![(String::new(), format!("{}/", path.trim_end_matches('/')))];
+    while let Some((relative_dir, dir)) = pending.pop() {
+        for entry in vfs.opendir(&dir)? {
+            let entry = entry?;
+            if entry.path == "." || entry.path == ".." {
+                continue;
+            }
+            let relative_path = format!("{}{}", relative_dir, entry.path);
+            if let Some(traversal) = traversal.as_deref_mut() {
+                if let Some(subdir) = traversal.descend(vfs, &format!("{}{}", dir, entry.path), &entry.d_type)? {
+                    pending.push((format!("{}/", relative_path), subdir));
+                }
+            }
+            if criteria.matches(&entry) {
+                found.push((relative_path, entry));
+            }
+        }
+    }
+    Ok(found)
+}
@@ -16,14 +16,13 @@
 
 use core::fmt::Debug;
 use std::path::Path;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::Sender;
 use std::sync::{Arc, RwLock};
 use nix::sys::stat::Mode;
 use nix::fcntl::OFlag;
-use libsmb2_rs::{Smb, SmbChangeNotifyAction, SmbChangeNotifyFileFilter, SmbChangeNotifyFlags};
-use url::Url;
+use libsmb2_rs::{Smb, SmbStat, SmbChangeNotifyAction, SmbChangeNotifyFileFilter, SmbChangeNotifyFlags, SmbSecurityMode};
 
-use super::{Result, VFSDirEntry, VFSDirectory, VFSFile, VFSFileNotificationOperation, VFSFileNotificationOperationFlags, VFSStat, VFSWatchMode, Time, VFS};
+use super::{Result, VFSConnectOptions, VFSConnectionInfo, VFSDirEntry, VFSFileAttributes, VFSDirectory, VFSFile, VFSFileNotificationOperation, VFSFileNotificationOperationFlags, VFSMetrics, VFSMetricsCounters, VFSSigning, VFSStat, VFSUrl, VFSWatchMode, Time, VFS, split_url_password, url_has_arg, url_with_args, VFSCapabilities, VFSResumeKey, VFSSecret};
 
 macro_rules! using_rwlock {
     ( $rwlock:expr ) => {
@@ -33,31 +32,19 @@ macro_rules! using_rwlock {
 
 pub(super) struct SMBConnection {
     smb: Arc<RwLock<Smb>>,
+    options: VFSConnectOptions,
+    metrics: Arc<VFSMetricsCounters>,
+    sealed: bool,
 }
 
 impl SMBConnection {
-    pub(super) fn connect(url: String) -> Result<Box<dyn VFS>> {
-        let mut real_url = url;
+    pub(super) fn connect(url: String, password: Option<&VFSSecret>, options: VFSConnectOptions) -> Result<Box<dyn VFS>> {
         let mut smb = Smb::new()?;
         let mut user: Option<String> = None;
-        let mut passwd: Option<String> = None;
         let mut domain: Option<String> = None;
-        let pre_parse_url = Url::parse(real_url.as_str());
-        match pre_parse_url {
-            Ok(mut purl) => {
-                let opasswd = purl.password();
-                match opasswd {
-                    Some(p) => {
-                        let p_owned = p.to_owned();
-                        passwd = Some(p_owned);    
-                    },
-                    None => {},
-                }
-                let _ = purl.set_password(None);
-                real_url = purl.to_string();
-            },
-            Err(_) => {},
-        }
+        let mut env_passwd: Option<VFSSecret> = None;
+        let real_url = url_with_args(url.as_str(), &options.connect_url_args());
+        let sealed = url_has_arg(real_url.as_str(), "seal");
         let user_env_var = std::env::var("SMB_USER");
         match user_env_var {
             Ok(user_str) => {
@@ -75,14 +62,28 @@ impl SMBConnection {
         let password_env_var = std::env::var("SMB_PASSWORD");
         match password_env_var {
             Ok(password_string) => {
-                passwd = Some(password_string);
+                env_passwd = Some(VFSSecret::from(password_string));
             },
             Err(_) => {},
         }
-        let conn_res = smb.parse_url_mount(real_url.as_str(), user,passwd, domain);
+        let security_mode = match options.signing {
+            VFSSigning::Required => SmbSecurityMode::SIGNING_ENABLED | SmbSecurityMode::SIGNING_REQUIRED,
+            VFSSigning::Enabled => SmbSecurityMode::SIGNING_ENABLED,
+            VFSSigning::Disabled => SmbSecurityMode::empty(),
+        };
+        smb.set_security_mode(security_mode)?;
+        if let Some(workstation_name) = &options.workstation_name {
+            smb.set_workstation(workstation_name)?;
+        }
+        if let Some(client_guid) = &options.client_guid {
+            smb.set_client_guid(client_guid)?;
+        }
+        let passwd = env_passwd.as_ref().or(password);
+        let conn_res = smb.parse_url_mount(real_url.as_str(), user, passwd.map(VFSSecret::expose), domain)
+            .and_then(|_| smb.set_tcp_options(options.tcp_no_delay, options.tcp_keep_alive));
         match conn_res {
             Ok(_) => {
-                return Ok(Box::new(SMBConnection{smb: Arc::new(RwLock::new(smb))}));
+                return Ok(Box::new(SMBConnection{smb: Arc::new(RwLock::new(smb)), options, metrics: Arc::default(), sealed}));
             },
             Err(e) => {
                 return Err(e);
@@ -91,6 +92,18 @@ impl SMBConnection {
     }
 }
 
+pub(super) fn parse_url(url: &str) -> Result<VFSUrl> {
+    let (real_url, password) = split_url_password(url);
+    drop(password.map(VFSSecret::from));
+    let mut smb = Smb::new()?;
+    let smb_url = smb.parse_url_incomplete(real_url.as_str())?;
+    Ok(VFSUrl{
+        server: smb_url.server().unwrap_or_default(),
+        share: smb_url.share().unwrap_or_default(),
+        path: smb_url.path().unwrap_or_default(),
+    })
+}
+
 impl Debug for SMBConnection {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SMBConnection").finish()
@@ -98,6 +111,35 @@ impl Debug for SMBConnection {
 }
 
 impl VFS for SMBConnection {
+    fn is_connected(&self) -> bool {
+        let my_smb = using_rwlock!(self.smb);
+        my_smb.is_connected()
+    }
+
+    fn connection_info(&self) -> VFSConnectionInfo {
+        VFSConnectionInfo{
+            connected: self.is_connected(),
+            signing: self.options.signing,
+        }
+    }
+
+    /// libsmb2 keeps the negotiate reply to itself, so these go by what it asks for: it fails to
+    /// connect with `seal` unless the server encrypts, and never requests DFS referrals.
+    fn capabilities(&self) -> VFSCapabilities {
+        VFSCapabilities{
+            encryption: self.sealed,
+            dfs: false,
+        }
+    }
+
+    fn metrics(&self, reset: bool) -> VFSMetrics {
+        self.metrics.snapshot(reset)
+    }
+
+    fn connect_options(&self) -> VFSConnectOptions {
+        self.options.clone()
+    }
+
     /*fn access(&self, path: &str, mode: u32) -> Result<()> {
         let my_smb = using_rwlock!(self.smb);
         my_smb.access(Path::new(path), mode as i32).map(|_| ())
@@ -106,39 +148,75 @@ impl VFS for SMBConnection {
     fn stat(&self, path: &str) -> Result<VFSStat> {
         let smb_path = normalize_smb_path(path);
         let my_smb = using_rwlock!(self.smb);
-        my_smb.stat64(Path::new(smb_path)).map(|res| VFSStat{
-            ino: res.smb2_ino,
-            nlink: res.smb2_nlink.into(),
-            size: res.smb2_size,
-            atime: res.smb2_atime,
-            mtime: res.smb2_mtime,
-            ctime: res.smb2_ctime,
-            btime: res.smb2_btime,
-            atime_nsec: res.smb2_atime_nsec,
-            mtime_nsec: res.smb2_mtime_nsec,
-            ctime_nsec: res.smb2_ctime_nsec,
-            btime_nsec: res.smb2_btime_nsec,
-        })
+        self.metrics.record_stat();
+        my_smb.stat64(Path::new(smb_path)).and_then(vfs_stat)
+    }
+
+    fn lstat(&self, path: &str) -> Result<VFSStat> {
+        let smb_path = normalize_smb_path(path);
+        let my_smb = using_rwlock!(self.smb);
+        self.metrics.record_stat();
+        my_smb.lstat64(Path::new(smb_path)).and_then(vfs_stat)
+    }
+
+    fn stat_many(&self, paths: &[String]) -> Result<Vec<Result<VFSStat>>> {
+        let smb_paths: Vec<&Path> = paths.iter().map(|path| Path::new(normalize_smb_path(path))).collect();
+        let my_smb = using_rwlock!(self.smb);
+        for _ in paths {
+            self.metrics.record_stat();
+        }
+        let stats = my_smb.stat_many(&smb_paths)?;
+        Ok(stats.into_iter().map(|res| res.and_then(vfs_stat)).collect())
+    }
+
+    fn exists(&self, path: &str) -> Result<bool> {
+        match self.stat(path) {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn attributes(&self, path: &str) -> Result<VFSFileAttributes> {
+        let smb_path = normalize_smb_path(path);
+        let my_smb = using_rwlock!(self.smb);
+        self.metrics.record_stat();
+        my_smb.get_attributes(Path::new(smb_path)).map(VFSFileAttributes::from_bits_truncate)
+    }
+
+    fn chmod(&self, _path: &str, _mode: u32) -> Result<()> {
+        // libsmb2 doesn't negotiate the SMB3 POSIX extensions, so there are no mode bits to set.
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "chmod is not supported without the SMB3 POSIX extensions"))
     }
 
     fn opendir(&mut self, path: &str) -> Result<Box<dyn VFSDirectory>> {
         let smb_path = normalize_smb_path(path);
         let mut my_smb = using_rwlock!(self.smb);
         let dir = my_smb.opendir(Path::new(smb_path))?;
+        self.metrics.record_readdir();
         Ok(Box::new(SMBDirectory2{dir}))
     }
 
-    fn mkdir(&self, path: &str, _mode: u32) -> Result<()> { // FIXME: mode
+    fn opendir_pattern(&mut self, path: &str, pattern: &str) -> Result<Box<dyn VFSDirectory>> {
+        let smb_path = normalize_smb_path(path);
+        let mut my_smb = using_rwlock!(self.smb);
+        let dir = my_smb.opendir_pattern(Path::new(smb_path), pattern)?;
+        self.metrics.record_readdir();
+        Ok(Box::new(SMBDirectory2{dir}))
+    }
+
+    fn mkdir(&self, path: &str, mode: u32) -> Result<()> {
         let smb_path = normalize_smb_path(path);
         let my_smb = using_rwlock!(self.smb);
-        my_smb.mkdir(Path::new(smb_path))
+        my_smb.mkdir(Path::new(smb_path), Mode::from_bits_truncate((mode as u16).into()))
     }
 
-    fn create(&mut self, path: &str, flags: u32, mode: u32) -> Result<Box<dyn VFSFile>> {
+    fn create(&mut self, path: &str, flags: u32, mode: u32, attributes: VFSFileAttributes) -> Result<Box<dyn VFSFile>> {
         let smb_path = normalize_smb_path(path);
         let mut my_smb = using_rwlock!(self.smb);
-        let file = my_smb.create(Path::new(smb_path), OFlag::from_bits_truncate(flags as i32), Mode::from_bits_truncate((mode as u16).into()))?;
-        Ok(Box::new(SMBFile2{file}))
+        let file = my_smb.create(Path::new(smb_path), OFlag::from_bits_truncate(flags as i32), Mode::from_bits_truncate((mode as u16).into()), attributes.bits())?;
+        self.metrics.record_open();
+        Ok(Box::new(SMBFile2{file, append: false, metrics: self.metrics.clone()}))
     }
 
     fn rmdir(&self, path: &str) -> Result<()> {
@@ -153,11 +231,32 @@ impl VFS for SMBConnection {
         my_smb.unlink(Path::new(smb_path))
     }
 
+    fn readlink(&self, path: &str) -> Result<String> {
+        let smb_path = normalize_smb_path(path);
+        let my_smb = using_rwlock!(self.smb);
+        let mut buf = vec![0u8; 4096];
+        my_smb.readlink(Path::new(smb_path), &mut buf)?;
+        let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+        String::from_utf8(buf[..len].to_vec()).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "symlink target is not valid UTF-8"))
+    }
+
+    fn rename(&self, from: &str, to: &str, replace: bool) -> Result<()> {
+        let (smb_from, smb_to) = (normalize_smb_path(from), normalize_smb_path(to));
+        let my_smb = using_rwlock!(self.smb);
+        my_smb.rename_replace(Path::new(smb_from), Path::new(smb_to), replace)
+    }
+
     fn open(&mut self, path: &str, flags: u32) -> Result<Box<dyn VFSFile>> {
         let smb_path = normalize_smb_path(path);
         let mut my_smb = using_rwlock!(self.smb);
-        let file = my_smb.open(Path::new(smb_path), OFlag::from_bits_truncate(flags as i32))?;
-        Ok(Box::new(SMBFile2{file}))
+        let oflags = OFlag::from_bits_truncate(flags as i32);
+        let append = oflags.contains(OFlag::O_APPEND);
+        let file = match append {
+            true => my_smb.open_append(Path::new(smb_path))?,
+            false => my_smb.open(Path::new(smb_path), oflags)?,
+        };
+        self.metrics.record_open();
+        Ok(Box::new(SMBFile2{file, append, metrics: self.metrics.clone()}))
     }
 
     fn truncate(&self, path: &str, len: u64) -> Result<()> {
@@ -166,12 +265,50 @@ impl VFS for SMBConnection {
         my_smb.truncate(Path::new(smb_path), len)
     }
 
-    fn watch(&self, path: &str, mode: VFSWatchMode, listen_events: VFSFileNotificationOperationFlags, cb: Box<dyn super::VFSNotifyChangeCallback>, ready_tx: &Sender<bool>, cancelled_rx: &Receiver<bool>) {
+    fn fsync_dir(&self, path: &str) -> Result<()> {
+        let smb_path = normalize_smb_path(path);
+        let mut my_smb = using_rwlock!(self.smb);
+        my_smb.fsync_dir(Path::new(smb_path))
+    }
+
+    fn set_times(&self, path: &str, atime: Option<Time>, mtime: Option<Time>, btime: Option<Time>) -> Result<()> {
+        let smb_path = normalize_smb_path(path);
+        let mut my_smb = using_rwlock!(self.smb);
+        let file = my_smb.open_attributes(Path::new(smb_path))?;
+        file.futimes(atime.map(|t| t.into()), mtime.map(|t| t.into()), btime.map(|t| t.into()))
+    }
+
+    fn get_security(&self, path: &str) -> Result<Vec<u8>> {
+        let smb_path = normalize_smb_path(path);
+        let my_smb = using_rwlock!(self.smb);
+        my_smb.get_security(Path::new(smb_path))
+    }
+
+    fn set_security(&self, path: &str, descriptor: &[u8]) -> Result<()> {
+        let smb_path = normalize_smb_path(path);
+        let my_smb = using_rwlock!(self.smb);
+        my_smb.set_security(Path::new(smb_path), descriptor)
+    }
+
+    /// libsmb2 keeps the system time of the negotiate response to itself, so the time is taken from
+    /// a probe file created in `path` and removed straight away.
+    fn server_time(&mut self, path: &str) -> Result<Time> {
+        let probe = format!("{}/.smbjs-server-time-{}-{}", path.trim_end_matches('/'), std::process::id(), Time::now().as_nanoseconds());
+        let file = self.create(&probe, (OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_WRONLY).bits() as u32, 0o600, VFSFileAttributes::empty())?;
+        let stat = file.fstat();
+        drop(file);
+        let removed = self.unlink(&probe);
+        let time = stat?.modified();
+        removed?;
+        Ok(time)
+    }
+
+    fn watch(&self, path: &str, mode: VFSWatchMode, listen_events: VFSFileNotificationOperationFlags, cb: Box<dyn super::VFSNotifyChangeCallback>, ready_tx: &Sender<bool>, cancellation: &super::VFSCancellation) -> Result<()> {
         let smb_path = normalize_smb_path(path);
         let my_smb = using_rwlock!(self.smb);
         let notify_flags = SmbChangeNotifyFlags::my_from(mode);
         let notify_filter = SmbChangeNotifyFileFilter::my_from(listen_events);
-        my_smb.notify_change(Path::new(smb_path), notify_flags, notify_filter, Box::new(super::NotifyChangeCallback{inner: cb}), ready_tx, cancelled_rx);
+        my_smb.notify_change(Path::new(smb_path), notify_flags, notify_filter, Box::new(super::NotifyChangeCallback{inner: cb}), ready_tx, cancellation)
     }
 }
 
@@ -253,16 +390,32 @@ impl MyFrom<SmbChangeNotifyAction> for VFSFileNotificationOperation {
     }
 }
 
+/// Turns a path of the VFS, absolute and with a trailing slash for directories, into one relative to
+/// the share as libsmb2 takes it. The root, `/`, becomes the empty path, which names the share root.
 pub fn normalize_smb_path(path: &str) -> &str {
-    let mut real_path = path;
-    let real_path_replaced = real_path.strip_prefix("/");
-    match real_path_replaced {
-        Some(replaced_path) => {
-            real_path = replaced_path;
-        },
-        None => {},
-    }
-    return real_path;
+    path.strip_prefix('/').unwrap_or(path).trim_end_matches('/')
+}
+
+/// Turns a stat of libsmb2-rs into one of the VFS, synthesizing the mode bits from the attributes.
+fn vfs_stat(stat: SmbStat) -> Result<VFSStat> {
+    let entry_type = stat.entry_type()?;
+    let mode = stat.mode()?;
+    let res = stat.stat;
+    Ok(VFSStat{
+        d_type: (entry_type as u32).into(),
+        mode,
+        ino: res.smb2_ino,
+        nlink: res.smb2_nlink.into(),
+        size: res.smb2_size,
+        atime: res.smb2_atime,
+        mtime: res.smb2_mtime,
+        ctime: res.smb2_ctime,
+        btime: res.smb2_btime,
+        atime_nsec: res.smb2_atime_nsec,
+        mtime_nsec: res.smb2_mtime_nsec,
+        ctime_nsec: res.smb2_ctime_nsec,
+        btime_nsec: res.smb2_btime_nsec,
+    })
 }
 
 pub struct SMBDirectory2 {
@@ -285,6 +438,7 @@ impl Iterator for SMBDirectory2 {
         self.dir.next().map(|res| res.map(|entry| VFSDirEntry{
             path: entry.path.display().to_string(),
             inode: entry.inode,
+            mode: entry.mode,
             d_type: (entry.d_type as u32).into(),
             size: entry.size,
             atime: Time{seconds: entry.atime as u32, nseconds: entry.atime_nsec},
@@ -302,6 +456,8 @@ impl Iterator for SMBDirectory2 {
 
 pub struct SMBFile2 {
     file: libsmb2_rs::SmbFile,
+    append: bool,
+    metrics: Arc<VFSMetricsCounters>,
 }
 
 impl Debug for SMBFile2 {
@@ -312,19 +468,8 @@ impl Debug for SMBFile2 {
 
 impl VFSFile for SMBFile2 {
     fn fstat(&self) -> Result<VFSStat> {
-        self.file.fstat64().map(|res| VFSStat{
-            ino: res.smb2_ino,
-            nlink: res.smb2_nlink.into(),
-            size: res.smb2_size,
-            atime: res.smb2_atime,
-            mtime: res.smb2_mtime,
-            ctime: res.smb2_ctime,
-            btime: res.smb2_btime,
-            atime_nsec: res.smb2_atime_nsec,
-            mtime_nsec: res.smb2_mtime_nsec,
-            ctime_nsec: res.smb2_ctime_nsec,
-            btime_nsec: res.smb2_btime_nsec,
-        })
+        self.metrics.record_stat();
+        self.file.fstat64().and_then(vfs_stat)
     }
 
     fn get_max_read_size(&self) -> u64 {
@@ -332,10 +477,62 @@ impl VFSFile for SMBFile2 {
     }
 
     fn pread_into(&self, count: u32, offset: u64, buffer: &mut [u8]) -> Result<u32> {
-        self.file.pread_into(count as u64, offset, buffer).map(|res| res as u32)
+        let res = self.file.pread_into(count as u64, offset, buffer)?;
+        self.metrics.record_read(res as u64);
+        Ok(res as u32)
     }
 
     fn pwrite(&self, buffer: &[u8], offset: u64) -> Result<u32> {
-        self.file.pwrite(buffer, offset).map(|res| res as u32)
+        let res = match self.append {
+            true => self.file.append(buffer)?,
+            false => self.file.pwrite(buffer, offset)?,
+        };
+        self.metrics.record_write(res as u64);
+        Ok(res as u32)
+    }
+
+    fn fsync(&self) -> Result<()> {
+        self.file.fsync()
+    }
+
+    fn set_allocation_size(&self, len: u64) -> Result<()> {
+        self.file.set_allocation_size(len)
+    }
+
+    fn ftruncate(&self, len: u64) -> Result<()> {
+        self.file.ftruncate(len)
+    }
+
+    fn allocated_ranges(&self, offset: u64, len: u64) -> Result<Vec<(u64, u64)>> {
+        self.file.query_allocated_ranges(offset, len)
+    }
+
+    fn set_sparse(&self) -> Result<()> {
+        self.file.set_sparse()
+    }
+
+    fn set_zero_data(&self, offset: u64, len: u64) -> Result<()> {
+        self.file.set_zero_data(offset, len)
+    }
+
+    fn resume_key(&self) -> Result<VFSResumeKey> {
+        self.file.request_resume_key()
+    }
+
+    fn copy_chunks(&self, source_key: &VFSResumeKey, chunks: &[(u64, u64, u32)]) -> Result<u64> {
+        self.file.copy_chunks(source_key, chunks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smb_paths_are_relative_to_the_share() {
+        assert_eq!(normalize_smb_path("/"), "");
+        assert_eq!(normalize_smb_path(""), "");
+        assert_eq!(normalize_smb_path("/first/"), "first");
+        assert_eq!(normalize_smb_path("/first/comment"), "first/comment");
     }
 }
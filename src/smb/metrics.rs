@@ -0,0 +1,74 @@
+// Copyright 2025 NetApp Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Snapshot of the traffic on a connection since it was made or its counters were last reset.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VFSMetrics {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub stat_ops: u64,
+    pub open_ops: u64,
+    pub readdir_ops: u64,
+}
+
+/// Counters shared by a connection and the files opened through it.
+#[derive(Debug, Default)]
+pub struct VFSMetricsCounters {
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    stat_ops: AtomicU64,
+    open_ops: AtomicU64,
+    readdir_ops: AtomicU64,
+}
+
+impl VFSMetricsCounters {
+    pub fn record_read(&self, count: u64) {
+        self.bytes_read.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_write(&self, count: u64) {
+        self.bytes_written.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_stat(&self) {
+        self.stat_ops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_open(&self) {
+        self.open_ops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_readdir(&self) {
+        self.readdir_ops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the current counts, zeroing them if `reset` is set.
+    pub fn snapshot(&self, reset: bool) -> VFSMetrics {
+        let read = |counter: &AtomicU64| match reset {
+            true => counter.swap(0, Ordering::Relaxed),
+            false => counter.load(Ordering::Relaxed),
+        };
+        VFSMetrics{
+            bytes_read: read(&self.bytes_read),
+            bytes_written: read(&self.bytes_written),
+            stat_ops: read(&self.stat_ops),
+            open_ops: read(&self.open_ops),
+            readdir_ops: read(&self.readdir_ops),
+        }
+    }
+}
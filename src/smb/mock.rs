@@ -15,12 +15,14 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::collections::{BTreeSet, BTreeMap};
-use std::io::Error;
-use std::sync::mpsc::{Receiver, Sender};
+use std::io::{Error, ErrorKind};
+use std::sync::mpsc::{channel, Sender, TryRecvError};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use bytes::BufMut;
 
-use super::{Result, VFSDirEntry, VFSDirectory, VFSEntryType, VFSFile, VFSStat, Time, VFS};
+use super::{Result, VFSCancellation, VFSCapabilities, VFSConnectOptions, VFSConnectionInfo, VFSDirEntry, VFSDirectory, VFSEntryType, VFSFile, VFSFileAttribute, VFSFileAttributes, VFSFileNotificationOperation, VFSFileNotificationOperationFlags, VFSResumeKey, VFSMetrics, VFSMetricsCounters, VFSNotifyChangeCallback, VFSStat, VFSWatchMode, Time, VFS, matches_pattern};
 use crate::get_parent_path_and_name;
 
 
@@ -37,20 +39,185 @@ macro_rules! using_rwlock_read {
 }  
   
 
+const ACTION_CREATE: &str = "create";
+const ACTION_REMOVE: &str = "remove";
+const ACTION_WRITE: &str = "write";
+const ACTION_RENAME: &str = "rename";
+
+/// Most space a mock file may have allocated, as if that were all the volume had free.
+const MAX_ALLOCATION_SIZE: u64 = 1 << 30;
+
+#[derive(Debug)]
+struct MockWatcher {
+    path: String,
+    mode: VFSWatchMode,
+    listen_events: VFSFileNotificationOperationFlags,
+    events_tx: Sender<(String, String, Option<String>, Option<VFSEntryType>)>,
+}
+
+impl MockWatcher {
+    fn relative_path(&self, path: &str) -> Option<String> {
+        let relative = path.strip_prefix(self.path.as_str())?.trim_end_matches('/');
+        match relative.is_empty() || (self.mode == VFSWatchMode::Default && relative.contains('/')) {
+            true => None,
+            false => Some(relative.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockTimes {
+    atime: Option<Time>,
+    mtime: Option<Time>,
+    btime: Option<Time>,
+}
+
 #[derive(Debug)]
 struct Mocks {
     dirs: BTreeSet<String>,
-    files: BTreeMap<String, Vec<u8>>
+    files: BTreeMap<String, Vec<u8>>,
+    times: BTreeMap<String, MockTimes>,
+    security: BTreeMap<String, Vec<u8>>,
+    modes: BTreeMap<String, u32>,
+    attributes: BTreeMap<String, VFSFileAttributes>,
+    links: BTreeMap<String, String>,
+    file_ids: BTreeMap<String, u64>,
+    next_file_id: u64,
+    watchers: Vec<MockWatcher>,
+}
+
+impl Mocks {
+    /// Mimic change notify, reporting paths relative to each watched directory.
+    fn notify(&mut self, path: &str, action: &str, from_path: Option<&str>) {
+        let operation = match action {
+            ACTION_CREATE => VFSFileNotificationOperation::Create,
+            ACTION_REMOVE => VFSFileNotificationOperation::Remove,
+            ACTION_RENAME => VFSFileNotificationOperation::Rename,
+            _ => VFSFileNotificationOperation::Write,
+        };
+        let kind = match path.ends_with('/') || self.dirs.contains(&format!("{}/", path)) {
+            true => VFSEntryType::Directory,
+            false => VFSEntryType::File,
+        };
+        self.watchers.retain(|watcher| {
+            let relative_path = match watcher.relative_path(path) {
+                Some(relative_path) if watcher.listen_events.contains(operation) => relative_path,
+                _ => return true,
+            };
+            let relative_from_path = from_path.and_then(|from_path| watcher.relative_path(from_path));
+            watcher.events_tx.send((relative_path, action.to_string(), relative_from_path, Some(kind))).is_ok()
+        });
+    }
+
+
+    /// Mimic file ids, which stay with a file when it is renamed.
+    fn add_file_id(&mut self, path: &str) {
+        self.next_file_id += 1;
+        let _ = self.file_ids.insert(path.to_string(), self.next_file_id);
+    }
+
+    /// The mode the entry was created with or last set to, seeded entries having a plausible one.
+    fn mode(&self, path: &str, d_type: VFSEntryType) -> u32 {
+        let key = match d_type {
+            VFSEntryType::Directory => path.trim_end_matches('/').to_string() + "/",
+            _ => path.to_string(),
+        };
+        if let Some(mode) = self.modes.get(&key) {
+            return *mode;
+        }
+        match (d_type, key.as_str()) {
+            (VFSEntryType::Directory, "/quatre/") => 0o555,
+            (VFSEntryType::Directory, _) => 0o775,
+            (VFSEntryType::Symlink, _) => 0o777,
+            (_, "/3") => 0o444,
+            _ => 0o664,
+        }
+    }
+
+    fn stat(&self, path: &String, d_type: VFSEntryType, size: u64) -> VFSStat {
+        let times = self.times.get(path);
+        let atime = times.and_then(|t| t.atime.clone());
+        let mtime = times.and_then(|t| t.mtime.clone());
+        let btime = times.and_then(|t| t.btime.clone());
+        VFSStat{
+            d_type,
+            ino: self.file_ids.get(path).copied().unwrap_or_default(),
+            mode: self.mode(path, d_type),
+            nlink: Default::default(),
+            size,
+            atime: atime.as_ref().map_or(1658159058, |t| t.seconds.into()),
+            mtime: mtime.as_ref().map_or(1658159058, |t| t.seconds.into()),
+            ctime: 1658159058,
+            btime: btime.as_ref().map_or(1658159058, |t| t.seconds.into()),
+            atime_nsec: atime.map_or(723_000_000, |t| t.nseconds),
+            mtime_nsec: mtime.map_or(723_000_000, |t| t.nseconds),
+            ctime_nsec: 720_000_000,
+            btime_nsec: btime.map_or(718_000_000, |t| t.nseconds),
+        }
+    }
+}
+
+/// Opt-in misbehaviour for testing timeouts and retries: every operation is delayed by `delay`,
+/// and the `fail_nth` operation counting from 1 fails with `ErrorKind::TimedOut`.
+/// Taken from `TEST_MOCK_DELAY_MS` and `TEST_MOCK_FAIL_NTH` when connecting.
+#[derive(Debug, Default)]
+pub(super) struct MockFaults {
+    delay: Duration,
+    fail_nth: Option<u64>,
+    calls: AtomicU64,
+}
+
+impl MockFaults {
+    pub(super) fn new(delay: Duration, fail_nth: Option<u64>) -> Self {
+        Self{delay, fail_nth, calls: AtomicU64::new(0)}
+    }
+
+    fn from_env() -> Self {
+        let var = |name| std::env::var(name).ok().and_then(|value| value.parse::<u64>().ok());
+        Self::new(Duration::from_millis(var("TEST_MOCK_DELAY_MS").unwrap_or_default()), var("TEST_MOCK_FAIL_NTH"))
+    }
+
+    fn inject(&self) -> Result<()> {
+        let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+        if !self.delay.is_zero() {
+            std::thread::sleep(self.delay);
+        }
+        match self.fail_nth {
+            Some(nth) if nth == call => Err(Error::new(ErrorKind::TimedOut, format!("injected failure of operation {}", call))),
+            _ => Ok(()),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub(super) struct SMBConnection {
     mocks: Arc<RwLock<Mocks>>,
+    options: VFSConnectOptions,
+    metrics: Arc<VFSMetricsCounters>,
+    faults: Arc<MockFaults>,
 }
 
 impl SMBConnection {
-    pub(super) fn connect(_url: String) -> Result<Box<dyn VFS>> {
-        let mut mocks = Mocks{dirs: BTreeSet::new(), files: BTreeMap::new()};
+    pub(super) fn connect(_url: String, options: VFSConnectOptions) -> Result<Box<dyn VFS>> {
+        Ok(Box::new(Self::with_faults(options, MockFaults::from_env())))
+    }
+
+    fn opendir_matching(&self, path: &str, pattern: Option<String>) -> Result<Box<dyn VFSDirectory>> {
+        self.faults.inject()?;
+        let mocks = using_rwlock_read!(&self.mocks);
+        if path != "/" && mocks.dirs.get(&path.to_string()).is_none() {
+            return Err(Error::new(ErrorKind::NotFound, "not found or not a directory"));
+        }
+        self.metrics.record_readdir();
+        Ok(Box::new(SMBSDirectory2{smb: self.clone(), path: path.to_string(), pattern, entries: None, index: 0}))
+    }
+
+    fn new(options: VFSConnectOptions) -> Self {
+        Self::with_faults(options, MockFaults::default())
+    }
+
+    pub(super) fn with_faults(options: VFSConnectOptions, faults: MockFaults) -> Self {
+        let mut mocks = Mocks{dirs: BTreeSet::new(), files: BTreeMap::new(), times: BTreeMap::new(), security: BTreeMap::new(), modes: BTreeMap::new(), attributes: BTreeMap::new(), links: BTreeMap::new(), file_ids: BTreeMap::new(), next_file_id: 0, watchers: Vec::new()};
         let _ = mocks.dirs.insert("/".into());
         let _ = mocks.dirs.insert("/first/".into());
         let _ = mocks.dirs.insert("/quatre/".into());
@@ -58,11 +225,34 @@ impl SMBConnection {
         let _ = mocks.files.insert("/annar".into(), "In order to make sure that this file is exactly 123 bytes in size, I have written this text while watching its chars count.".as_bytes().to_vec());
         let _ = mocks.files.insert("/first/comment".into(), Vec::new());
         let _ = mocks.files.insert("/quatre/points".into(), Vec::new());
-        Ok(Box::new(SMBConnection{mocks: Arc::new(RwLock::new(mocks))}))
+        for file in mocks.files.keys().cloned().collect::<Vec<String>>() {
+            mocks.add_file_id(&file);
+        }
+        SMBConnection{mocks: Arc::new(RwLock::new(mocks)), options, metrics: Arc::default(), faults: Arc::new(faults)}
     }
 }
 
 impl VFS for SMBConnection {
+    fn is_connected(&self) -> bool {
+        true
+    }
+
+    fn connection_info(&self) -> VFSConnectionInfo {
+        VFSConnectionInfo{connected: true, signing: self.options.signing}
+    }
+
+    fn capabilities(&self) -> VFSCapabilities {
+        VFSCapabilities::default()
+    }
+
+    fn metrics(&self, reset: bool) -> VFSMetrics {
+        self.metrics.snapshot(reset)
+    }
+
+    fn connect_options(&self) -> VFSConnectOptions {
+        self.options.clone()
+    }
+
     /*fn access(&self, path: &str, mode: u32) -> Result<()> {
         let p = Path::new(path);
         if let Some(name) = p.file_name() {
@@ -70,97 +260,303 @@ impl VFS for SMBConnection {
                 return Ok(());
             }
         }
-        Err(Error::new(std::io::ErrorKind::PermissionDenied, "permission denied"))
+        Err(Error::new(ErrorKind::PermissionDenied, "permission denied"))
     }*/
 
     fn stat(&self, path: &str) -> Result<VFSStat> {
+        self.faults.inject()?;
         let mocks = using_rwlock_read!(&self.mocks);
+        self.metrics.record_stat();
         let size = if let Some(c) = mocks.files.get(&path.to_string()) {
             Some(c.len() as u64)
         } else {
-            if !mocks.dirs.contains(&path.to_string()) {
-                return Err(Error::new(std::io::ErrorKind::Other, "entry not found"));
+            if !mocks.dirs.contains(&path.to_string()) && !mocks.dirs.contains(&(path.to_string() + "/")) {
+                return Err(Error::new(ErrorKind::NotFound, "entry not found"));
             }
             None
         };
-        /*let mode = if size.is_some() {
-            if path == "/3" { 0o444 } else { 0o664 }
-        } else {
-            if path == "/quatre" || path == "/quatre/" { 0o555 } else { 0o775 }
-        };*/
+        let d_type = if size.is_some() { VFSEntryType::File } else { VFSEntryType::Directory };
+        Ok(mocks.stat(&path.to_string(), d_type, size.unwrap_or_default()))
+    }
 
-        Ok(VFSStat{
-            ino: Default::default(),
-            nlink: Default::default(),
-            size: size.unwrap_or_default(),
-            atime: 1658159058723,
-            mtime: 1658159058723,
-            ctime: 1658159058720,
-            btime: 1658159058718,
-            atime_nsec: Default::default(),
-            mtime_nsec: Default::default(),
-            ctime_nsec: Default::default(),
-            btime_nsec: Default::default(),
-        })
-    }
-
-    //fn lchmod(&self, _path: &str, _mode: u32) -> Result<()> {
-    //    Ok(())
-    //}
+    fn lstat(&self, path: &str) -> Result<VFSStat> {
+        let mocks = using_rwlock_read!(&self.mocks);
+        if let Some(target) = mocks.links.get(&path.to_string()) {
+            self.faults.inject()?;
+            self.metrics.record_stat();
+            return Ok(mocks.stat(&path.to_string(), VFSEntryType::Symlink, target.len() as u64));
+        }
+        drop(mocks);
+        self.stat(path)
+    }
 
-    fn opendir(&mut self, path: &str) -> Result<Box<dyn VFSDirectory>> {
+    fn exists(&self, path: &str) -> Result<bool> {
+        self.faults.inject()?;
         let mocks = using_rwlock_read!(&self.mocks);
-        if path != "/" && mocks.dirs.get(&path.to_string()).is_none() {
-            return Err(Error::new(std::io::ErrorKind::Other, "not found or not a directory"));
+        self.metrics.record_stat();
+        let path = path.to_string();
+        Ok(mocks.files.contains_key(&path) || mocks.dirs.contains(&path) || mocks.dirs.contains(&(path.clone() + "/")))
+    }
+
+    fn attributes(&self, path: &str) -> Result<VFSFileAttributes> {
+        self.faults.inject()?;
+        let mocks = using_rwlock_read!(&self.mocks);
+        self.metrics.record_stat();
+        if mocks.links.contains_key(&path.to_string()) {
+            Ok(VFSFileAttribute::ReparsePoint.into())
+        } else if mocks.files.contains_key(&path.to_string()) {
+            Ok(mocks.attributes.get(&path.to_string()).copied().unwrap_or_default())
+        } else if path == "/" || mocks.dirs.contains(&path.to_string()) {
+            Ok(VFSFileAttribute::Directory.into())
+        } else {
+            Err(Error::new(ErrorKind::NotFound, "entry not found"))
         }
-        Ok(Box::new(SMBSDirectory2{smb: self.clone(), path: path.to_string(), entries: None, index: 0}))
     }
 
-    fn mkdir(&self, path: &str, _mode: u32) -> Result<()> {
+    fn chmod(&self, path: &str, mode: u32) -> Result<()> {
+        self.faults.inject()?;
         let mocks = &mut using_rwlock!(self.mocks);
-        let _ = mocks.dirs.insert(path.to_string() + "/");
+        let key = if mocks.files.contains_key(&path.to_string()) {
+            path.to_string()
+        } else if mocks.dirs.contains(&(path.trim_end_matches('/').to_string() + "/")) {
+            path.trim_end_matches('/').to_string() + "/"
+        } else {
+            return Err(Error::new(ErrorKind::NotFound, "entry not found"));
+        };
+        let _ = mocks.modes.insert(key, mode);
+        Ok(())
+    }
+
+    fn opendir(&mut self, path: &str) -> Result<Box<dyn VFSDirectory>> {
+        self.opendir_matching(path, None)
+    }
+
+    fn opendir_pattern(&mut self, path: &str, pattern: &str) -> Result<Box<dyn VFSDirectory>> {
+        self.opendir_matching(path, Some(pattern.to_string()))
+    }
+
+    fn mkdir(&self, path: &str, mode: u32) -> Result<()> {
+        self.faults.inject()?;
+        let mocks = &mut using_rwlock!(self.mocks);
+        if mocks.dirs.insert(path.to_string() + "/") {
+            mocks.notify(path, ACTION_CREATE, None);
+        }
+        let _ = mocks.modes.insert(path.to_string() + "/", mode);
         Ok(())
     }
 
-    fn create(&mut self, path: &str, _flags: u32, _mode: u32) -> Result<Box<dyn VFSFile>> {
+    fn create(&mut self, path: &str, flags: u32, mode: u32, attributes: VFSFileAttributes) -> Result<Box<dyn VFSFile>> {
+        self.faults.inject()?;
         let mocks = &mut using_rwlock!(self.mocks);
-        let _ = mocks.files.insert(path.to_string(), Vec::new());
-        Ok(Box::new(SMBFile2{smb: self.clone(), path: path.to_string()}))
+        let exists = mocks.files.contains_key(&path.to_string()) || mocks.dirs.contains(&(path.to_string() + "/"));
+        if exists && flags & nix::fcntl::OFlag::O_EXCL.bits() as u32 != 0 {
+            return Err(Error::new(ErrorKind::AlreadyExists, "entry already exists"));
+        }
+        match mocks.files.get_mut(&path.to_string()) {
+            Some(contents) => {
+                if flags & nix::fcntl::OFlag::O_TRUNC.bits() as u32 != 0 {
+                    contents.clear();
+                    mocks.notify(path, ACTION_WRITE, None);
+                }
+            },
+            None => {
+                mocks.files.insert(path.to_string(), Vec::new());
+                mocks.add_file_id(path);
+                mocks.notify(path, ACTION_CREATE, None);
+                let _ = mocks.modes.insert(path.to_string(), mode);
+                if !attributes.is_empty() {
+                    let _ = mocks.attributes.insert(path.to_string(), attributes);
+                }
+            },
+        }
+        self.metrics.record_open();
+        Ok(Box::new(SMBFile2{smb: self.clone(), path: path.to_string(), append: false}))
     }
 
     fn rmdir(&self, path: &str) -> Result<()> {
+        self.faults.inject()?;
         let mocks = &mut using_rwlock!(self.mocks);
         let path = path.to_string() + "/";
-        let _ = mocks.dirs.remove(&path);
+        if mocks.dirs.remove(&path) {
+            mocks.notify(&path, ACTION_REMOVE, None);
+        }
+        let _ = mocks.modes.remove(&path);
         Ok(())
     }
 
     fn unlink(&self, path: &str) -> Result<()> {
+        self.faults.inject()?;
         let mocks = &mut using_rwlock!(self.mocks);
-        let _ = mocks.files.remove(&path.to_string());
+        if mocks.files.remove(&path.to_string()).is_some() {
+            mocks.notify(path, ACTION_REMOVE, None);
+        }
+        let _ = mocks.times.remove(&path.to_string());
+        let _ = mocks.security.remove(&path.to_string());
+        let _ = mocks.modes.remove(&path.to_string());
+        let _ = mocks.attributes.remove(&path.to_string());
+        let _ = mocks.links.remove(&path.to_string());
+        let _ = mocks.file_ids.remove(&path.to_string());
         Ok(())
     }
 
-    fn open(&mut self, path: &str, _flags: u32) -> Result<Box<dyn VFSFile>> {
+    fn readlink(&self, path: &str) -> Result<String> {
+        self.faults.inject()?;
+        let mocks = using_rwlock_read!(self.mocks);
+        mocks.links.get(&path.to_string()).cloned().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "not a symlink"))
+    }
+
+    fn rename(&self, from: &str, to: &str, replace: bool) -> Result<()> {
+        self.faults.inject()?;
+        let mocks = &mut using_rwlock!(self.mocks);
+        let (from_dir, to_dir) = (format!("{}/", from.trim_end_matches('/')), format!("{}/", to.trim_end_matches('/')));
+        if mocks.dirs.contains(&from_dir) {
+            if mocks.dirs.contains(&to_dir) || mocks.files.contains_key(to_dir.trim_end_matches('/')) {
+                return Err(Error::new(ErrorKind::AlreadyExists, "entry already exists"));
+            }
+            let moved_dirs: Vec<String> = mocks.dirs.iter().filter(|dir| dir.starts_with(&from_dir)).cloned().collect();
+            for dir in moved_dirs {
+                mocks.dirs.remove(&dir);
+                mocks.dirs.insert(dir.replacen(&from_dir, &to_dir, 1));
+            }
+            let moved_files: Vec<String> = mocks.files.keys().filter(|file| file.starts_with(&from_dir)).cloned().collect();
+            for file in moved_files {
+                if let Some(contents) = mocks.files.remove(&file) {
+                    mocks.files.insert(file.replacen(&from_dir, &to_dir, 1), contents);
+                }
+                if let Some(file_id) = mocks.file_ids.remove(&file) {
+                    mocks.file_ids.insert(file.replacen(&from_dir, &to_dir, 1), file_id);
+                }
+            }
+            mocks.notify(&to_dir, ACTION_RENAME, Some(&from_dir));
+            return Ok(());
+        }
+        let (from, to) = (from.to_string(), to.to_string());
+        if !mocks.files.contains_key(&from) {
+            return Err(Error::new(ErrorKind::NotFound, "entry not found"));
+        }
+        if mocks.files.contains_key(&to) && !replace {
+            return Err(Error::new(ErrorKind::AlreadyExists, "entry already exists"));
+        }
+        let _ = mocks.times.remove(&to);
+        let _ = mocks.security.remove(&to);
+        let _ = mocks.modes.remove(&to);
+        let _ = mocks.attributes.remove(&to);
+        if let Some(contents) = mocks.files.remove(&from) {
+            mocks.files.insert(to.clone(), contents);
+        }
+        if let Some(times) = mocks.times.remove(&from) {
+            mocks.times.insert(to.clone(), times);
+        }
+        if let Some(descriptor) = mocks.security.remove(&from) {
+            mocks.security.insert(to.clone(), descriptor);
+        }
+        if let Some(mode) = mocks.modes.remove(&from) {
+            mocks.modes.insert(to.clone(), mode);
+        }
+        if let Some(attributes) = mocks.attributes.remove(&from) {
+            mocks.attributes.insert(to.clone(), attributes);
+        }
+        if let Some(file_id) = mocks.file_ids.remove(&from) {
+            mocks.file_ids.insert(to.clone(), file_id);
+        }
+        mocks.notify(&to, ACTION_RENAME, Some(&from));
+        Ok(())
+    }
+
+    fn open(&mut self, path: &str, flags: u32) -> Result<Box<dyn VFSFile>> {
+        self.faults.inject()?;
         let mocks = &mut using_rwlock!(self.mocks);
         if mocks.dirs.get(&path.to_string()).is_some() {
-            return Err(Error::new(std::io::ErrorKind::Other, "is a directory"));
+            return Err(Error::new(ErrorKind::IsADirectory, "is a directory"));
         }
         if mocks.files.get(&path.to_string()).is_none() {
             mocks.files.insert(path.to_string(), Vec::new());
+            mocks.add_file_id(path);
         }
-        Ok(Box::new(SMBFile2{smb: self.clone(), path: path.to_string()}))
+        self.metrics.record_open();
+        let append = flags & nix::fcntl::OFlag::O_APPEND.bits() as u32 != 0;
+        Ok(Box::new(SMBFile2{smb: self.clone(), path: path.to_string(), append}))
     }
 
     fn truncate(&self, path: &str, len: u64) -> Result<()> {
+        self.faults.inject()?;
         let mocks = &mut using_rwlock!(self.mocks);
         let contents = mocks.files.entry(path.to_string()).or_default();
         contents.resize(len as usize, 0);
+        mocks.notify(path, ACTION_WRITE, None);
         Ok(())
     }
 
-    fn watch(&self, _path: &str, _mode: super::VFSWatchMode, _listen_events: super::VFSFileNotificationOperationFlags, _cb: Box<dyn super::VFSNotifyChangeCallback>, _ready_tx: &Sender<bool>, _cancelled_rx: &Receiver<bool>) {
-        todo!("watch unimplemented for mock")
+    fn fsync_dir(&self, path: &str) -> Result<()> {
+        self.faults.inject()?;
+        let mocks = using_rwlock_read!(&self.mocks);
+        if path != "/" && !mocks.dirs.contains(&path.to_string()) {
+            return Err(Error::new(ErrorKind::NotFound, "not found or not a directory"));
+        }
+        Ok(())
+    }
+
+    fn set_times(&self, path: &str, atime: Option<Time>, mtime: Option<Time>, btime: Option<Time>) -> Result<()> {
+        self.faults.inject()?;
+        let mocks = &mut using_rwlock!(self.mocks);
+        if !mocks.files.contains_key(&path.to_string()) {
+            return Err(Error::new(ErrorKind::NotFound, "entry not found"));
+        }
+        let times = mocks.times.entry(path.to_string()).or_default();
+        if atime.is_some() {
+            times.atime = atime;
+        }
+        if mtime.is_some() {
+            times.mtime = mtime;
+        }
+        if btime.is_some() {
+            times.btime = btime;
+        }
+        Ok(())
+    }
+
+    fn get_security(&self, path: &str) -> Result<Vec<u8>> {
+        self.faults.inject()?;
+        let mocks = using_rwlock!(self.mocks);
+        if !mocks.files.contains_key(&path.to_string()) && !mocks.dirs.contains(&path.to_string()) {
+            return Err(Error::new(ErrorKind::NotFound, "entry not found"));
+        }
+        Ok(mocks.security.get(&path.to_string()).cloned().unwrap_or_default())
+    }
+
+    fn set_security(&self, path: &str, descriptor: &[u8]) -> Result<()> {
+        self.faults.inject()?;
+        let mocks = &mut using_rwlock!(self.mocks);
+        if !mocks.files.contains_key(&path.to_string()) && !mocks.dirs.contains(&path.to_string()) {
+            return Err(Error::new(ErrorKind::NotFound, "entry not found"));
+        }
+        mocks.security.insert(path.to_string(), descriptor.to_vec());
+        Ok(())
+    }
+
+    fn server_time(&mut self, path: &str) -> Result<Time> {
+        self.faults.inject()?;
+        let mocks = using_rwlock_read!(&self.mocks);
+        if path != "/" && !mocks.dirs.contains(&path.to_string()) {
+            return Err(Error::new(ErrorKind::NotFound, "not found or not a directory"));
+        }
+        Ok(Time{seconds: 1700000000, nseconds: 0})
+    }
+
+    fn watch(&self, path: &str, mode: VFSWatchMode, listen_events: VFSFileNotificationOperationFlags, cb: Box<dyn VFSNotifyChangeCallback>, ready_tx: &Sender<bool>, cancellation: &VFSCancellation) -> Result<()> {
+        let (events_tx, events_rx) = channel();
+        using_rwlock!(self.mocks).watchers.push(MockWatcher{path: format!("{}/", path.trim_end_matches('/')), mode, listen_events, events_tx});
+        let _ = ready_tx.send(true);
+        loop {
+            match events_rx.try_recv() {
+                Ok((path, action, from_path, kind)) => cb.call(path, action, from_path, kind),
+                Err(TryRecvError::Empty) => if cancellation.wait_timeout(Duration::from_millis(10)) {
+                    break;
+                },
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        Ok(())
     }
 }
 
@@ -168,6 +564,7 @@ impl VFS for SMBConnection {
 pub struct SMBSDirectory2 {
     smb: SMBConnection,
     path: String,
+    pattern: Option<String>,
     entries: Option<Vec<VFSDirEntry>>,
     index: usize,
 }
@@ -186,13 +583,35 @@ impl Iterator for SMBSDirectory2 {
             for (mock_file, content) in &mocks.files {
                 let (parent_path, name) = get_parent_path_and_name(&mock_file);
                 if parent_path == self.path {
-                        //let mode = if mock_file == "/3" { 0o444 } else { 0o664 };
+                        let mtime = mocks.times.get(mock_file).and_then(|t| t.mtime.clone()).unwrap_or(Time{seconds: 1658159058, nseconds: 0});
                         entries.push(VFSDirEntry{
                         path: name,
-                        inode: Default::default(),
+                        inode: mocks.file_ids.get(mock_file).copied().unwrap_or_default(),
+                        mode: mocks.mode(mock_file, VFSEntryType::File),
                         d_type: VFSEntryType::File,
                         size: content.len() as u64,
                         atime: Time{seconds: 1658159058, nseconds: 0},
+                        mtime_nsec: mtime.nseconds,
+                        mtime,
+                        ctime: Time{seconds: 1658159055, nseconds: 0},
+                        btime: Time{seconds: 1658159053, nseconds: 0},
+                        nlink: Default::default(),
+                        atime_nsec: Default::default(),
+                        ctime_nsec: Default::default(),
+                        btime_nsec: Default::default(),
+                    });
+                }
+            }
+            for mock_link in mocks.links.keys() {
+                let (parent_path, name) = get_parent_path_and_name(&mock_link);
+                if parent_path == self.path {
+                    entries.push(VFSDirEntry{
+                        path: name,
+                        inode: Default::default(),
+                        mode: mocks.mode(mock_link, VFSEntryType::Symlink),
+                        d_type: VFSEntryType::Symlink,
+                        size: Default::default(),
+                        atime: Time{seconds: 1658159058, nseconds: 0},
                         mtime: Time{seconds: 1658159058, nseconds: 0},
                         ctime: Time{seconds: 1658159055, nseconds: 0},
                         btime: Time{seconds: 1658159053, nseconds: 0},
@@ -207,10 +626,10 @@ impl Iterator for SMBSDirectory2 {
             for mock_dir in mocks.dirs.iter().rev() {
                 let (parent_path, name) = get_parent_path_and_name(&mock_dir.trim_end_matches('/').into());
                 if parent_path == self.path {
-                    //let mode = if mock_dir == "/quatre/" { 0o555 } else { 0o775 };
                     entries.push(VFSDirEntry{
                         path: name,
                         inode: Default::default(),
+                        mode: mocks.mode(mock_dir, VFSEntryType::Directory),
                         d_type: VFSEntryType::Directory,
                         size: Default::default(),
                         atime: Time{seconds: 1658159058, nseconds: 0},
@@ -225,6 +644,9 @@ impl Iterator for SMBSDirectory2 {
                     });
                 }
             }
+            if let Some(pattern) = &self.pattern {
+                entries.retain(|entry| matches_pattern(&entry.path, pattern));
+            }
             self.entries = Some(entries);
             self.index = 0;
         }
@@ -247,29 +669,20 @@ impl Iterator for SMBSDirectory2 {
 pub struct SMBFile2 {
     smb: SMBConnection,
     path: String,
+    append: bool,
 }
 
 impl VFSFile for SMBFile2 {
     fn fstat(&self) -> Result<VFSStat> {
+        self.smb.faults.inject()?;
         let mocks = using_rwlock_read!(self.smb.mocks);
+        self.smb.metrics.record_stat();
         let size = if let Some(c) = mocks.files.get(&self.path) {
             c.len() as u64
         } else {
             0
         };
-        Ok(VFSStat{
-            ino: Default::default(),
-            nlink: Default::default(),
-            size,
-            atime: 1658159058723,
-            mtime: 1658159058723,
-            ctime: 1658159058720,
-            btime: 1658159058718,
-            atime_nsec: Default::default(),
-            mtime_nsec: Default::default(),
-            ctime_nsec: Default::default(),
-            btime_nsec: Default::default(),
-        })
+        Ok(mocks.stat(&self.path, VFSEntryType::File, size))
     }
 
     fn get_max_read_size(&self) -> u64 {
@@ -277,6 +690,7 @@ impl VFSFile for SMBFile2 {
     }
 
     fn pread_into(&self, count: u32, offset: u64, buffer: &mut [u8]) -> Result<u32> {
+        self.smb.faults.inject()?;
         let mocks = using_rwlock_read!(self.smb.mocks);
         let readlen = if let Some(content) = mocks.files.get(&self.path) {
             let (offset, count, len) = (offset as usize, count as usize, content.len());
@@ -288,13 +702,16 @@ impl VFSFile for SMBFile2 {
         } else {
             0
         };
+        self.smb.metrics.record_read(readlen as u64);
         Ok(readlen)
     }
 
     fn pwrite(&self, buffer: &[u8], offset: u64) -> Result<u32> {
+        self.smb.faults.inject()?;
         let mut mocks = using_rwlock!(self.smb.mocks);
+        mocks.notify(&self.path, ACTION_WRITE, None);
         let contents = mocks.files.entry(self.path.clone()).or_default();
-        let offset = offset as usize;
+        let offset = if self.append { contents.len() } else { offset as usize };
         let writelen = if contents.len() >= offset + buffer.len() {
             contents.splice(offset..(offset + buffer.len()), buffer.iter().cloned());
             buffer.len() as u32
@@ -304,17 +721,67 @@ impl VFSFile for SMBFile2 {
             contents.append(&mut buffer.to_vec());
             (padlen + buffer.len()) as u32
         };
+        self.smb.metrics.record_write(writelen as u64);
         Ok(writelen)
     }
+
+    fn fsync(&self) -> Result<()> {
+        self.smb.faults.inject()
+    }
+
+    fn set_allocation_size(&self, len: u64) -> Result<()> {
+        self.smb.faults.inject()?;
+        if len > MAX_ALLOCATION_SIZE {
+            return Err(Error::new(ErrorKind::StorageFull, "not enough space"));
+        }
+        let mut mocks = using_rwlock!(self.smb.mocks);
+        let contents = mocks.files.entry(self.path.clone()).or_default();
+        contents.reserve_exact((len as usize).saturating_sub(contents.len()));
+        Ok(())
+    }
+
+    fn ftruncate(&self, len: u64) -> Result<()> {
+        self.smb.faults.inject()?;
+        let mut mocks = using_rwlock!(self.smb.mocks);
+        mocks.files.entry(self.path.clone()).or_default().resize(len as usize, 0);
+        mocks.notify(&self.path, ACTION_WRITE, None);
+        Ok(())
+    }
+
+    fn allocated_ranges(&self, _offset: u64, _len: u64) -> Result<Vec<(u64, u64)>> {
+        Err(Error::new(ErrorKind::Unsupported, "mock files are not sparse"))
+    }
+
+    fn set_sparse(&self) -> Result<()> {
+        Err(Error::new(ErrorKind::Unsupported, "mock files are not sparse"))
+    }
+
+    fn set_zero_data(&self, _offset: u64, _len: u64) -> Result<()> {
+        Err(Error::new(ErrorKind::Unsupported, "mock files are not sparse"))
+    }
+
+    fn resume_key(&self) -> Result<VFSResumeKey> {
+        Err(Error::new(ErrorKind::Unsupported, "mock files can't be copied server-side"))
+    }
+
+    fn copy_chunks(&self, _source_key: &VFSResumeKey, _chunks: &[(u64, u64, u32)]) -> Result<u64> {
+        Err(Error::new(ErrorKind::Unsupported, "mock files can't be copied server-side"))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::smb::{VFSSymlinkPolicy, VFSTraversal};
+
+    /// A connection to a fresh copy of the mock share, with its seeded entries.
+    fn mock_connection() -> SMBConnection {
+        SMBConnection::new(VFSConnectOptions::default())
+    }
 
     #[test]
     fn mock_implementation_works() {
-        let smb = SMBConnection::connect(String::new());
+        let smb = SMBConnection::connect(String::new(), VFSConnectOptions::default());
         match smb {
             Ok(mut smb) => {
                 let res = smb.opendir("/");
@@ -361,4 +828,359 @@ mod tests {
             Err(_) => {},
         }
     }
+
+    #[test]
+    fn mock_create_exclusive_fails_without_truncating() {
+        let mut smb = mock_connection();
+        let flags = nix::fcntl::OFlag::O_RDWR | nix::fcntl::OFlag::O_CREAT | nix::fcntl::OFlag::O_EXCL | nix::fcntl::OFlag::O_TRUNC;
+        let file = smb.create("/exclusive", flags.bits() as u32, 0o444, VFSFileAttributes::empty()).unwrap();
+        file.pwrite(b"kept", 0).unwrap();
+        let err = smb.create("/exclusive", flags.bits() as u32, 0o644, VFSFileAttributes::empty()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        let mocks = using_rwlock!(smb.mocks);
+        assert_eq!(mocks.files.get("/exclusive"), Some(&b"kept".to_vec()));
+        assert_eq!(mocks.modes.get("/exclusive"), Some(&0o444));
+    }
+
+    #[test]
+    fn mock_create_truncates_only_with_o_trunc() {
+        let mut smb = mock_connection();
+        let flags = nix::fcntl::OFlag::O_RDWR | nix::fcntl::OFlag::O_CREAT;
+        smb.create("/truncated", flags.bits() as u32, 0o600, VFSFileAttributes::empty()).unwrap().pwrite(b"contents", 0).unwrap();
+        smb.create("/truncated", flags.bits() as u32, 0o644, VFSFileAttributes::empty()).unwrap();
+        assert_eq!(using_rwlock!(smb.mocks).files.get("/truncated"), Some(&b"contents".to_vec()));
+        smb.create("/truncated", (flags | nix::fcntl::OFlag::O_TRUNC).bits() as u32, 0o644, VFSFileAttributes::empty()).unwrap();
+        let mocks = using_rwlock!(smb.mocks);
+        assert_eq!(mocks.files.get("/truncated"), Some(&Vec::new()));
+        assert_eq!(mocks.modes.get("/truncated"), Some(&0o600));
+    }
+
+    #[test]
+    fn mock_opendir_pattern_filters_names() {
+        let mut smb = mock_connection();
+        let names = |dir: Box<dyn VFSDirectory>| dir.map(|entry| entry.unwrap().path).collect::<Vec<String>>();
+        assert_eq!(names(smb.opendir_pattern("/", "A*").unwrap()), vec!["annar"]);
+        assert_eq!(names(smb.opendir_pattern("/", "?").unwrap()), vec!["3"]);
+        assert_eq!(names(smb.opendir_pattern("/", "*r*").unwrap()), vec!["annar", "quatre", "first"]);
+        assert!(names(smb.opendir_pattern("/", "*.log").unwrap()).is_empty());
+        assert!(matches_pattern("points", "p*n?s"));
+        assert!(matches_pattern("a.b.c", "*.c"));
+        assert!(!matches_pattern("a.b.c", "*.b"));
+        assert!(matches_pattern("", "*"));
+    }
+
+    #[test]
+    fn mock_exists() {
+        let smb = mock_connection();
+        assert!(smb.exists("/").unwrap());
+        assert!(smb.exists("/annar").unwrap());
+        assert!(smb.exists("/first").unwrap());
+        assert!(smb.exists("/first/").unwrap());
+        assert!(smb.exists("/first/comment").unwrap());
+        assert!(!smb.exists("/missing").unwrap());
+        assert!(!smb.exists("/first/missing/").unwrap());
+    }
+
+    #[test]
+    fn mock_create_records_attributes_of_new_files_only() {
+        let mut smb = mock_connection();
+        let flags = nix::fcntl::OFlag::O_RDWR | nix::fcntl::OFlag::O_CREAT;
+        let hidden = VFSFileAttributes::from(VFSFileAttribute::Hidden);
+        smb.create("/hidden", flags.bits() as u32, 0o644, hidden).unwrap();
+        assert_eq!(smb.attributes("/hidden").unwrap(), hidden);
+        smb.create("/hidden", flags.bits() as u32, 0o644, VFSFileAttribute::System.into()).unwrap();
+        assert_eq!(smb.attributes("/hidden").unwrap(), hidden);
+        smb.rename("/hidden", "/still_hidden", false).unwrap();
+        assert_eq!(smb.attributes("/still_hidden").unwrap(), hidden);
+        smb.unlink("/still_hidden").unwrap();
+        smb.create("/still_hidden", flags.bits() as u32, 0o644, VFSFileAttributes::empty()).unwrap();
+        assert_eq!(smb.attributes("/still_hidden").unwrap(), VFSFileAttributes::empty());
+    }
+
+    #[test]
+    fn mock_mkdir_records_mode() {
+        let smb = mock_connection();
+        let res = smb.mkdir("/moded", 0o750);
+        assert!(res.is_ok(), "err = {}", res.unwrap_err());
+        let mocks = using_rwlock!(smb.mocks);
+        assert_eq!(mocks.modes.get("/moded/"), Some(&0o750));
+    }
+
+    #[test]
+    fn mock_root_stats_as_a_directory() {
+        let smb = mock_connection();
+        for path in ["/", ""] {
+            let stat = smb.stat(path).unwrap();
+            assert_eq!(stat.d_type, VFSEntryType::Directory, "{:?}", path);
+            assert_eq!(smb.lstat(path).unwrap().d_type, VFSEntryType::Directory, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn mock_chmod_shows_in_stat_and_listing() {
+        let mut smb = mock_connection();
+        let flags = nix::fcntl::OFlag::O_CREAT | nix::fcntl::OFlag::O_WRONLY;
+        smb.create("/first/moded", flags.bits() as u32, 0o644, VFSFileAttributes::empty()).unwrap();
+        assert_eq!(smb.stat("/first/moded").unwrap().mode, 0o644);
+        smb.chmod("/first/moded", 0o600).unwrap();
+        smb.chmod("/first", 0o700).unwrap();
+        assert_eq!(smb.stat("/first/moded").unwrap().mode, 0o600);
+        assert_eq!(smb.stat("/first").unwrap().mode, 0o700);
+        let listed = smb.opendir("/first/").unwrap().map(|entry| entry.unwrap()).find(|entry| entry.path == "moded").unwrap();
+        assert_eq!(listed.stat().mode, 0o600);
+        assert_eq!(smb.stat("/annar").unwrap().mode, 0o664);
+        assert_eq!(smb.stat("/3").unwrap().mode, 0o444);
+        assert_eq!(smb.stat("/quatre").unwrap().mode, 0o555);
+        assert_eq!(smb.chmod("/missing", 0o600).unwrap_err().kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn mock_stat_many_reports_each_path() {
+        let smb = mock_connection();
+        let paths = vec!["/annar".to_string(), "/missing".to_string(), "/first".to_string()];
+        let stats = smb.stat_many(&paths).unwrap();
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[0].as_ref().unwrap().size, 123);
+        assert_eq!(stats[1].as_ref().unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(stats[2].as_ref().unwrap().d_type, VFSEntryType::Directory);
+        assert_eq!(smb.metrics(false).stat_ops, 3);
+    }
+
+    #[test]
+    fn mock_symlink_to_ancestor_is_not_followed() {
+        let mut smb = mock_connection();
+        let _ = using_rwlock!(smb.mocks).links.insert("/first/loop".into(), "..".into());
+        let _ = using_rwlock!(smb.mocks).links.insert("/first/sibling".into(), "../quatre".into());
+        let _ = using_rwlock!(smb.mocks).links.insert("/first/outside".into(), "../../elsewhere".into());
+
+        let mut skip = VFSTraversal::new(VFSSymlinkPolicy::Skip, "/first/");
+        assert_eq!(skip.descend(&mut smb, "/first/", &VFSEntryType::Directory).unwrap(), Some("/first/".to_string()));
+        assert_eq!(skip.descend(&mut smb, "/first/loop", &VFSEntryType::Symlink).unwrap(), None);
+        assert_eq!(skip.descend(&mut smb, "/first/sibling", &VFSEntryType::Symlink).unwrap(), None);
+
+        let mut error = VFSTraversal::new(VFSSymlinkPolicy::Error, "/first/");
+        assert!(error.descend(&mut smb, "/first/loop", &VFSEntryType::Symlink).is_err());
+
+        let mut follow = VFSTraversal::new(VFSSymlinkPolicy::Follow, "/first/");
+        assert_eq!(follow.descend(&mut smb, "/first/", &VFSEntryType::Directory).unwrap(), Some("/first/".to_string()));
+        assert_eq!(follow.descend(&mut smb, "/first/loop", &VFSEntryType::Symlink).unwrap(), None);
+        assert_eq!(follow.descend(&mut smb, "/first/outside", &VFSEntryType::Symlink).unwrap(), None);
+        assert_eq!(follow.descend(&mut smb, "/first/sibling", &VFSEntryType::Symlink).unwrap(), Some("/quatre/".to_string()));
+        assert_eq!(follow.descend(&mut smb, "/quatre/", &VFSEntryType::Directory).unwrap(), None);
+    }
+
+    #[test]
+    fn mock_opendir_lists_large_directory() {
+        let mut smb = mock_connection();
+        smb.mkdir("/large", 0o775).unwrap();
+        for i in 0..100_000 {
+            using_rwlock!(smb.mocks).files.insert(format!("/large/{}", i), Vec::new());
+        }
+        let dir = smb.opendir("/large/").unwrap();
+        let mut count = 0;
+        for entry in dir {
+            assert!(entry.is_ok(), "err = {}", entry.unwrap_err());
+            count += 1;
+        }
+        assert_eq!(count, 100_000);
+    }
+
+    #[test]
+    fn mock_lstat_describes_symlink_itself() {
+        let smb = mock_connection();
+        let _ = using_rwlock!(smb.mocks).links.insert("/first/sibling".into(), "../quatre".into());
+        assert_eq!(smb.lstat("/first/sibling").unwrap().d_type, VFSEntryType::Symlink);
+        assert_eq!(smb.lstat("/annar").unwrap().d_type, VFSEntryType::File);
+        assert_eq!(smb.stat("/quatre/").unwrap().d_type, VFSEntryType::Directory);
+    }
+
+    struct ForwardingCallback(Sender<(String, String, Option<String>, Option<VFSEntryType>)>);
+
+    impl VFSNotifyChangeCallback for ForwardingCallback {
+        fn call(&self, path: String, action: String, from_path: Option<String>, kind: Option<VFSEntryType>) {
+            let _ = self.0.send((path, action, from_path, kind));
+        }
+    }
+
+    #[test]
+    fn mock_watch_reports_rename_with_old_name() {
+        let smb = mock_connection();
+        let watcher = smb.clone();
+        let (ready_tx, ready_rx) = channel();
+        let cancellation = Arc::new(VFSCancellation::new().unwrap());
+        let watcher_cancellation = cancellation.clone();
+        let (events_tx, events_rx) = channel();
+        let handle = std::thread::spawn(move || {
+            let cb = Box::new(ForwardingCallback(events_tx));
+            watcher.watch("/", VFSWatchMode::Recursive, VFSFileNotificationOperation::all(), cb, &ready_tx, &watcher_cancellation).unwrap();
+        });
+        assert_eq!(ready_rx.recv(), Ok(true));
+
+        smb.rename("/annar", "/first/annar", false).unwrap();
+        let event = events_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event, ("first/annar".to_string(), "rename".to_string(), Some("annar".to_string()), Some(VFSEntryType::File)));
+
+        smb.mkdir("/first/sub", 0o775).unwrap();
+        let event = events_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event, ("first/sub".to_string(), "create".to_string(), None, Some(VFSEntryType::Directory)));
+
+        cancellation.cancel();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn mock_watch_stops_promptly_on_cancel() {
+        let smb = mock_connection();
+        let cancellation = Arc::new(VFSCancellation::new().unwrap());
+        let watcher_cancellation = cancellation.clone();
+        let (ready_tx, ready_rx) = channel();
+        let (done_tx, done_rx) = channel();
+        std::thread::spawn(move || {
+            let (events_tx, _events_rx) = channel();
+            let cb = Box::new(ForwardingCallback(events_tx));
+            smb.watch("/", VFSWatchMode::Recursive, VFSFileNotificationOperation::all(), cb, &ready_tx, &watcher_cancellation).unwrap();
+            let _ = done_tx.send(());
+        });
+        assert_eq!(ready_rx.recv(), Ok(true));
+
+        let started = std::time::Instant::now();
+        cancellation.cancel();
+        cancellation.cancel();
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(1)), Ok(()));
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(cancellation.is_cancelled());
+        assert!(cancellation.wait_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn mock_poll_watch_reports_differences_between_listings() {
+        use crate::smb::poll_watch;
+        let mut smb = mock_connection();
+        let mut watcher = smb.clone();
+        let (ready_tx, ready_rx) = channel();
+        let cancellation = Arc::new(VFSCancellation::new().unwrap());
+        let watcher_cancellation = cancellation.clone();
+        let (events_tx, events_rx) = channel();
+        let handle = std::thread::spawn(move || {
+            let cb = Box::new(ForwardingCallback(events_tx));
+            poll_watch(&mut watcher, "/", VFSFileNotificationOperation::all(), Duration::from_millis(10), cb, &ready_tx, &watcher_cancellation).unwrap();
+        });
+        assert_eq!(ready_rx.recv(), Ok(true));
+
+        let flags = nix::fcntl::OFlag::O_RDWR | nix::fcntl::OFlag::O_CREAT;
+        smb.create("/first/polled", flags.bits() as u32, 0o644, VFSFileAttributes::empty()).unwrap();
+        let event = events_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event, ("first/polled".to_string(), "create".to_string(), None, Some(VFSEntryType::File)));
+
+        smb.open("/first/polled", nix::fcntl::OFlag::O_RDWR.bits() as u32).unwrap().pwrite(b"polled", 0).unwrap();
+        let event = events_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event, ("first/polled".to_string(), "write".to_string(), None, None));
+
+        smb.unlink("/first/polled").unwrap();
+        let event = events_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event, ("first/polled".to_string(), "remove".to_string(), None, Some(VFSEntryType::File)));
+
+        cancellation.cancel();
+        handle.join().unwrap();
+        assert!(events_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn mock_poll_watch_fails_to_start_on_a_missing_directory() {
+        use crate::smb::poll_watch;
+        let mut smb = mock_connection();
+        let (ready_tx, ready_rx) = channel();
+        let cancellation = VFSCancellation::new().unwrap();
+        let (events_tx, _events_rx) = channel();
+        let cb = Box::new(ForwardingCallback(events_tx));
+        let res = poll_watch(&mut smb, "/missing/", VFSFileNotificationOperation::all(), Duration::from_millis(10), cb, &ready_tx, &cancellation);
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(ready_rx.try_recv(), Ok(false));
+    }
+
+    #[test]
+    fn mock_find_matches_size_criteria() {
+        use crate::smb::{find, VFSFindCriteria};
+        let mut smb = mock_connection();
+        let flags = nix::fcntl::OFlag::O_RDWR | nix::fcntl::OFlag::O_CREAT;
+        smb.create("/first/small", flags.bits() as u32, 0o644, VFSFileAttributes::empty()).unwrap().pwrite(b"s", 0).unwrap();
+        smb.create("/first/large", flags.bits() as u32, 0o644, VFSFileAttributes::empty()).unwrap().pwrite(&[0u8; 4096], 0).unwrap();
+        let criteria = VFSFindCriteria{min_size: Some(2), ..Default::default()};
+        let found: Vec<String> = find(&mut smb, "/first", &criteria, None).unwrap().into_iter().map(|(path, _)| path).collect();
+        assert_eq!(found, vec!["large".to_string()]);
+        let criteria = VFSFindCriteria{max_size: Some(1), directory: Some(false), ..Default::default()};
+        let mut found: Vec<String> = find(&mut smb, "/first", &criteria, None).unwrap().into_iter().map(|(path, _)| path).collect();
+        found.sort();
+        assert_eq!(found, vec!["comment".to_string(), "small".to_string()]);
+        let criteria = VFSFindCriteria{min_size: Some(4096), max_size: Some(4096), name_pattern: Some("L*".to_string()), ..Default::default()};
+        let mut traversal = VFSTraversal::new(VFSSymlinkPolicy::Skip, "/");
+        let found: Vec<String> = find(&mut smb, "/", &criteria, Some(&mut traversal)).unwrap().into_iter().map(|(path, _)| path).collect();
+        assert_eq!(found, vec!["first/large".to_string()]);
+    }
+
+    #[test]
+    fn mock_find_matches_mtime_criteria() {
+        use crate::smb::{find, VFSFindCriteria};
+        let mut smb = mock_connection();
+        smb.set_times("/quatre/points", None, Some(Time::from_nanoseconds(1_700_000_000_500_000_000).unwrap()), None).unwrap();
+        let criteria = VFSFindCriteria{modified_after: Time::from_nanoseconds(1_700_000_000_000_000_000), ..Default::default()};
+        let mut traversal = VFSTraversal::new(VFSSymlinkPolicy::Skip, "/");
+        let found = find(&mut smb, "/", &criteria, Some(&mut traversal)).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, "quatre/points");
+        assert_eq!(found[0].1.stat().modified(), Time::from_nanoseconds(1_700_000_000_500_000_000).unwrap());
+        let criteria = VFSFindCriteria{modified_after: Time::from_nanoseconds(1_700_000_000_500_000_000), ..Default::default()};
+        assert!(find(&mut smb, "/quatre", &criteria, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn mock_faults_delay_and_fail_the_nth_operation() {
+        let smb = SMBConnection::with_faults(VFSConnectOptions::default(), MockFaults::new(Duration::from_millis(20), Some(2)));
+        let started = std::time::Instant::now();
+        assert!(smb.stat("/annar").is_ok());
+        let err = smb.stat("/annar").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(smb.stat("/annar").is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn mock_rename_keeps_an_existing_target_without_replace() {
+        let smb = mock_connection();
+        let err = smb.rename("/annar", "/3", false).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        let mocks = using_rwlock!(smb.mocks);
+        assert_eq!(mocks.files.get("/annar").map(Vec::len), Some(123));
+        assert!(mocks.files.contains_key("/3"));
+    }
+
+    #[test]
+    fn mock_rename_replaces_an_existing_target_with_replace() {
+        let smb = mock_connection();
+        smb.rename("/annar", "/3", true).unwrap();
+        assert_eq!(smb.stat("/3").unwrap().size, 123);
+        assert_eq!(smb.stat("/annar").unwrap_err().kind(), ErrorKind::NotFound);
+        let err = smb.rename("/first", "/quatre", true).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn mock_tree_lists_the_seeded_tree() {
+        use crate::smb::{tree, VFSTreeNode};
+        fn names(nodes: &[VFSTreeNode]) -> Vec<&str> {
+            nodes.iter().map(|node| node.entry.path.as_str()).collect()
+        }
+        let mut smb = mock_connection();
+        let mut traversal = VFSTraversal::new(VFSSymlinkPolicy::Skip, "/");
+        let nodes = tree(&mut smb, "/", None, &mut traversal).unwrap();
+        assert_eq!(names(&nodes), vec!["3", "annar", "first", "quatre"]);
+        assert!(nodes[0].children.is_none());
+        assert_eq!(nodes[1].entry.stat().size, 123);
+        assert_eq!(names(nodes[2].children.as_ref().unwrap()), vec!["comment"]);
+        assert_eq!(names(nodes[3].children.as_ref().unwrap()), vec!["points"]);
+        let mut traversal = VFSTraversal::new(VFSSymlinkPolicy::Skip, "/");
+        let nodes = tree(&mut smb, "/", Some(1), &mut traversal).unwrap();
+        assert_eq!(names(&nodes), vec!["3", "annar", "first", "quatre"]);
+        assert!(nodes.iter().all(|node| node.children.is_none()));
+    }
 }
@@ -0,0 +1,106 @@
+// Copyright 2025 NetApp Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::BTreeMap;
+use std::io::Result;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use super::{Time, VFSCancellation, VFSEntryType, VFSFileNotificationOperation, VFSFileNotificationOperationFlags, VFSNotifyChangeCallback, VFS};
+
+const ACTION_CREATE: &str = "create";
+const ACTION_REMOVE: &str = "remove";
+const ACTION_WRITE: &str = "write";
+
+/// What a listing tells of an entry, a change of which between two polls is reported as a write.
+#[derive(Clone, Debug, PartialEq)]
+struct PolledEntry {
+    d_type: VFSEntryType,
+    size: u64,
+    mtime: Time,
+}
+
+/// Watches the tree under `path` by listing it every `interval` and reporting how each listing differs
+/// from the one before, for servers that reject change notify requests. Renames show as a removal and
+/// a creation, and changes undone within one interval go unnoticed. Sends false on `ready_tx` if the
+/// first listing fails.
+pub fn poll_watch(vfs: &mut dyn VFS, path: &str, listen_events: VFSFileNotificationOperationFlags, interval: Duration, cb: Box<dyn VFSNotifyChangeCallback>, ready_tx: &Sender<bool>, cancellation: &VFSCancellation) -> Result<()> {
+    let mut previous = match list_tree(vfs, path) {
+        Ok(entries) => entries,
+        Err(err) => {
+            let _ = ready_tx.send(false);
+            return Err(err);
+        }
+    };
+    let _ = ready_tx.send(true);
+    while !cancellation.wait_timeout(interval) {
+        let current = list_tree(vfs, path)?;
+        report_changes(&previous, &current, listen_events, cb.as_ref());
+        previous = current;
+    }
+    Ok(())
+}
+
+/// Lists the entries under `root` by path relative to it, descending into subdirectories but never
+/// into symlinks.
+fn list_tree(vfs: &mut dyn VFS, root: &str) -> Result<BTreeMap<String, PolledEntry>> {
+    let mut entries = BTreeMap::new();
+    let mut pending = vec![String::new()];
+    while let Some(relative_dir) = pending.pop() {
+        let dir = format!("{}/{}", root.trim_end_matches('/'), relative_dir);
+        for entry in vfs.opendir(&dir)? {
+            let entry = entry?;
+            if entry.path == "." || entry.path == ".." {
+                continue;
+            }
+            let relative_path = format!("{}{}", relative_dir, entry.path);
+            if entry.d_type == VFSEntryType::Directory {
+                pending.push(format!("{}/", relative_path));
+            }
+            entries.insert(relative_path, PolledEntry{d_type: entry.d_type, size: entry.size, mtime: entry.mtime});
+        }
+    }
+    Ok(entries)
+}
+
+/// Calls `cb` for the entries removed, created and written between two listings, in that order,
+/// leaving out the actions not in `listen_events`. Directories are not reported as written.
+fn report_changes(previous: &BTreeMap<String, PolledEntry>, current: &BTreeMap<String, PolledEntry>, listen_events: VFSFileNotificationOperationFlags, cb: &dyn VFSNotifyChangeCallback) {
+    if listen_events.contains(VFSFileNotificationOperation::Remove) {
+        for (path, entry) in previous.iter().filter(|(path, _)| !current.contains_key(*path)) {
+            cb.call(path.clone(), ACTION_REMOVE.to_string(), None, Some(entry.d_type));
+        }
+    }
+    for (path, entry) in current {
+        match previous.get(path) {
+            None if listen_events.contains(VFSFileNotificationOperation::Create) => {
+                cb.call(path.clone(), ACTION_CREATE.to_string(), None, Some(entry.d_type));
+            },
+            Some(before) if before.d_type != entry.d_type => {
+                if listen_events.contains(VFSFileNotificationOperation::Remove) {
+                    cb.call(path.clone(), ACTION_REMOVE.to_string(), None, Some(before.d_type));
+                }
+                if listen_events.contains(VFSFileNotificationOperation::Create) {
+                    cb.call(path.clone(), ACTION_CREATE.to_string(), None, Some(entry.d_type));
+                }
+            },
+            Some(before) if before != entry && entry.d_type != VFSEntryType::Directory && listen_events.contains(VFSFileNotificationOperation::Write) => {
+                cb.call(path.clone(), ACTION_WRITE.to_string(), None, None);
+            },
+            _ => {},
+        }
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2025 NetApp Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{Error, ErrorKind, Result};
+
+const SE_DACL_PRESENT: u16 = 0x0004;
+const SE_SELF_RELATIVE: u16 = 0x8000;
+const ACL_REVISION: u8 = 2;
+
+const ACE_TYPE_ACCESS_ALLOWED: u8 = 0;
+const ACE_TYPE_ACCESS_DENIED: u8 = 1;
+const ACE_TYPE_SYSTEM_AUDIT: u8 = 2;
+const ACE_TYPE_SYSTEM_ALARM: u8 = 3;
+
+const ACE_ALLOWED: &str = "allowed";
+const ACE_DENIED: &str = "denied";
+const ACE_AUDIT: &str = "audit";
+const ACE_ALARM: &str = "alarm";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VFSAce {
+    pub sid: String,
+    pub type_: String,
+    pub flags: u8,
+    pub mask: u32,
+}
+
+/// Minimal view of a self-relative security descriptor (MS-DTYP 2.4.6):
+/// owner and group SIDs plus the DACL's allow/deny/audit/alarm ACEs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VFSSecurityDescriptor {
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub aces: Vec<VFSAce>,
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("malformed security descriptor: {}", msg))
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Result<u16> {
+    buf.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or_else(|| invalid("truncated"))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Result<u32> {
+    buf.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok_or_else(|| invalid("truncated"))
+}
+
+fn parse_sid(buf: &[u8], offset: usize) -> Result<String> {
+    let header = buf.get(offset..offset + 8).ok_or_else(|| invalid("truncated SID"))?;
+    let revision = header[0];
+    let sub_auth_count = header[1] as usize;
+    let id_auth = header[2..8].iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+    let mut sid = format!("S-{}-{}", revision, id_auth);
+    for i in 0..sub_auth_count {
+        sid.push_str(format!("-{}", read_u32(buf, offset + 8 + i * 4)?).as_str());
+    }
+    Ok(sid)
+}
+
+fn sid_to_bytes(sid: &str) -> Result<Vec<u8>> {
+    let bad_sid = || Error::new(ErrorKind::InvalidInput, format!("invalid SID {:?}", sid));
+    let mut parts = sid.split('-');
+    if parts.next() != Some("S") {
+        return Err(bad_sid());
+    }
+    let revision: u8 = parts.next().and_then(|p| p.parse().ok()).ok_or_else(bad_sid)?;
+    let id_auth: u64 = parts.next().and_then(|p| p.parse().ok()).filter(|a| *a < 1 << 48).ok_or_else(bad_sid)?;
+    let sub_auths = parts.map(|p| p.parse::<u32>().map_err(|_| bad_sid())).collect::<Result<Vec<u32>>>()?;
+    if sub_auths.len() > 15 {
+        return Err(bad_sid());
+    }
+    let mut buf = vec![revision, sub_auths.len() as u8];
+    buf.extend_from_slice(&id_auth.to_be_bytes()[2..]);
+    for sub_auth in sub_auths {
+        buf.extend_from_slice(&sub_auth.to_le_bytes());
+    }
+    Ok(buf)
+}
+
+impl VFSSecurityDescriptor {
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        let mut sd = Self::default();
+        if buf.is_empty() {
+            return Ok(sd);
+        }
+        if buf.len() < 20 {
+            return Err(invalid("truncated header"));
+        }
+        let owner_offset = read_u32(buf, 4)? as usize;
+        let group_offset = read_u32(buf, 8)? as usize;
+        let dacl_offset = read_u32(buf, 16)? as usize;
+        if owner_offset != 0 {
+            sd.owner = Some(parse_sid(buf, owner_offset)?);
+        }
+        if group_offset != 0 {
+            sd.group = Some(parse_sid(buf, group_offset)?);
+        }
+        if dacl_offset != 0 {
+            let ace_count = read_u16(buf, dacl_offset + 4)?;
+            let mut offset = dacl_offset + 8;
+            for _ in 0..ace_count {
+                let ace_type = *buf.get(offset).ok_or_else(|| invalid("truncated ACE"))?;
+                let flags = *buf.get(offset + 1).ok_or_else(|| invalid("truncated ACE"))?;
+                let ace_size = read_u16(buf, offset + 2)? as usize;
+                if ace_size < 8 {
+                    return Err(invalid("ACE too small"));
+                }
+                let type_ = match ace_type {
+                    ACE_TYPE_ACCESS_ALLOWED => ACE_ALLOWED,
+                    ACE_TYPE_ACCESS_DENIED => ACE_DENIED,
+                    ACE_TYPE_SYSTEM_AUDIT => ACE_AUDIT,
+                    ACE_TYPE_SYSTEM_ALARM => ACE_ALARM,
+                    _ => {
+                        offset += ace_size;
+                        continue;
+                    },
+                };
+                let mask = read_u32(buf, offset + 4)?;
+                let sid = parse_sid(buf, offset + 8)?;
+                sd.aces.push(VFSAce{sid, type_: type_.into(), flags, mask});
+                offset += ace_size;
+            }
+        }
+        Ok(sd)
+    }
+
+    /// Serialize into a self-relative descriptor, validating SIDs and ACE types.
+    /// Only allow and deny ACEs are accepted, audit and alarm ACEs belong in a SACL.
+    /// The DACL is always written, so an empty ACE list denies everyone.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; 20];
+        buf[0] = 1;
+        if let Some(owner) = &self.owner {
+            let offset = buf.len() as u32;
+            buf.extend(sid_to_bytes(owner)?);
+            buf[4..8].copy_from_slice(&offset.to_le_bytes());
+        }
+        if let Some(group) = &self.group {
+            let offset = buf.len() as u32;
+            buf.extend(sid_to_bytes(group)?);
+            buf[8..12].copy_from_slice(&offset.to_le_bytes());
+        }
+        let acl_offset = buf.len();
+        buf.extend_from_slice(&[ACL_REVISION, 0, 0, 0, 0, 0, 0, 0]);
+        for ace in &self.aces {
+            let ace_type = match ace.type_.as_str() {
+                ACE_ALLOWED => ACE_TYPE_ACCESS_ALLOWED,
+                ACE_DENIED => ACE_TYPE_ACCESS_DENIED,
+                ACE_AUDIT | ACE_ALARM => return Err(Error::new(ErrorKind::InvalidInput, format!("ACE type {:?} not supported in a DACL", ace.type_))),
+                _ => return Err(Error::new(ErrorKind::InvalidInput, format!("invalid ACE type {:?}", ace.type_))),
+            };
+            let sid = sid_to_bytes(&ace.sid)?;
+            let ace_size = (8 + sid.len()) as u16;
+            buf.extend_from_slice(&[ace_type, ace.flags]);
+            buf.extend_from_slice(&ace_size.to_le_bytes());
+            buf.extend_from_slice(&ace.mask.to_le_bytes());
+            buf.extend(sid);
+        }
+        if self.aces.len() > u16::MAX as usize || buf.len() - acl_offset > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "too many ACEs"));
+        }
+        let acl_size = (buf.len() - acl_offset) as u16;
+        buf[acl_offset + 2..acl_offset + 4].copy_from_slice(&acl_size.to_le_bytes());
+        buf[acl_offset + 4..acl_offset + 6].copy_from_slice(&(self.aces.len() as u16).to_le_bytes());
+        buf[16..20].copy_from_slice(&(acl_offset as u32).to_le_bytes());
+        buf[2..4].copy_from_slice(&(SE_SELF_RELATIVE | SE_DACL_PRESENT).to_le_bytes());
+        Ok(buf)
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright 2025 NetApp Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind, Result};
+
+use super::{VFSEntryType, VFS};
+
+/// What recursive operations do when they come across a symlink.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum VFSSymlinkPolicy {
+    /// Treat the symlink as a leaf, never looking at its target.
+    #[default]
+    Skip,
+    /// Descend into symlinked directories, unless that would revisit a directory or leave the tree.
+    Follow,
+    /// Fail the operation.
+    Error,
+}
+
+/// Tracks the directories visited by a recursive operation rooted at `root` and applies its symlink policy.
+pub struct VFSTraversal {
+    policy: VFSSymlinkPolicy,
+    root: String,
+    visited_paths: HashSet<String>,
+    visited_inodes: HashSet<u64>,
+}
+
+impl VFSTraversal {
+    pub fn new(policy: VFSSymlinkPolicy, root: &str) -> Self {
+        Self{policy, root: format_dir(root), visited_paths: HashSet::new(), visited_inodes: HashSet::new()}
+    }
+
+    /// Returns the directory path to list for an entry at `path` of type `d_type`,
+    /// or None if the entry should not be descended into.
+    pub fn descend(&mut self, vfs: &mut dyn VFS, path: &str, d_type: &VFSEntryType) -> Result<Option<String>> {
+        match d_type {
+            VFSEntryType::Directory => self.visit(vfs, format_dir(path)),
+            VFSEntryType::Symlink => match self.policy {
+                VFSSymlinkPolicy::Skip => Ok(None),
+                VFSSymlinkPolicy::Error => Err(Error::new(ErrorKind::Other, format!("symlink {:?} encountered", path.trim_end_matches('/')))),
+                VFSSymlinkPolicy::Follow => {
+                    let link = path.trim_end_matches('/');
+                    let target = match resolve_link(link, &vfs.readlink(link)?) {
+                        Some(target) => target,
+                        None => return Ok(None), // escapes the share
+                    };
+                    if self.root.starts_with(&target) || vfs.opendir(&target).is_err() {
+                        return Ok(None); // ancestor of the traversal root, or not a directory
+                    }
+                    self.visit(vfs, target)
+                },
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn visit(&mut self, vfs: &mut dyn VFS, dir: String) -> Result<Option<String>> {
+        if self.visited_paths.contains(&dir) {
+            return Ok(None);
+        }
+        if self.policy == VFSSymlinkPolicy::Follow {
+            // XXX: servers following symlinks on their own make them look like plain directories,
+            // so cycles through those can only be told apart by inode
+            let ino = vfs.stat(&dir)?.ino;
+            if ino != 0 && !self.visited_inodes.insert(ino) {
+                return Ok(None);
+            }
+        }
+        self.visited_paths.insert(dir.clone());
+        Ok(Some(dir))
+    }
+}
+
+fn format_dir(path: &str) -> String {
+    format!("{}/", path.trim_end_matches('/'))
+}
+
+/// Resolve a symlink's target relative to the directory containing the link.
+/// Returns None if the target lies outside the share.
+fn resolve_link(link: &str, target: &str) -> Option<String> {
+    let target = target.replace('\\', "/");
+    let mut components: Vec<&str> = match target.starts_with('/') {
+        true => Vec::new(),
+        false => link.split('/').filter(|c| !c.is_empty()).collect(),
+    };
+    if !target.starts_with('/') {
+        components.pop();
+    }
+    for component in target.split('/') {
+        match component {
+            "" | "." => {},
+            ".." => { components.pop()?; },
+            _ => components.push(component),
+        }
+    }
+    Some(format_dir(&format!("/{}", components.join("/"))))
+}
@@ -0,0 +1,51 @@
+// Copyright 2025 NetApp Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::Result;
+
+use super::{VFSDirEntry, VFSTraversal, VFS};
+
+/// An entry of the tree listed by `tree`, with the stat data of the listing it was found in.
+/// `children` is None for entries that weren't descended into: those other than directories,
+/// and directories at `max_depth` or left out by the traversal's symlink policy.
+#[derive(Clone, Debug)]
+pub struct VFSTreeNode {
+    pub entry: VFSDirEntry,
+    pub children: Option<Vec<VFSTreeNode>>,
+}
+
+/// Lists the whole tree below the directory at `path`, `max_depth` (if any) levels of directories
+/// deep, with the entries of each directory sorted by name. Symlinks are handled as the policy of
+/// `traversal` says. The tree is kept in memory as a whole.
+pub fn tree(vfs: &mut dyn VFS, path: &str, max_depth: Option<u32>, traversal: &mut VFSTraversal) -> Result<Vec<VFSTreeNode>> {
+    let dir = format!("{}/", path.trim_end_matches('/'));
+    let mut entries = vfs.opendir(&dir)?.collect::<Result<Vec<VFSDirEntry>>>()?;
+    entries.retain(|entry| entry.path != "." && entry.path != "..");
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut nodes = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let subdir = match max_depth.is_none_or(|max_depth| max_depth > 1) {
+            true => traversal.descend(vfs, &format!("{}{}", dir, entry.path), &entry.d_type)?,
+            false => None,
+        };
+        let children = match subdir {
+            Some(subdir) => Some(tree(vfs, &subdir, max_depth.map(|max_depth| max_depth - 1), traversal)?),
+            None => None,
+        };
+        nodes.push(VFSTreeNode{entry, children});
+    }
+    Ok(nodes)
+}